@@ -0,0 +1,83 @@
+//! Sandbox setup shared by the tests of the versions, each includes it with
+//! `#[path = "../../tests/fixtures.rs"] mod fixtures;`
+use std::fs;
+
+use near_workspaces::types::NearToken;
+use near_workspaces::{Account, Contract};
+use serde_json::json;
+
+const FIVE_NEAR: NearToken = NearToken::from_near(5);
+const TEN_NEAR: NearToken = NearToken::from_near(10);
+
+pub struct Common {
+    pub contract: Contract,
+    /// Code of the version under test
+    pub code: Vec<u8>,
+    pub alice: Account,
+    pub bob: Account,
+}
+
+/// Deploys the code of `project` on the guest book account, `target` is the name of its wasm
+pub async fn deploy(project: &str, target: &str) -> Common {
+    let sandbox = near_workspaces::sandbox().await.unwrap();
+
+    let current = env!("CARGO_PKG_NAME").replace('-', "_");
+    fs::create_dir_all(format!("../../target/near/{current}")).unwrap();
+    let code = near_workspaces::compile_project("./").await.unwrap();
+    let project_code = if project == "./" {
+        code.clone()
+    } else {
+        fs::create_dir_all(format!("../../target/near/{target}")).unwrap();
+        near_workspaces::compile_project(project).await.unwrap()
+    };
+
+    let root = sandbox.root_account().unwrap();
+    let alice = root
+        .create_subaccount("alice")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    let bob = root
+        .create_subaccount("bob")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    let guest_book_account = root
+        .create_subaccount("gbook")
+        .initial_balance(TEN_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    let contract = guest_book_account
+        .deploy(&project_code)
+        .await
+        .unwrap()
+        .into_result()
+        .unwrap();
+
+    Common {
+        contract,
+        code,
+        alice,
+        bob,
+    }
+}
+
+/// Initializes a contract from v2 on, alice being its owner
+pub async fn init(common: &Common) {
+    let new_outcome = common
+        .contract
+        .call("new")
+        .args_json(json!({"owner": common.alice.id()}))
+        .transact()
+        .await
+        .unwrap();
+
+    assert!(new_outcome.is_success());
+}
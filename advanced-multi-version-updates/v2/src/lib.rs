@@ -1,9 +1,11 @@
+mod access_control;
 pub mod migrations;
 
 use near_sdk::{near, BorshStorageKey, PanicOnDefault};
 
+use migration_kit::{paginate, Page};
 use near_sdk::borsh::BorshSerialize;
-use near_sdk::json_types::{U128, U64};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
 use near_sdk::store::Vector;
 
 use near_sdk::{env, AccountId, NearToken};
@@ -37,7 +39,7 @@ impl GuestBook {
     #[init]
     pub fn new(owner: AccountId) -> Self {
         // New contracts will use the latest state version
-        migrations::state_version_write(&migrations::StateVersion::V2);
+        migrations::StateVersion::LATEST.write();
 
         Self {
             messages: Vector::new(StorageKey::Messages),
@@ -65,25 +67,30 @@ impl GuestBook {
         &self,
         from_index: Option<U128>,
         limit: Option<U64>,
-    ) -> Vec<&PostedMessage> {
-        let from = u128::from(from_index.unwrap_or(U128(0)));
-
-        self.messages
-            .iter()
-            .skip(from as usize)
-            .take(u64::from(limit.unwrap_or(U64::from(10))) as usize)
-            .collect()
+        cursor: Option<Base64VecU8>,
+    ) -> Page<&PostedMessage> {
+        let len = self.messages.len().into();
+        paginate(len, from_index, limit, cursor, |idx| {
+            self.messages.get(idx as u32)
+        })
     }
 
-    pub fn get_payments(&self, from_index: Option<U128>, limit: Option<U64>) -> Vec<U128> {
-        let from = u128::from(from_index.unwrap_or(U128(0)));
+    pub fn get_payments(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> Page<U128> {
+        let len = self.payments.len().into();
+        paginate(len, from_index, limit, cursor, |idx| {
+            self.payments
+                .get(idx as u32)
+                .map(|x| U128(x.as_yoctonear()))
+        })
+    }
 
-        self.payments
-            .iter()
-            .skip(from as usize)
-            .take(u64::from(limit.unwrap_or(U64::from(10))) as usize)
-            .map(|x| U128(x.as_yoctonear()))
-            .collect()
+    pub fn total_messages(&self) -> U64 {
+        U64(self.messages.len().into())
     }
 
     pub fn get_owner(&self) -> AccountId {
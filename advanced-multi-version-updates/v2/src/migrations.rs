@@ -1,43 +1,76 @@
-use std::str::FromStr;
-
 use crate::*;
-use near_sdk::{
-    borsh::{to_vec, BorshDeserialize},
-    near, PanicOnDefault, Promise,
-};
+use migration_kit::{DeployAndMigrate, StateMigrations};
+use near_sdk::{near, PanicOnDefault, Promise};
 
-#[near]
-#[derive(Debug)]
-pub(crate) enum StateVersion {
-    V1,
-    V2,
-    V3,
+// Every layout the contract state had, oldest first, and how to move between them
+#[derive(StateMigrations)]
+#[migrations(version = StateVersion, args = MigrationArgs)]
+pub enum GuestBookState {
+    V1(GuestBookV1),
+    #[migrate(with = GuestBook::unsafe_add_owner)]
+    V2(GuestBookV2),
+}
+
+impl GuestBookState {
+    // Owner recorded in the stored state, V1 states have none
+    pub(crate) fn owner(&self) -> Option<AccountId> {
+        match self {
+            GuestBookState::V1(_) => None,
+            GuestBookState::V2(state) => Some(state.owner.clone()),
+        }
+    }
+}
+
+// Parameters of the hops, passed as JSON to `unsafe_migrate`
+#[near(serializers = [json])]
+#[serde(deny_unknown_fields)]
+#[derive(Default)]
+pub struct MigrationArgs {
+    // Account set as `owner` when migrating from V1
+    pub owner: Option<AccountId>,
+}
+
+impl MigrationArgs {
+    // Rejects arguments lacking a parameter needed by the hops left to apply
+    fn validate(&self, from: StateVersion) {
+        if from == StateVersion::V1 {
+            near_sdk::require!(
+                self.owner.is_some(),
+                "`owner` is required to migrate from V1"
+            );
+        }
+    }
 }
 
 #[near]
 #[derive(PanicOnDefault)]
-struct GuestBookV1 {
+pub struct GuestBookV1 {
     messages: Vector<PostedMessage>,
     payments: Vector<NearToken>,
 }
 
 // From V1 to V2
 impl GuestBook {
-    fn unsafe_add_owner() {
-        let GuestBookV1 { messages, payments } = env::state_read().unwrap();
-        let owner = AccountId::from_str("bob.near").unwrap();
+    fn unsafe_add_owner(
+        GuestBookV1 { messages, payments }: GuestBookV1,
+        args: &MigrationArgs,
+    ) -> GuestBookV2 {
+        let owner = args
+            .owner
+            .clone()
+            .expect("`owner` is required to migrate from V1");
 
-        env::state_write(&GuestBookV2 {
+        GuestBookV2 {
             messages,
             payments,
             owner,
-        });
+        }
     }
 }
 
 #[near]
 #[derive(PanicOnDefault)]
-struct GuestBookV2 {
+pub struct GuestBookV2 {
     messages: Vector<PostedMessage>,
     payments: Vector<NearToken>,
     owner: AccountId,
@@ -46,19 +79,25 @@ struct GuestBookV2 {
 // Implement publicly available functions of the contract for self-upgrade and migration
 #[near]
 impl GuestBook {
-    pub fn unsafe_self_upgrade() {
-        near_sdk::assert_self();
+    // `migrate_args` is the JSON passed as is to the `unsafe_migrate` of the new code,
+    // which validates it against its own `MigrationArgs`
+    pub fn unsafe_self_upgrade(
+        #[serializer(borsh)] code: Vec<u8>,
+        #[serializer(borsh)] migrate_args: String,
+    ) -> Promise {
+        access_control::assert_owner_or_self();
 
-        let contract = env::input().expect("No contract code is attached in input");
-        Promise::new(env::current_account_id())
-            .deploy_contract(contract)
-            .then(Promise::new(env::current_account_id()).function_call(
-                "unsafe_migrate".to_string(),
-                Vec::new(),
-                NearToken::from_near(0),
-                env::prepaid_gas().saturating_sub(near_sdk::Gas::from_tgas(100)),
-            ))
-            .as_return();
+        let migrate_args = if migrate_args.is_empty() {
+            "{}".to_string()
+        } else {
+            migrate_args
+        };
+        DeployAndMigrate::new(code)
+            .method("unsafe_migrate")
+            .args(migrate_args.into_bytes())
+            .gas(env::prepaid_gas().saturating_sub(near_sdk::Gas::from_tgas(100)))
+            .then_migrate()
+            .build()
     }
 
     fn migration_done() {
@@ -70,35 +109,16 @@ impl GuestBook {
         env::value_return(b"\"needs-migration\"");
     }
 
-    pub fn unsafe_migrate() {
-        near_sdk::assert_self();
-        let current_version = state_version_read();
+    pub fn unsafe_migrate(migration_args: Option<MigrationArgs>) {
+        access_control::assert_owner_or_self();
+        let current_version = StateVersion::read();
         near_sdk::log!("Migrating from version: {:?}", current_version);
-        match current_version {
-            StateVersion::V1 => {
-                GuestBook::unsafe_add_owner();
-                state_version_write(&StateVersion::V2);
-            }
-            _ => {
-                return GuestBook::migration_done();
-            }
+
+        let args = migration_args.unwrap_or_default();
+        args.validate(current_version);
+        match GuestBookState::migrate_step(&args) {
+            Some(_) => GuestBook::needs_migration(),
+            None => GuestBook::migration_done(),
         }
-        GuestBook::needs_migration();
     }
 }
-
-const VERSION_KEY: &[u8] = b"VERSION";
-
-fn state_version_read() -> StateVersion {
-    env::storage_read(VERSION_KEY)
-        .map(|data| {
-            StateVersion::try_from_slice(&data).expect("Cannot deserialize the contract state.")
-        })
-        .unwrap_or(StateVersion::V1) // StateVersion is introduced in V2 State.
-}
-
-pub(crate) fn state_version_write(version: &StateVersion) {
-    let data = to_vec(&version).expect("Cannot serialize the contract state.");
-    env::storage_write(VERSION_KEY, &data);
-    near_sdk::log!("Migrated to version: {:?}", version);
-}
@@ -0,0 +1,177 @@
+use migration_kit::Page;
+use near_sdk::AccountId;
+use near_workspaces::types::{Gas, NearToken};
+use near_workspaces::Account;
+use rstest::{fixture, rstest};
+use serde_json::{json, Value};
+
+use fixtures::{deploy, init, Common};
+
+#[path = "../../tests/fixtures.rs"]
+mod fixtures;
+
+const ONE_TENTH_NEAR: NearToken = NearToken::from_millinear(100);
+const NINE_HUNDREDTH_NEAR: NearToken = NearToken::from_millinear(90);
+
+// `StateVersion::V2` as stored under the `VERSION` key
+const V2: &[u8] = &[1];
+
+async fn add_messages(common: &Common) {
+    for (sender, text, payment) in [
+        (&common.alice, "hello", NINE_HUNDREDTH_NEAR),
+        (&common.bob, "bye", ONE_TENTH_NEAR),
+    ] {
+        let add_message_outcome = sender
+            .call(common.contract.id(), "add_message")
+            .args_json(json!({"text": text}))
+            .deposit(payment)
+            .transact()
+            .await
+            .unwrap();
+        assert!(add_message_outcome.is_success());
+    }
+}
+
+#[fixture]
+async fn v1_contract() -> Common {
+    let common = deploy("../v1", "advanced_v1").await;
+    add_messages(&common).await;
+    common
+}
+
+#[fixture]
+async fn v2_contract() -> Common {
+    let common = deploy("./", "advanced_v2").await;
+    init(&common).await;
+    add_messages(&common).await;
+    common
+}
+
+async fn stored_version(common: &Common) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let state = common.contract.view_state().await?;
+    Ok(state.get(b"VERSION".as_slice()).cloned())
+}
+
+async fn unsafe_migrate(
+    caller: &Account,
+    common: &Common,
+    args: Value,
+) -> Result<near_workspaces::result::ExecutionFinalResult, near_workspaces::error::Error> {
+    caller
+        .call(common.contract.id(), "unsafe_migrate")
+        .args_json(args)
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await
+}
+
+fn expected_messages(common: &Common) -> Value {
+    json!([
+        {"premium": false, "sender": common.alice.id(), "text": "hello"},
+        {"premium": true, "sender": common.bob.id(), "text": "bye"},
+    ])
+}
+
+async fn assert_guest_book(common: &Common) -> Result<(), Box<dyn std::error::Error>> {
+    let messages: Vec<Value> = common
+        .contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+    assert_eq!(json!(messages), expected_messages(common));
+
+    let payments: Vec<Value> = common
+        .contract
+        .view("get_payments")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+    assert_eq!(
+        json!(payments),
+        json!([NINE_HUNDREDTH_NEAR, ONE_TENTH_NEAR])
+    );
+
+    let owner: AccountId = common
+        .contract
+        .view("get_owner")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(owner, common.alice.id().clone());
+
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_v1_to_v2(
+    #[future] v1_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v1_contract.await;
+
+    // V1 states were written before versioning
+    assert_eq!(stored_version(&common).await?, None);
+
+    let deploy_outcome = common.contract.as_account().deploy(&common.code).await?;
+    assert!(deploy_outcome.is_success());
+
+    // a V1 state has no owner, only the contract itself can migrate it
+    let alice_migrate_outcome = unsafe_migrate(
+        &common.alice,
+        &common,
+        json!({"migration_args": {"owner": common.alice.id()}}),
+    )
+    .await?;
+    assert!(alice_migrate_outcome.is_failure());
+
+    let missing_owner_outcome =
+        unsafe_migrate(common.contract.as_account(), &common, json!({})).await?;
+    assert!(
+        format!("{:?}", missing_owner_outcome.into_result().unwrap_err())
+            .contains("`owner` is required to migrate from V1")
+    );
+    assert_eq!(stored_version(&common).await?, None);
+
+    let migrate_outcome = unsafe_migrate(
+        common.contract.as_account(),
+        &common,
+        json!({"migration_args": {"owner": common.alice.id()}}),
+    )
+    .await?;
+    assert!(migrate_outcome.is_success());
+
+    assert_eq!(stored_version(&common).await?.as_deref(), Some(V2));
+    assert_guest_book(&common).await?;
+
+    // the owner set by the migration can call it again, it has nothing left to do
+    for _ in 0..2 {
+        let alice_migrate_outcome = unsafe_migrate(&common.alice, &common, json!({})).await?;
+        assert_eq!(alice_migrate_outcome.json::<String>()?, "done");
+    }
+    assert_eq!(stored_version(&common).await?.as_deref(), Some(V2));
+    assert_guest_book(&common).await?;
+
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_v2_new_writes_version(
+    #[future] v2_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v2_contract.await;
+
+    assert_eq!(stored_version(&common).await?.as_deref(), Some(V2));
+    assert_guest_book(&common).await?;
+
+    let alice_migrate_outcome = unsafe_migrate(&common.alice, &common, json!({})).await?;
+    assert_eq!(alice_migrate_outcome.json::<String>()?, "done");
+
+    let bob_migrate_outcome = unsafe_migrate(&common.bob, &common, json!({})).await?;
+    assert!(bob_migrate_outcome.is_failure());
+
+    Ok(())
+}
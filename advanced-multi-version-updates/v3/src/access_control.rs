@@ -0,0 +1,65 @@
+use migration_kit::{read_key, write_key};
+use near_sdk::require;
+
+use crate::migrations::GuestBookState;
+use crate::*;
+
+// The account offered the ownership, kept apart from the state so its layout does not change
+const PENDING_OWNER_KEY: &[u8] = b"PENDING_OWNER";
+
+fn pending_owner_read() -> Option<AccountId> {
+    read_key(PENDING_OWNER_KEY).unwrap_or_else(|err| err.panic())
+}
+
+// Works with any stored state version, so it can guard the migration itself
+pub(crate) fn assert_owner_or_self() {
+    let caller = env::predecessor_account_id();
+    if caller == env::current_account_id() {
+        return;
+    }
+
+    require!(
+        GuestBookState::read().owner() == Some(caller),
+        "Only the owner can call this method"
+    );
+}
+
+#[near]
+impl GuestBook {
+    fn assert_owner(&self) {
+        require!(
+            env::predecessor_account_id() == self.owner,
+            "Only the owner can call this method"
+        );
+    }
+
+    // First step of the transfer, the new owner must accept it
+    pub fn transfer_ownership(&mut self, new_owner: AccountId) {
+        self.assert_owner();
+
+        write_key(PENDING_OWNER_KEY, &new_owner);
+        near_sdk::log!("Ownership offered to {}", new_owner);
+    }
+
+    pub fn accept_ownership(&mut self) {
+        let caller = env::predecessor_account_id();
+        require!(
+            pending_owner_read() == Some(caller.clone()),
+            "Only the pending owner can accept the ownership"
+        );
+
+        env::storage_remove(PENDING_OWNER_KEY);
+        near_sdk::log!("Ownership transferred from {} to {}", self.owner, caller);
+        self.owner = caller;
+    }
+
+    pub fn cancel_ownership_transfer(&mut self) {
+        self.assert_owner();
+
+        env::storage_remove(PENDING_OWNER_KEY);
+    }
+
+    pub fn get_pending_owner(&self) -> Option<AccountId> {
+        pending_owner_read()
+    }
+}
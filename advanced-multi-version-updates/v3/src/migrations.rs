@@ -1,22 +1,58 @@
-use std::str::FromStr;
-
 use crate::*;
-use near_sdk::{
-    borsh::{to_vec, BorshDeserialize},
-    near, PanicOnDefault, Promise,
+use migration_kit::{
+    DeployAndMigrate, MigrationReport, MigrationStatus, PrefixGuard, StateMigrations,
 };
+use near_sdk::{near, Gas, PanicOnDefault, Promise};
+
+// Gas kept aside when deciding whether another hop fits in the current call
+const DEFAULT_GAS_MARGIN: Gas = Gas::from_tgas(50);
+
+// Every layout the contract state had, oldest first, and how to move between them
+#[derive(StateMigrations)]
+#[migrations(version = StateVersion, args = MigrationArgs)]
+pub enum GuestBookState {
+    V1(GuestBookV1),
+    #[migrate(with = GuestBook::unsafe_add_owner)]
+    V2(GuestBookV2),
+    #[migrate(with = GuestBook::unsafe_add_payment_to_message)]
+    V3(GuestBookV3),
+}
 
-#[near]
-#[derive(Debug)]
-pub(crate) enum StateVersion {
-    V1,
-    V2,
-    V3,
+impl GuestBookState {
+    // Owner recorded in the stored state, V1 states have none
+    pub(crate) fn owner(&self) -> Option<AccountId> {
+        match self {
+            GuestBookState::V1(_) => None,
+            GuestBookState::V2(state) => Some(state.owner.clone()),
+            GuestBookState::V3(state) => Some(state.owner.clone()),
+        }
+    }
+}
+
+// Parameters of the hops, passed as JSON to `unsafe_migrate`
+#[near(serializers = [json])]
+#[serde(deny_unknown_fields)]
+#[derive(Default)]
+pub struct MigrationArgs {
+    // Account set as `owner` when migrating from V1
+    pub owner: Option<AccountId>,
+}
+
+impl MigrationArgs {
+    // Rejects arguments lacking a parameter needed by the hops left to apply
+    fn validate(&self, from: StateVersion) {
+        if from == StateVersion::V1 {
+            near_sdk::require!(
+                self.owner.is_some(),
+                "`owner` is required to migrate from V1"
+            );
+        }
+    }
 }
 
 #[near]
 #[derive(PanicOnDefault)]
-struct GuestBookV1 {
+pub struct GuestBookV1 {
     messages: Vector<PostedMessageV1>,
     payments: Vector<NearToken>,
 }
@@ -24,7 +60,7 @@ struct GuestBookV1 {
 // This structure is implemented from V1
 #[near]
 #[derive(Clone)]
-struct PostedMessageV1 {
+pub struct PostedMessageV1 {
     pub premium: bool,
     pub sender: AccountId,
     pub text: String,
@@ -32,21 +68,26 @@ struct PostedMessageV1 {
 
 // From V1 to V2
 impl GuestBook {
-    fn unsafe_add_owner() {
-        let GuestBookV1 { messages, payments } = env::state_read().unwrap();
-        let owner = AccountId::from_str("bob.near").unwrap();
-
-        env::state_write(&GuestBookV2 {
+    fn unsafe_add_owner(
+        GuestBookV1 { messages, payments }: GuestBookV1,
+        args: &MigrationArgs,
+    ) -> GuestBookV2 {
+        let owner = args
+            .owner
+            .clone()
+            .expect("`owner` is required to migrate from V1");
+
+        GuestBookV2 {
             messages,
             payments,
             owner,
-        });
+        }
     }
 }
 
 #[near]
 #[derive(PanicOnDefault)]
-struct GuestBookV2 {
+pub struct GuestBookV2 {
     messages: Vector<PostedMessageV1>,
     payments: Vector<NearToken>,
     owner: AccountId,
@@ -54,39 +95,47 @@ struct GuestBookV2 {
 
 // From V2 to V3
 impl GuestBook {
-    fn unsafe_add_payment_to_message() {
-        let GuestBookV2 {
+    fn unsafe_add_payment_to_message(
+        GuestBookV2 {
             messages: old_messages,
             mut payments,
             owner,
-        } = env::state_read().unwrap();
-
+        }: GuestBookV2,
+        _args: &MigrationArgs,
+    ) -> GuestBookV3 {
         let default_payment = NearToken::from_yoctonear(0);
 
-        // New messages must be written to storage
-        let mut messages = Vector::new(StorageKey::Messages);
+        // New messages must be written to storage, overwriting the old ones in place
+        let mut prefixes = PrefixGuard::new()
+            .live(StorageKey::Messages)
+            .live(StorageKey::Payments);
+        let mut messages = Vector::new(
+            prefixes
+                .replace(StorageKey::Messages)
+                .unwrap_or_else(|err| err.panic()),
+        );
 
         for (idx, old_message) in old_messages.iter().enumerate() {
             let payment = payments.get(idx as u32).unwrap_or(&default_payment);
 
             messages.push(PostedMessageV3 {
-                premium: old_message.premium.clone(),
+                premium: old_message.premium,
                 sender: old_message.sender.clone(),
                 text: old_message.text.clone(),
-                payment: payment.clone(),
+                payment: *payment,
             });
         }
 
         // Payments must be removed from storage
         payments.clear();
 
-        env::state_write(&GuestBookV3 { messages, owner });
+        GuestBookV3 { messages, owner }
     }
 }
 
 #[near]
 #[derive(PanicOnDefault)]
-struct GuestBookV3 {
+pub struct GuestBookV3 {
     messages: Vector<PostedMessageV3>,
     owner: AccountId,
 }
@@ -94,7 +143,7 @@ struct GuestBookV3 {
 // New field was introduced as part of V3
 #[near]
 #[derive(Clone)]
-struct PostedMessageV3 {
+pub struct PostedMessageV3 {
     pub payment: NearToken,
     pub premium: bool,
     pub sender: AccountId,
@@ -104,63 +153,48 @@ struct PostedMessageV3 {
 // Implement publicly available functions of the contract for self-upgrade and migration
 #[near]
 impl GuestBook {
-    pub fn unsafe_self_upgrade() {
-        near_sdk::assert_self();
-
-        let contract = env::input().expect("No contract code is attached in input");
-        Promise::new(env::current_account_id())
-            .deploy_contract(contract)
-            .then(Promise::new(env::current_account_id()).function_call(
-                "unsafe_migrate".to_string(),
-                Vec::new(),
-                NearToken::from_near(0),
-                env::prepaid_gas().saturating_sub(near_sdk::Gas::from_tgas(100)),
-            ))
-            .as_return();
-    }
-
-    fn migration_done() {
-        near_sdk::log!("Migration done.");
-        env::value_return(b"\"done\"");
+    // `migrate_args` is the JSON passed as is to the `unsafe_migrate` of the new code,
+    // which validates it against its own `MigrationArgs`
+    pub fn unsafe_self_upgrade(
+        #[serializer(borsh)] code: Vec<u8>,
+        #[serializer(borsh)] migrate_args: String,
+    ) -> Promise {
+        access_control::assert_owner_or_self();
+
+        let migrate_args = if migrate_args.is_empty() {
+            "{}".to_string()
+        } else {
+            migrate_args
+        };
+        DeployAndMigrate::new(code)
+            .method("unsafe_migrate")
+            .args(migrate_args.into_bytes())
+            .gas(env::prepaid_gas().saturating_sub(near_sdk::Gas::from_tgas(100)))
+            .then_migrate()
+            .build()
     }
 
-    fn needs_migration() {
-        env::value_return(b"\"needs-migration\"");
-    }
-
-    pub fn unsafe_migrate() {
-        near_sdk::assert_self();
-        let current_version = state_version_read();
+    pub fn unsafe_migrate(
+        migration_args: Option<MigrationArgs>,
+        gas_margin: Option<Gas>,
+    ) -> MigrationReport<StateVersion> {
+        access_control::assert_owner_or_self();
+        let current_version = StateVersion::read();
         near_sdk::log!("Migrating from version: {:?}", current_version);
-        match current_version {
-            StateVersion::V1 => {
-                GuestBook::unsafe_add_owner();
-                state_version_write(&StateVersion::V2);
-            }
-            StateVersion::V2 => {
-                GuestBook::unsafe_add_payment_to_message();
-                state_version_write(&StateVersion::V3);
-            }
-            _ => {
-                return GuestBook::migration_done();
+
+        let args = migration_args.unwrap_or_default();
+        args.validate(current_version);
+        let report =
+            GuestBookState::migrate_within_gas(gas_margin.unwrap_or(DEFAULT_GAS_MARGIN), &args);
+        match report.status {
+            MigrationStatus::Done => near_sdk::log!("Migration done."),
+            MigrationStatus::NeedsMigration => {
+                near_sdk::log!(
+                    "Out of gas at version {:?}, call again.",
+                    report.end_version
+                )
             }
         }
-        GuestBook::needs_migration();
+        report
     }
 }
-
-const VERSION_KEY: &[u8] = b"VERSION";
-
-fn state_version_read() -> StateVersion {
-    env::storage_read(VERSION_KEY)
-        .map(|data| {
-            StateVersion::try_from_slice(&data).expect("Cannot deserialize the contract state.")
-        })
-        .unwrap_or(StateVersion::V1) // StateVersion is introduced in V2 State.
-}
-
-pub(crate) fn state_version_write(version: &StateVersion) {
-    let data = to_vec(&version).expect("Cannot serialize the contract state.");
-    env::storage_write(VERSION_KEY, &data);
-    near_sdk::log!("Migrated to version: {:?}", version);
-}
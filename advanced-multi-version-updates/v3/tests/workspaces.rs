@@ -0,0 +1,433 @@
+use migration_kit::Page;
+use near_sdk::json_types::U64;
+use near_sdk::AccountId;
+use near_workspaces::operations::Function;
+use near_workspaces::types::{Gas, NearToken};
+use near_workspaces::Account;
+use rstest::{fixture, rstest};
+use serde_json::{json, Value};
+
+use fixtures::{deploy, init, Common};
+
+#[path = "../../tests/fixtures.rs"]
+mod fixtures;
+
+const ONE_TENTH_NEAR: NearToken = NearToken::from_millinear(100);
+const NINE_HUNDREDTH_NEAR: NearToken = NearToken::from_millinear(90);
+
+// `StateVersion::V3` as stored under the `VERSION` key
+const V3: &[u8] = &[2];
+// Prefix of the payments of V1 and V2 states
+const PAYMENTS_PREFIX: &[u8] = &[1];
+
+async fn add_messages(common: &Common) {
+    for (sender, text, payment) in [
+        (&common.alice, "hello", NINE_HUNDREDTH_NEAR),
+        (&common.bob, "bye", ONE_TENTH_NEAR),
+    ] {
+        let add_message_outcome = sender
+            .call(common.contract.id(), "add_message")
+            .args_json(json!({"text": text}))
+            .deposit(payment)
+            .transact()
+            .await
+            .unwrap();
+        assert!(add_message_outcome.is_success());
+    }
+}
+
+#[fixture]
+async fn v3_contract() -> Common {
+    let common = deploy("./", "advanced_v3").await;
+    init(&common).await;
+    common
+}
+
+#[fixture]
+async fn v2_contract() -> Common {
+    let common = deploy("../v2", "advanced_v2").await;
+    init(&common).await;
+    add_messages(&common).await;
+    common
+}
+
+#[fixture]
+async fn v1_contract() -> Common {
+    let common = deploy("../v1", "advanced_v1").await;
+    add_messages(&common).await;
+    common
+}
+
+async fn self_upgrade(
+    caller: &Account,
+    common: &Common,
+) -> Result<near_workspaces::result::ExecutionFinalResult, near_workspaces::error::Error> {
+    caller
+        .call(common.contract.id(), "unsafe_self_upgrade")
+        .args_borsh((common.code.clone(), String::new()))
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await
+}
+
+async fn unsafe_migrate(
+    caller: &Account,
+    common: &Common,
+    args: Value,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    let migrate_outcome = caller
+        .call(common.contract.id(), "unsafe_migrate")
+        .args_json(args)
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+    assert!(migrate_outcome.is_success(), "{migrate_outcome:?}");
+
+    Ok(migrate_outcome.json()?)
+}
+
+async fn stored_version(common: &Common) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let state = common.contract.view_state().await?;
+    Ok(state.get(b"VERSION".as_slice()).cloned())
+}
+
+// Checks the state is a V3 one holding the fixture messages with their payments
+async fn assert_v3_guest_book(common: &Common) -> Result<(), Box<dyn std::error::Error>> {
+    assert_eq!(stored_version(common).await?.as_deref(), Some(V3));
+
+    let messages: Vec<Value> = common
+        .contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+    assert_eq!(
+        json!(messages),
+        json!([
+            {
+                "payment": NINE_HUNDREDTH_NEAR,
+                "premium": false,
+                "sender": common.alice.id(),
+                "text": "hello",
+            },
+            {
+                "payment": ONE_TENTH_NEAR,
+                "premium": true,
+                "sender": common.bob.id(),
+                "text": "bye",
+            },
+        ])
+    );
+    assert_eq!(get_owner(common).await?, common.alice.id().clone());
+
+    // the payments vector is gone, from the code and from the storage
+    assert!(common
+        .contract
+        .view("get_payments")
+        .args_json(json!({}))
+        .await
+        .is_err());
+    let state = common.contract.view_state().await?;
+    assert!(!state.keys().any(|key| key.starts_with(PAYMENTS_PREFIX)));
+
+    // further migrations have nothing left to do
+    for _ in 0..2 {
+        let report = unsafe_migrate(&common.alice, common, json!({})).await?;
+        assert_eq!(report["status"], "done");
+        assert_eq!(report["hops_applied"], 0);
+        assert_eq!(report["end_version"], "V3");
+    }
+    assert_eq!(stored_version(common).await?.as_deref(), Some(V3));
+
+    Ok(())
+}
+
+async fn get_owner(common: &Common) -> Result<AccountId, Box<dyn std::error::Error>> {
+    Ok(common
+        .contract
+        .view("get_owner")
+        .args_json(json!({}))
+        .await?
+        .json()?)
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_non_owner_cannot_upgrade(
+    #[future] v3_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v3_contract.await;
+
+    let bob_upgrade_outcome = self_upgrade(&common.bob, &common).await?;
+    assert!(bob_upgrade_outcome.is_failure());
+    assert!(
+        format!("{:?}", bob_upgrade_outcome.into_result().unwrap_err())
+            .contains("Only the owner can call this method")
+    );
+
+    let bob_migrate_outcome = common
+        .bob
+        .call(common.contract.id(), "unsafe_migrate")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    assert!(bob_migrate_outcome.is_failure());
+
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_owner_can_upgrade(
+    #[future] v3_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v3_contract.await;
+
+    let alice_upgrade_outcome = self_upgrade(&common.alice, &common).await?;
+    assert!(alice_upgrade_outcome.is_success());
+
+    let alice_migrate_outcome = common
+        .alice
+        .call(common.contract.id(), "unsafe_migrate")
+        .args_json(json!({}))
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+    assert!(alice_migrate_outcome.is_success());
+
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_two_step_ownership_transfer(
+    #[future] v3_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v3_contract.await;
+
+    // only the owner can offer the ownership
+    let bob_transfer_outcome = common
+        .bob
+        .call(common.contract.id(), "transfer_ownership")
+        .args_json(json!({"new_owner": common.bob.id()}))
+        .transact()
+        .await?;
+    assert!(bob_transfer_outcome.is_failure());
+
+    let alice_transfer_outcome = common
+        .alice
+        .call(common.contract.id(), "transfer_ownership")
+        .args_json(json!({"new_owner": common.bob.id()}))
+        .transact()
+        .await?;
+    assert!(alice_transfer_outcome.is_success());
+
+    let pending_owner: Option<AccountId> = common
+        .contract
+        .view("get_pending_owner")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(pending_owner, Some(common.bob.id().clone()));
+
+    // the ownership does not move until it is accepted
+    assert_eq!(get_owner(&common).await?, common.alice.id().clone());
+    assert!(self_upgrade(&common.bob, &common).await?.is_failure());
+
+    let alice_accept_outcome = common
+        .alice
+        .call(common.contract.id(), "accept_ownership")
+        .transact()
+        .await?;
+    assert!(alice_accept_outcome.is_failure());
+
+    let bob_accept_outcome = common
+        .bob
+        .call(common.contract.id(), "accept_ownership")
+        .transact()
+        .await?;
+    assert!(bob_accept_outcome.is_success());
+
+    assert_eq!(get_owner(&common).await?, common.bob.id().clone());
+    let pending_owner: Option<AccountId> = common
+        .contract
+        .view("get_pending_owner")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(pending_owner, None);
+
+    assert!(self_upgrade(&common.alice, &common).await?.is_failure());
+    assert!(self_upgrade(&common.bob, &common).await?.is_success());
+
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_cancel_ownership_transfer(
+    #[future] v3_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v3_contract.await;
+
+    let alice_transfer_outcome = common
+        .alice
+        .call(common.contract.id(), "transfer_ownership")
+        .args_json(json!({"new_owner": common.bob.id()}))
+        .transact()
+        .await?;
+    assert!(alice_transfer_outcome.is_success());
+
+    let alice_cancel_outcome = common
+        .alice
+        .call(common.contract.id(), "cancel_ownership_transfer")
+        .transact()
+        .await?;
+    assert!(alice_cancel_outcome.is_success());
+
+    let bob_accept_outcome = common
+        .bob
+        .call(common.contract.id(), "accept_ownership")
+        .transact()
+        .await?;
+    assert!(bob_accept_outcome.is_failure());
+    assert_eq!(get_owner(&common).await?, common.alice.id().clone());
+
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_v3_new_writes_version(
+    #[future] v3_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v3_contract.await;
+
+    assert_eq!(stored_version(&common).await?.as_deref(), Some(V3));
+
+    add_messages(&common).await;
+    assert_v3_guest_book(&common).await?;
+
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_v2_to_v3(
+    #[future] v2_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v2_contract.await;
+
+    // the v2 code deploys the v3 one and calls its `unsafe_migrate`
+    let alice_upgrade_outcome = self_upgrade(&common.alice, &common).await?;
+    assert!(alice_upgrade_outcome.is_success());
+
+    assert_v3_guest_book(&common).await?;
+
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_v1_to_v3(
+    #[future] v1_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v1_contract.await;
+
+    // V1 has no `unsafe_self_upgrade`, the account deploys the v3 code itself
+    let deploy_outcome = common.contract.as_account().deploy(&common.code).await?;
+    assert!(deploy_outcome.is_success());
+    assert_eq!(stored_version(&common).await?, None);
+
+    let migration_args = json!({"owner": common.alice.id()});
+
+    // a margin above the attached gas leaves no room for any hop
+    let report = unsafe_migrate(
+        common.contract.as_account(),
+        &common,
+        json!({"migration_args": migration_args, "gas_margin": Gas::from_tgas(300)}),
+    )
+    .await?;
+    assert_eq!(report["status"], "needs-migration");
+    assert_eq!(report["hops_applied"], 0);
+    assert_eq!(report["start_version"], "V1");
+    assert_eq!(stored_version(&common).await?, None);
+
+    // V1 -> V2 -> V3, over as many calls as the gas requires
+    let mut hops_applied = 0;
+    let mut report = Value::Null;
+    while report["status"] != "done" {
+        report = unsafe_migrate(
+            common.contract.as_account(),
+            &common,
+            json!({"migration_args": migration_args}),
+        )
+        .await?;
+        hops_applied += report["hops_applied"].as_u64().unwrap();
+        assert!(hops_applied <= 2);
+    }
+    assert_eq!(hops_applied, 2);
+
+    assert_v3_guest_book(&common).await?;
+
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_deep_page_gas(
+    #[future] v3_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v3_contract.await;
+
+    for batch in 0..3 {
+        let mut transaction = common.bob.batch(common.contract.id());
+        for i in 0..20 {
+            transaction = transaction.call(
+                Function::new("add_message")
+                    .args_json(json!({"text": format!("message {}", batch * 20 + i)}))
+                    .deposit(ONE_TENTH_NEAR)
+                    .gas(Gas::from_tgas(10)),
+            );
+        }
+        assert!(transaction.transact().await?.is_success());
+    }
+
+    let total: U64 = common
+        .contract
+        .view("total_messages")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(total, U64(60));
+
+    // views do not report their gas, so the pages are read in transactions
+    let mut gas = vec![];
+    let mut cursor = None;
+    for args in [json!({}), json!({"from_index": "50"})] {
+        let outcome = common
+            .alice
+            .call(common.contract.id(), "get_messages")
+            .args_json(args)
+            .transact()
+            .await?;
+        gas.push(outcome.total_gas_burnt.as_gas());
+
+        let page: Page<Value> = outcome.json()?;
+        assert_eq!(page.items.len(), 10);
+        cursor = cursor.or(page.next_cursor);
+    }
+    // the messages before the deep page are never read
+    assert!(gas[1] < gas[0] * 11 / 10);
+
+    // the cursor of the first page resumes right after it
+    let page: Page<Value> = common
+        .contract
+        .view("get_messages")
+        .args_json(json!({"cursor": cursor}))
+        .await?
+        .json()?;
+    assert_eq!(page.items[0]["text"], "message 10");
+    Ok(())
+}
@@ -0,0 +1,129 @@
+mod access_control;
+pub mod migrations;
+mod moderation;
+mod sender_index;
+mod storage;
+
+use near_sdk::{near, BorshStorageKey, PanicOnDefault};
+
+use migration_kit::{paginate, Page};
+use near_sdk::borsh::BorshSerialize;
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::store::{LookupMap, Vector};
+
+use near_sdk::{env, AccountId, CryptoHash, NearToken};
+
+pub use moderation::{MessageDetails, MessageRevision, MessageView, Tombstone};
+pub use storage::{StorageBalance, StorageBalanceBounds};
+
+const POINT_ONE: NearToken = NearToken::from_millinear(100);
+
+#[derive(BorshSerialize, BorshStorageKey)]
+#[borsh(crate = "near_sdk::borsh")]
+pub enum StorageKey {
+    Messages,
+    // payments of V2 states, removed by the migration to V3
+    Payments,
+    SenderIndex,
+    SenderMessages { account_hash: CryptoHash },
+    // edit histories and tombstones, outside of the versioned state
+    Revisions,
+    Tombstones,
+    // NEP-145 storage balances, outside of the versioned state
+    StorageBalances,
+}
+
+#[near(serializers=[json, borsh])]
+pub struct PostedMessage {
+    pub payment: NearToken,
+    pub premium: bool,
+    pub sender: AccountId,
+    pub text: String,
+}
+
+#[near(contract_state)]
+#[derive(PanicOnDefault)]
+pub struct GuestBook {
+    messages: Vector<PostedMessage>,
+    owner: AccountId,
+    // indexes of the messages of each sender, oldest first
+    sender_index: LookupMap<AccountId, Vector<u32>>,
+    // messages recorded in `sender_index`, behind `messages` while the migration to V4 fills it
+    indexed_messages: u32,
+}
+
+#[near]
+impl GuestBook {
+    #[init]
+    pub fn new(owner: AccountId) -> Self {
+        // New contracts will use the latest state version
+        migrations::StateVersion::LATEST.write();
+
+        Self {
+            messages: Vector::new(StorageKey::Messages),
+            owner,
+            sender_index: LookupMap::new(StorageKey::SenderIndex),
+            indexed_messages: 0,
+        }
+    }
+
+    /// Posts a message, whose storage is paid from the storage balance of the sender when it
+    /// covers it, from the attached deposit otherwise. What is left of the deposit is the
+    /// payment of the message when it reaches 0.1 NEAR, making it premium, and is refunded
+    /// otherwise.
+    #[payable]
+    pub fn add_message(&mut self, text: String) -> U64 {
+        let initial_usage = env::storage_usage();
+        let sender = env::predecessor_account_id();
+        let deposit = env::attached_deposit();
+
+        // the payment and premium do not change the size of the message, they are set once the
+        // storage is paid
+        let message = PostedMessage {
+            payment: NearToken::from_yoctonear(0),
+            premium: false,
+            sender: sender.clone(),
+            text,
+        };
+        self.messages.push(message);
+        let idx = self.messages.len() - 1;
+
+        // while the index is being filled, the migration reaches this message in turn
+        if self.indexed_messages == idx {
+            self.index_next_message();
+        }
+        self.messages.flush();
+
+        let payment = storage::settle_storage(&sender, initial_usage, deposit);
+        if payment >= POINT_ONE {
+            let message = self.messages.get_mut(idx).unwrap();
+            message.payment = payment;
+            message.premium = true;
+        } else {
+            storage::refund(sender, payment);
+        }
+
+        U64(idx.into())
+    }
+
+    pub fn get_messages(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> Page<MessageView<'_>> {
+        // deleted messages are left out, but still count toward the limit
+        let len = self.messages.len().into();
+        paginate(len, from_index, limit, cursor, |idx| {
+            self.message_view(idx as u32)
+        })
+    }
+
+    pub fn total_messages(&self) -> U64 {
+        U64(self.messages.len().into())
+    }
+
+    pub fn get_owner(&self) -> AccountId {
+        self.owner.clone()
+    }
+}
@@ -0,0 +1,245 @@
+use crate::*;
+use migration_kit::{
+    DeployAndMigrate, MigrationReport, MigrationStatus, PrefixGuard, StateMigrations,
+};
+use near_sdk::{near, Gas, PanicOnDefault, Promise};
+
+// Gas kept aside when deciding whether another hop fits in the current call
+const DEFAULT_GAS_MARGIN: Gas = Gas::from_tgas(50);
+
+// Every layout the contract state had, oldest first, and how to move between them
+#[derive(StateMigrations)]
+#[migrations(version = StateVersion, args = MigrationArgs)]
+pub enum GuestBookState {
+    V1(GuestBookV1),
+    #[migrate(with = GuestBook::unsafe_add_owner)]
+    V2(GuestBookV2),
+    #[migrate(with = GuestBook::unsafe_add_payment_to_message)]
+    V3(GuestBookV3),
+    #[migrate(with = GuestBook::unsafe_add_sender_index)]
+    V4(GuestBookV4),
+}
+
+impl GuestBookState {
+    // Owner recorded in the stored state, V1 states have none
+    pub(crate) fn owner(&self) -> Option<AccountId> {
+        match self {
+            GuestBookState::V1(_) => None,
+            GuestBookState::V2(state) => Some(state.owner.clone()),
+            GuestBookState::V3(state) => Some(state.owner.clone()),
+            GuestBookState::V4(state) => Some(state.owner.clone()),
+        }
+    }
+}
+
+// Parameters of the hops, passed as JSON to `unsafe_migrate`
+#[near(serializers = [json])]
+#[serde(deny_unknown_fields)]
+#[derive(Default)]
+pub struct MigrationArgs {
+    // Account set as `owner` when migrating from V1
+    pub owner: Option<AccountId>,
+}
+
+impl MigrationArgs {
+    // Rejects arguments lacking a parameter needed by the hops left to apply
+    fn validate(&self, from: StateVersion) {
+        if from == StateVersion::V1 {
+            near_sdk::require!(
+                self.owner.is_some(),
+                "`owner` is required to migrate from V1"
+            );
+        }
+    }
+}
+
+#[near]
+#[derive(PanicOnDefault)]
+pub struct GuestBookV1 {
+    messages: Vector<PostedMessageV1>,
+    payments: Vector<NearToken>,
+}
+
+// This structure is implemented from V1
+#[near]
+#[derive(Clone)]
+pub struct PostedMessageV1 {
+    pub premium: bool,
+    pub sender: AccountId,
+    pub text: String,
+}
+
+// From V1 to V2
+impl GuestBook {
+    fn unsafe_add_owner(
+        GuestBookV1 { messages, payments }: GuestBookV1,
+        args: &MigrationArgs,
+    ) -> GuestBookV2 {
+        let owner = args
+            .owner
+            .clone()
+            .expect("`owner` is required to migrate from V1");
+
+        GuestBookV2 {
+            messages,
+            payments,
+            owner,
+        }
+    }
+}
+
+#[near]
+#[derive(PanicOnDefault)]
+pub struct GuestBookV2 {
+    messages: Vector<PostedMessageV1>,
+    payments: Vector<NearToken>,
+    owner: AccountId,
+}
+
+// From V2 to V3
+impl GuestBook {
+    fn unsafe_add_payment_to_message(
+        GuestBookV2 {
+            messages: old_messages,
+            mut payments,
+            owner,
+        }: GuestBookV2,
+        _args: &MigrationArgs,
+    ) -> GuestBookV3 {
+        let default_payment = NearToken::from_yoctonear(0);
+
+        // New messages must be written to storage, overwriting the old ones in place
+        let mut prefixes = PrefixGuard::new()
+            .live(StorageKey::Messages)
+            .live(StorageKey::Payments);
+        let mut messages = Vector::new(
+            prefixes
+                .replace(StorageKey::Messages)
+                .unwrap_or_else(|err| err.panic()),
+        );
+
+        for (idx, old_message) in old_messages.iter().enumerate() {
+            let payment = payments.get(idx as u32).unwrap_or(&default_payment);
+
+            messages.push(PostedMessageV3 {
+                premium: old_message.premium,
+                sender: old_message.sender.clone(),
+                text: old_message.text.clone(),
+                payment: *payment,
+            });
+        }
+
+        // Payments must be removed from storage
+        payments.clear();
+
+        GuestBookV3 { messages, owner }
+    }
+}
+
+#[near]
+#[derive(PanicOnDefault)]
+pub struct GuestBookV3 {
+    messages: Vector<PostedMessageV3>,
+    owner: AccountId,
+}
+
+// New field was introduced as part of V3
+#[near]
+#[derive(Clone)]
+pub struct PostedMessageV3 {
+    pub payment: NearToken,
+    pub premium: bool,
+    pub sender: AccountId,
+    pub text: String,
+}
+
+// From V3 to V4
+impl GuestBook {
+    fn unsafe_add_sender_index(
+        GuestBookV3 { messages, owner }: GuestBookV3,
+        _args: &MigrationArgs,
+    ) -> GuestBookV4 {
+        // The index starts empty, filling it in this hop could run out of gas on a large
+        // guest book, so `unsafe_migrate` fills it over as many calls as it takes
+        let mut prefixes = PrefixGuard::new().live(StorageKey::Messages);
+        let sender_index = LookupMap::new(
+            prefixes
+                .create(StorageKey::SenderIndex)
+                .unwrap_or_else(|err| err.panic()),
+        );
+
+        GuestBookV4 {
+            messages,
+            owner,
+            sender_index,
+            indexed_messages: 0,
+        }
+    }
+}
+
+#[near]
+#[derive(PanicOnDefault)]
+pub struct GuestBookV4 {
+    messages: Vector<PostedMessageV3>,
+    owner: AccountId,
+    sender_index: LookupMap<AccountId, Vector<u32>>,
+    indexed_messages: u32,
+}
+
+// Implement publicly available functions of the contract for self-upgrade and migration
+#[near]
+impl GuestBook {
+    // `migrate_args` is the JSON passed as is to the `unsafe_migrate` of the new code,
+    // which validates it against its own `MigrationArgs`
+    pub fn unsafe_self_upgrade(
+        #[serializer(borsh)] code: Vec<u8>,
+        #[serializer(borsh)] migrate_args: String,
+    ) -> Promise {
+        access_control::assert_owner_or_self();
+
+        let migrate_args = if migrate_args.is_empty() {
+            "{}".to_string()
+        } else {
+            migrate_args
+        };
+        DeployAndMigrate::new(code)
+            .method("unsafe_migrate")
+            .args(migrate_args.into_bytes())
+            .gas(env::prepaid_gas().saturating_sub(near_sdk::Gas::from_tgas(100)))
+            .then_migrate()
+            .build()
+    }
+
+    pub fn unsafe_migrate(
+        migration_args: Option<MigrationArgs>,
+        gas_margin: Option<Gas>,
+    ) -> MigrationReport<StateVersion> {
+        access_control::assert_owner_or_self();
+        let current_version = StateVersion::read();
+        near_sdk::log!("Migrating from version: {:?}", current_version);
+
+        let args = migration_args.unwrap_or_default();
+        args.validate(current_version);
+        let gas_margin = gas_margin.unwrap_or(DEFAULT_GAS_MARGIN);
+        let mut report = GuestBookState::migrate_within_gas(gas_margin, &args);
+
+        // the migration is only done once the messages posted before V4 are all indexed
+        if report.end_version == StateVersion::V4 {
+            let mut contract: GuestBook = migration_kit::expect_state();
+            if !contract.fill_sender_index(gas_margin) {
+                report.status = MigrationStatus::NeedsMigration;
+            }
+            env::state_write(&contract);
+        }
+        match report.status {
+            MigrationStatus::Done => near_sdk::log!("Migration done."),
+            MigrationStatus::NeedsMigration => {
+                near_sdk::log!(
+                    "Out of gas at version {:?}, call again.",
+                    report.end_version
+                )
+            }
+        }
+        report
+    }
+}
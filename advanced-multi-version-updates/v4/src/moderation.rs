@@ -0,0 +1,166 @@
+use near_sdk::serde::Serialize;
+use near_sdk::store::LookupMap;
+use near_sdk::{require, NearSchema};
+
+use crate::*;
+
+/// A previous text of a message
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct MessageRevision {
+    pub text: String,
+    pub edited_by: AccountId,
+    pub edited_at: U64,
+}
+
+/// Left in place of a deleted message, so the ids of the following ones do not change
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct Tombstone {
+    pub deleted_by: AccountId,
+    pub deleted_at: U64,
+}
+
+/// A message as listed by the views, with the id to edit or delete it
+#[derive(NearSchema, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[abi(json)]
+pub struct MessageView<'a> {
+    pub id: U64,
+    #[serde(flatten)]
+    pub message: &'a PostedMessage,
+    pub edited: bool,
+}
+
+/// A message with its previous texts, oldest first, the text and history of a deleted one
+/// are cleared
+#[derive(NearSchema, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[abi(json)]
+pub struct MessageDetails<'a> {
+    pub id: U64,
+    #[serde(flatten)]
+    pub message: &'a PostedMessage,
+    pub history: Vec<MessageRevision>,
+    pub deleted: Option<Tombstone>,
+}
+
+// Edit histories and tombstones are kept apart from the state, so later versions carry them
+// without a migration
+fn revisions() -> LookupMap<u32, Vec<MessageRevision>> {
+    LookupMap::new(StorageKey::Revisions)
+}
+
+fn tombstones() -> LookupMap<u32, Tombstone> {
+    LookupMap::new(StorageKey::Tombstones)
+}
+
+#[near]
+impl GuestBook {
+    /// Replaces the text of a message, keeping the previous one in its history.
+    ///
+    /// The editor pays for the storage the edit takes, the surplus of the deposit is refunded.
+    /// The storage a shorter text frees goes to the storage balance of the sender, like for
+    /// a deletion.
+    #[payable]
+    pub fn edit_message(&mut self, id: U64, text: String) {
+        let initial_usage = env::storage_usage();
+        let id = self.assert_can_moderate(id);
+        let editor = env::predecessor_account_id();
+
+        let message = self.messages.get_mut(id).unwrap();
+        let previous = std::mem::replace(&mut message.text, text);
+        let sender = message.sender.clone();
+
+        revisions().entry(id).or_default().push(MessageRevision {
+            text: previous,
+            edited_by: editor.clone(),
+            edited_at: U64(env::block_timestamp()),
+        });
+        self.messages.flush();
+        near_sdk::log!("Message {} edited by {}", id, editor);
+
+        storage::settle_owned_storage_and_refund(
+            &sender,
+            editor,
+            initial_usage,
+            env::attached_deposit(),
+        );
+    }
+
+    /// Clears the text and history of a message, which is then left out of the views.
+    ///
+    /// The storage freed goes to the storage balance of the sender, who paid for it, if
+    /// registered. The deleter pays when the tombstone takes more than the text frees.
+    #[payable]
+    pub fn delete_message(&mut self, id: U64) {
+        let initial_usage = env::storage_usage();
+        let id = self.assert_can_moderate(id);
+        let deleter = env::predecessor_account_id();
+
+        let message = self.messages.get_mut(id).unwrap();
+        message.text.clear();
+        let sender = message.sender.clone();
+        revisions().remove(&id);
+        tombstones().insert(
+            id,
+            Tombstone {
+                deleted_by: deleter.clone(),
+                deleted_at: U64(env::block_timestamp()),
+            },
+        );
+        self.messages.flush();
+        near_sdk::log!("Message {} deleted by {}", id, deleter);
+
+        storage::settle_owned_storage_and_refund(
+            &sender,
+            deleter,
+            initial_usage,
+            env::attached_deposit(),
+        );
+    }
+
+    pub fn get_message(&self, id: U64) -> Option<MessageDetails<'_>> {
+        let idx = u32::try_from(id.0).ok()?;
+        let message = self.messages.get(idx)?;
+
+        Some(MessageDetails {
+            id,
+            message,
+            history: revisions().get(&idx).cloned().unwrap_or_default(),
+            deleted: tombstones().get(&idx).cloned(),
+        })
+    }
+}
+
+impl GuestBook {
+    // The message at `idx`, unless it was deleted
+    pub(crate) fn message_view(&self, idx: u32) -> Option<MessageView<'_>> {
+        if tombstones().contains_key(&idx) {
+            return None;
+        }
+
+        Some(MessageView {
+            id: U64(idx.into()),
+            message: self.messages.get(idx)?,
+            edited: revisions().contains_key(&idx),
+        })
+    }
+
+    // Only the sender of a message and the owner can change it, and not once deleted
+    fn assert_can_moderate(&self, id: U64) -> u32 {
+        let idx = u32::try_from(id.0).unwrap_or(u32::MAX);
+        let message = self
+            .messages
+            .get(idx)
+            .unwrap_or_else(|| env::panic_str("No message with this id"));
+
+        let caller = env::predecessor_account_id();
+        require!(
+            caller == message.sender || caller == self.owner,
+            "Only the sender or the owner can change this message"
+        );
+        require!(!tombstones().contains_key(&idx), "The message was deleted");
+        idx
+    }
+}
@@ -0,0 +1,82 @@
+use near_sdk::{require, Gas};
+
+use crate::*;
+
+// Each sender has its own vector of message indexes, under a prefix derived from its account
+fn sender_messages_new(sender: &AccountId) -> Vector<u32> {
+    Vector::new(StorageKey::SenderMessages {
+        account_hash: env::sha256_array(sender.as_bytes()),
+    })
+}
+
+#[near]
+impl GuestBook {
+    /// Messages posted by `sender`, oldest first, leaving out the deleted ones
+    pub fn get_messages_by_sender(
+        &self,
+        sender: AccountId,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> Page<MessageView<'_>> {
+        require!(
+            self.sender_index_complete(),
+            "The sender index is still being built, call unsafe_migrate"
+        );
+
+        let Some(sent) = self.sender_index.get(&sender) else {
+            return paginate(0, from_index, limit, cursor, |_| None);
+        };
+        paginate(sent.len().into(), from_index, limit, cursor, |pos| {
+            sent.get(pos as u32).and_then(|idx| self.message_view(*idx))
+        })
+    }
+}
+
+impl GuestBook {
+    pub(crate) fn sender_index_complete(&self) -> bool {
+        self.indexed_messages == self.messages.len()
+    }
+
+    // Records the oldest message missing from the index
+    pub(crate) fn index_next_message(&mut self) {
+        let idx = self.indexed_messages;
+        let sender = self
+            .messages
+            .get(idx)
+            .expect("failed to get message")
+            .sender
+            .clone();
+
+        let sent = self
+            .sender_index
+            .entry(sender)
+            .or_insert_with_key(sender_messages_new);
+        sent.push(idx);
+        // written right away rather than when the contract is dropped, so the gas used
+        // accounts for every message indexed so far
+        sent.flush();
+        self.sender_index.flush();
+
+        self.indexed_messages += 1;
+    }
+
+    /// Indexes the messages posted before V4 while the remaining prepaid gas stays above
+    /// `gas_margin`, returning whether every message is indexed
+    pub(crate) fn fill_sender_index(&mut self, gas_margin: Gas) -> bool {
+        while !self.sender_index_complete() {
+            let remaining = env::prepaid_gas().saturating_sub(env::used_gas());
+            if remaining <= gas_margin {
+                break;
+            }
+            self.index_next_message();
+        }
+
+        near_sdk::log!(
+            "Indexed {} of {} messages",
+            self.indexed_messages,
+            self.messages.len()
+        );
+        self.sender_index_complete()
+    }
+}
@@ -0,0 +1,199 @@
+use near_sdk::store::LookupMap;
+use near_sdk::{assert_one_yocto, require, Promise, StorageUsage};
+
+use crate::*;
+
+// A balance entry, its storage record overhead, key and value, for the longest account id
+const REGISTRATION_BYTES: StorageUsage = 40 + 1 + 4 + 64 + 16 + 16;
+
+/// Storage balance of an account, as defined by NEP-145
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct StorageBalance {
+    pub total: NearToken,
+    /// Part of `total` not locked by the registration or by the storage already used
+    pub available: NearToken,
+}
+
+#[near(serializers = [json])]
+pub struct StorageBalanceBounds {
+    pub min: NearToken,
+    pub max: Option<NearToken>,
+}
+
+// Storage balances are kept apart from the state, like the edit histories
+fn balances() -> LookupMap<AccountId, StorageBalance> {
+    LookupMap::new(StorageKey::StorageBalances)
+}
+
+fn storage_cost(bytes: StorageUsage) -> NearToken {
+    env::storage_byte_cost().saturating_mul(bytes.into())
+}
+
+fn registration_cost() -> NearToken {
+    storage_cost(REGISTRATION_BYTES)
+}
+
+pub(crate) fn refund(account: AccountId, amount: NearToken) {
+    if !amount.is_zero() {
+        Promise::new(account).transfer(amount).detach();
+    }
+}
+
+/// Charges `account` for the storage used since `initial_usage`, from its storage balance when
+/// it covers the cost, from `deposit` otherwise, and returns what is left of the deposit.
+///
+/// Storage freed since `initial_usage` is credited back to the storage balance of `account`.
+pub(crate) fn settle_storage(
+    account: &AccountId,
+    initial_usage: StorageUsage,
+    deposit: NearToken,
+) -> NearToken {
+    // measured before the balance is updated, which rewrites its entry at the same size
+    let usage = env::storage_usage();
+    let mut balances = balances();
+    let balance = balances.get_mut(account);
+
+    if usage <= initial_usage {
+        // at most what the balance locks, storage paid before registering is not its own
+        if let Some(balance) = balance {
+            let locked = balance.total.saturating_sub(registration_cost());
+            let freed = storage_cost(initial_usage - usage);
+            balance.available = balance.available.saturating_add(freed).min(locked);
+        }
+        return deposit;
+    }
+
+    let cost = storage_cost(usage - initial_usage);
+    match balance {
+        Some(balance) if balance.available >= cost => {
+            balance.available = balance.available.saturating_sub(cost);
+            deposit
+        }
+        balance => {
+            require!(
+                deposit >= cost,
+                format!(
+                    "{} bytes of storage cost {}, attach them or add them with storage_deposit",
+                    usage - initial_usage,
+                    cost.exact_amount_display()
+                )
+            );
+            // the deposit now pays for storage the balance gets back when it is freed
+            if let Some(balance) = balance {
+                balance.total = balance.total.saturating_add(cost);
+            }
+            deposit.saturating_sub(cost)
+        }
+    }
+}
+
+/// Same as [`settle_storage`], refunding what is left of the deposit
+pub(crate) fn settle_storage_and_refund(
+    account: AccountId,
+    initial_usage: StorageUsage,
+    deposit: NearToken,
+) {
+    let surplus = settle_storage(&account, initial_usage, deposit);
+    refund(account, surplus);
+}
+
+/// Same as [`settle_storage_and_refund`] for a change `payer` makes to storage `owner` paid
+/// for: the bytes it frees go back to the storage balance of `owner`, and the whole deposit
+/// to `payer`.
+pub(crate) fn settle_owned_storage_and_refund(
+    owner: &AccountId,
+    payer: AccountId,
+    initial_usage: StorageUsage,
+    deposit: NearToken,
+) {
+    if env::storage_usage() < initial_usage {
+        settle_storage(owner, initial_usage, NearToken::from_yoctonear(0));
+        refund(payer, deposit);
+    } else {
+        settle_storage_and_refund(payer, initial_usage, deposit);
+    }
+}
+
+// NEP-145 storage management
+#[near]
+impl GuestBook {
+    /// Adds the attached deposit to the storage balance of `account_id`, the caller by
+    /// default, registering it first. With `registration_only`, only the registration is
+    /// paid and the rest of the deposit is refunded.
+    #[payable]
+    pub fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let deposit = env::attached_deposit();
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let registration_only = registration_only.unwrap_or(false);
+        let mut balances = balances();
+
+        let (balance, surplus) = match balances.get(&account_id) {
+            Some(balance) if registration_only => (balance.clone(), deposit),
+            Some(balance) => (
+                StorageBalance {
+                    total: balance.total.saturating_add(deposit),
+                    available: balance.available.saturating_add(deposit),
+                },
+                NearToken::from_yoctonear(0),
+            ),
+            None => {
+                let min = registration_cost();
+                require!(
+                    deposit >= min,
+                    format!("The registration costs {}", min.exact_amount_display())
+                );
+                let total = if registration_only { min } else { deposit };
+                (
+                    StorageBalance {
+                        total,
+                        available: total.saturating_sub(min),
+                    },
+                    deposit.saturating_sub(total),
+                )
+            }
+        };
+
+        balances.insert(account_id, balance.clone());
+        refund(env::predecessor_account_id(), surplus);
+        balance
+    }
+
+    /// Withdraws `amount` of the available storage balance of the caller, all of it by default
+    #[payable]
+    pub fn storage_withdraw(&mut self, amount: Option<NearToken>) -> StorageBalance {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut balances = balances();
+        let balance = balances
+            .get_mut(&account_id)
+            .unwrap_or_else(|| env::panic_str("The account is not registered"));
+
+        let amount = amount.unwrap_or(balance.available);
+        require!(
+            amount <= balance.available,
+            "The amount is larger than the available storage balance"
+        );
+        balance.total = balance.total.saturating_sub(amount);
+        balance.available = balance.available.saturating_sub(amount);
+
+        let balance = balance.clone();
+        refund(account_id, amount);
+        balance
+    }
+
+    pub fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        balances().get(&account_id).cloned()
+    }
+
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: registration_cost(),
+            max: None,
+        }
+    }
+}
@@ -0,0 +1,523 @@
+use migration_kit::Page;
+use near_sdk::json_types::U64;
+use near_workspaces::operations::Function;
+use near_workspaces::types::{Gas, NearToken};
+use near_workspaces::Account;
+use rstest::{fixture, rstest};
+use serde_json::{json, Value};
+
+use fixtures::{deploy, init, Common};
+
+#[path = "../../tests/fixtures.rs"]
+mod fixtures;
+
+const ONE_TENTH_NEAR: NearToken = NearToken::from_millinear(100);
+const NINE_HUNDREDTH_NEAR: NearToken = NearToken::from_millinear(90);
+
+// `StateVersion::V4` as stored under the `VERSION` key
+const V4: &[u8] = &[3];
+
+// Alice and bob take turns posting `count` messages, alice posting the even ones
+async fn add_messages(common: &Common, count: usize) {
+    for batch in (0..count).collect::<Vec<_>>().chunks(20) {
+        for (sender, parity) in [(&common.alice, 0), (&common.bob, 1)] {
+            let mut transaction = sender.batch(common.contract.id());
+            for i in batch.iter().filter(|i| *i % 2 == parity) {
+                transaction = transaction.call(
+                    Function::new("add_message")
+                        .args_json(json!({"text": format!("message {i}")}))
+                        .deposit(NINE_HUNDREDTH_NEAR)
+                        .gas(Gas::from_tgas(10)),
+                );
+            }
+            assert!(transaction.transact().await.unwrap().is_success());
+        }
+    }
+}
+
+#[fixture]
+async fn v4_contract() -> Common {
+    let common = deploy("./", "advanced_v4").await;
+    init(&common).await;
+    common
+}
+
+#[fixture]
+async fn v3_contract() -> Common {
+    let common = deploy("../v3", "advanced_v3").await;
+    init(&common).await;
+    common
+}
+
+async fn unsafe_migrate(common: &Common, args: Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let migrate_outcome = common
+        .alice
+        .call(common.contract.id(), "unsafe_migrate")
+        .args_json(args)
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+    assert!(migrate_outcome.is_success(), "{migrate_outcome:?}");
+
+    Ok(migrate_outcome.json()?)
+}
+
+// Texts of the messages of `sender`, read a page at a time
+async fn sender_texts(
+    common: &Common,
+    sender: &Account,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut texts = vec![];
+    let mut cursor = None;
+    loop {
+        let page: Page<Value> = common
+            .contract
+            .view("get_messages_by_sender")
+            .args_json(json!({"sender": sender.id(), "limit": "7", "cursor": cursor}))
+            .await?
+            .json()?;
+        texts.extend(
+            page.items
+                .iter()
+                .map(|message| message["text"].as_str().unwrap().to_string()),
+        );
+        cursor = page.next_cursor;
+        if cursor.is_none() {
+            return Ok(texts);
+        }
+    }
+}
+
+fn expected_texts(indexes: impl Iterator<Item = usize>) -> Vec<String> {
+    indexes.map(|i| format!("message {i}")).collect()
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_v4_indexes_new_messages(
+    #[future] v4_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v4_contract.await;
+
+    let state = common.contract.view_state().await?;
+    assert_eq!(
+        state.get(b"VERSION".as_slice()).map(Vec::as_slice),
+        Some(V4)
+    );
+
+    add_messages(&common, 20).await;
+    assert_eq!(
+        sender_texts(&common, &common.alice).await?,
+        expected_texts((0..20).step_by(2))
+    );
+    assert_eq!(
+        sender_texts(&common, &common.bob).await?,
+        expected_texts((1..20).step_by(2))
+    );
+
+    // an account that never posted has no messages
+    let page: Page<Value> = common
+        .contract
+        .view("get_messages_by_sender")
+        .args_json(json!({"sender": "carol.test.near"}))
+        .await?
+        .json()?;
+    assert!(page.items.is_empty());
+    assert_eq!(page.next_cursor, None);
+
+    let report = unsafe_migrate(&common, json!({})).await?;
+    assert_eq!(report["status"], "done");
+    assert_eq!(report["hops_applied"], 0);
+
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_v3_to_v4_fills_index(
+    #[future] v3_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v3_contract.await;
+    add_messages(&common, 100).await;
+
+    let deploy_outcome = common.contract.as_account().deploy(&common.code).await?;
+    assert!(deploy_outcome.is_success());
+
+    // a margin close to the attached gas leaves room for the hop but not for the whole index
+    let report = unsafe_migrate(&common, json!({"gas_margin": Gas::from_tgas(290)})).await?;
+    assert_eq!(report["start_version"], "V3");
+    assert_eq!(report["end_version"], "V4");
+    assert_eq!(report["hops_applied"], 1);
+    assert_eq!(report["status"], "needs-migration");
+
+    let by_sender = common
+        .contract
+        .view("get_messages_by_sender")
+        .args_json(json!({"sender": common.alice.id()}))
+        .await;
+    assert!(by_sender.is_err());
+
+    // messages posted meanwhile are indexed after the older ones
+    let add_message_outcome = common
+        .alice
+        .call(common.contract.id(), "add_message")
+        .args_json(json!({"text": "message 100"}))
+        .deposit(ONE_TENTH_NEAR)
+        .transact()
+        .await?;
+    assert!(add_message_outcome.is_success());
+
+    let mut calls = 0;
+    let mut report = Value::Null;
+    while report["status"] != "done" {
+        report = unsafe_migrate(&common, json!({})).await?;
+        assert_eq!(report["hops_applied"], 0);
+        calls += 1;
+        assert!(calls <= 5);
+    }
+
+    assert_eq!(
+        sender_texts(&common, &common.alice).await?,
+        expected_texts((0..=100).step_by(2))
+    );
+    assert_eq!(
+        sender_texts(&common, &common.bob).await?,
+        expected_texts((1..100).step_by(2))
+    );
+
+    // new messages are indexed right away once the index is complete
+    add_messages(&common, 2).await;
+    let bob_texts = sender_texts(&common, &common.bob).await?;
+    assert_eq!(bob_texts.last().map(String::as_str), Some("message 1"));
+    assert_eq!(bob_texts.len(), 51);
+
+    Ok(())
+}
+
+async fn post(
+    common: &Common,
+    sender: &Account,
+    text: &str,
+) -> Result<U64, Box<dyn std::error::Error>> {
+    let add_message_outcome = sender
+        .call(common.contract.id(), "add_message")
+        .args_json(json!({"text": text}))
+        .deposit(ONE_TENTH_NEAR)
+        .transact()
+        .await?;
+    assert!(add_message_outcome.is_success());
+    Ok(add_message_outcome.json()?)
+}
+
+async fn change_message(
+    common: &Common,
+    caller: &Account,
+    method: &str,
+    args: Value,
+) -> Result<near_workspaces::result::ExecutionFinalResult, near_workspaces::error::Error> {
+    // edits pay for their storage, the surplus is refunded
+    caller
+        .call(common.contract.id(), method)
+        .args_json(args)
+        .deposit(ONE_TENTH_NEAR)
+        .transact()
+        .await
+}
+
+async fn get_message(common: &Common, id: U64) -> Result<Value, Box<dyn std::error::Error>> {
+    Ok(common
+        .contract
+        .view("get_message")
+        .args_json(json!({"id": id}))
+        .await?
+        .json()?)
+}
+
+async fn listed_ids(common: &Common) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let page: Page<Value> = common
+        .contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    Ok(page
+        .items
+        .iter()
+        .map(|message| message["id"].clone())
+        .collect())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_edit_and_delete_messages(
+    #[future] v4_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v4_contract.await;
+    let (alice, bob) = (&common.alice, &common.bob);
+
+    let hello = post(&common, alice, "hello").await?;
+    let bye = post(&common, bob, "bye").await?;
+    let spam = post(&common, bob, "spam").await?;
+    assert_eq!([hello, bye, spam], [U64(0), U64(1), U64(2)]);
+
+    // only the sender edits its message, keeping the previous text
+    let edit_args = json!({"id": hello, "text": "hello, world"});
+    let bob_edit_outcome = change_message(&common, bob, "edit_message", edit_args.clone()).await?;
+    assert!(format!("{:?}", bob_edit_outcome.into_result().unwrap_err())
+        .contains("Only the sender or the owner can change this message"));
+    assert!(change_message(&common, alice, "edit_message", edit_args)
+        .await?
+        .is_success());
+
+    let message = get_message(&common, hello).await?;
+    assert_eq!(message["text"], "hello, world");
+    assert_eq!(message["history"][0]["text"], "hello");
+    assert_eq!(message["history"][0]["edited_by"], json!(alice.id()));
+    assert_eq!(message["deleted"], Value::Null);
+
+    // deleting leaves a tombstone, the ids of the other messages do not change
+    let delete_outcome = change_message(&common, bob, "delete_message", json!({"id": bye})).await?;
+    assert!(delete_outcome.is_success());
+    assert_eq!(listed_ids(&common).await?, [json!("0"), json!("2")]);
+
+    let message = get_message(&common, bye).await?;
+    assert_eq!(message["text"], "");
+    assert_eq!(message["deleted"]["deleted_by"], json!(bob.id()));
+    let edit_deleted_outcome = change_message(
+        &common,
+        bob,
+        "edit_message",
+        json!({"id": bye, "text": "back"}),
+    )
+    .await?;
+    assert!(edit_deleted_outcome.is_failure());
+
+    // the owner moderates the messages of others
+    let moderate_outcome =
+        change_message(&common, alice, "delete_message", json!({"id": spam})).await?;
+    assert!(moderate_outcome.is_success());
+    assert_eq!(sender_texts(&common, bob).await?, Vec::<String>::new());
+    assert!(
+        change_message(&common, bob, "delete_message", json!({"id": hello}))
+            .await?
+            .is_failure()
+    );
+
+    let page: Page<Value> = common
+        .contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(page.items[0]["edited"], true);
+
+    // edits and tombstones are kept apart from the versioned state, an upgrade keeps them
+    let upgrade_outcome = alice
+        .call(common.contract.id(), "unsafe_self_upgrade")
+        .args_borsh((common.code.clone(), String::new()))
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+    assert!(upgrade_outcome.is_success());
+    assert_eq!(listed_ids(&common).await?, [json!("0")]);
+    assert_eq!(
+        get_message(&common, hello).await?["history"][0]["text"],
+        "hello"
+    );
+
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_upgrade_keeps_tombstones_without_history(
+    #[future] v4_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v4_contract.await;
+    let (alice, bob) = (&common.alice, &common.bob);
+
+    let edited = post(&common, alice, "draft").await?;
+    let deleted = post(&common, bob, "first try").await?;
+    for (caller, args) in [
+        (alice, json!({"id": edited, "text": "final"})),
+        (bob, json!({"id": deleted, "text": "second try"})),
+    ] {
+        assert!(change_message(&common, caller, "edit_message", args)
+            .await?
+            .is_success());
+    }
+    assert!(
+        change_message(&common, bob, "delete_message", json!({"id": deleted}))
+            .await?
+            .is_success()
+    );
+    let before = [
+        get_message(&common, edited).await?,
+        get_message(&common, deleted).await?,
+    ];
+
+    let upgrade_outcome = alice
+        .call(common.contract.id(), "unsafe_self_upgrade")
+        .args_borsh((common.code.clone(), String::new()))
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+    assert!(upgrade_outcome.is_success());
+
+    let message = get_message(&common, edited).await?;
+    assert_eq!(message["text"], "final");
+    assert_eq!(message["history"][0]["text"], "draft");
+    assert_eq!(message["deleted"], Value::Null);
+
+    // the deleted message keeps its tombstone, the history from before its deletion is gone
+    let tombstoned = get_message(&common, deleted).await?;
+    assert_eq!(tombstoned["text"], "");
+    assert_eq!(tombstoned["history"], json!([]));
+    assert_eq!(tombstoned["deleted"]["deleted_by"], json!(bob.id()));
+
+    assert_eq!([message, tombstoned], before);
+    assert_eq!(listed_ids(&common).await?, [json!("0")]);
+    Ok(())
+}
+
+async fn storage_deposit(
+    common: &Common,
+    account: &Account,
+    args: Value,
+    deposit: NearToken,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    let deposit_outcome = account
+        .call(common.contract.id(), "storage_deposit")
+        .args_json(args)
+        .deposit(deposit)
+        .transact()
+        .await?;
+    assert!(deposit_outcome.is_success(), "{deposit_outcome:?}");
+    Ok(deposit_outcome.json()?)
+}
+
+async fn storage_balance_of(
+    common: &Common,
+    account: &Account,
+) -> Result<Value, Box<dyn std::error::Error>> {
+    Ok(common
+        .contract
+        .view("storage_balance_of")
+        .args_json(json!({"account_id": account.id()}))
+        .await?
+        .json()?)
+}
+
+fn yocto(amount: &Value) -> u128 {
+    amount.as_str().unwrap().parse().unwrap()
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_advanced_storage_staking(
+    #[future] v4_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let common = v4_contract.await;
+    let (alice, bob) = (&common.alice, &common.bob);
+
+    let bounds: Value = common
+        .contract
+        .view("storage_balance_bounds")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    let min = yocto(&bounds["min"]);
+    assert!(min > 0);
+    assert_eq!(bounds["max"], Value::Null);
+
+    // without a storage balance, the deposit must cover the storage of the message
+    let underfunded_outcome = bob
+        .call(common.contract.id(), "add_message")
+        .args_json(json!({"text": "hello"}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(
+        format!("{:?}", underfunded_outcome.into_result().unwrap_err())
+            .contains("bytes of storage cost")
+    );
+
+    // and premium goes by what is left of it, refunded when below 0.1 NEAR
+    let id = post(&common, alice, "hello").await?;
+    let message = get_message(&common, id).await?;
+    assert_eq!(yocto(&message["payment"]), 0);
+    assert_eq!(message["premium"], false);
+    assert_eq!(storage_balance_of(&common, alice).await?, Value::Null);
+
+    // registering only keeps the minimum, the rest is refunded
+    let balance = storage_deposit(
+        &common,
+        bob,
+        json!({"registration_only": true}),
+        ONE_TENTH_NEAR,
+    )
+    .await?;
+    assert_eq!(yocto(&balance["total"]), min);
+    assert_eq!(yocto(&balance["available"]), 0);
+
+    let prepaid = NearToken::from_millinear(50);
+    let balance = storage_deposit(&common, bob, json!({}), prepaid).await?;
+    assert_eq!(yocto(&balance["available"]), prepaid.as_yoctonear());
+
+    // the storage balance pays for the message, the whole deposit is its payment
+    let id = post(&common, bob, &"bye ".repeat(100)).await?;
+    let message = get_message(&common, id).await?;
+    assert_eq!(yocto(&message["payment"]), ONE_TENTH_NEAR.as_yoctonear());
+    assert_eq!(message["premium"], true);
+
+    let available = yocto(&storage_balance_of(&common, bob).await?["available"]);
+    assert!(available < prepaid.as_yoctonear());
+
+    // a text larger than the balance and the deposit can pay for is rejected
+    let too_long_outcome = bob
+        .call(common.contract.id(), "add_message")
+        .args_json(json!({"text": "x".repeat(10_000)}))
+        .transact()
+        .await?;
+    assert!(too_long_outcome.is_failure());
+
+    // deleting the message gives the storage of its text back to the balance
+    let delete_outcome = change_message(&common, bob, "delete_message", json!({"id": id})).await?;
+    assert!(delete_outcome.is_success());
+    let available_after_delete = yocto(&storage_balance_of(&common, bob).await?["available"]);
+    assert!(available_after_delete > available);
+
+    // when the owner moderates, the storage goes back to the sender, who paid for it
+    let id = post(&common, bob, &"spam ".repeat(100)).await?;
+    let available = yocto(&storage_balance_of(&common, bob).await?["available"]);
+    let moderate_outcome =
+        change_message(&common, alice, "delete_message", json!({"id": id})).await?;
+    assert!(moderate_outcome.is_success());
+    let available_after_delete = yocto(&storage_balance_of(&common, bob).await?["available"]);
+    assert!(available_after_delete > available);
+    assert_eq!(storage_balance_of(&common, alice).await?, Value::Null);
+
+    // withdrawing requires one yoctoNEAR and leaves the registration
+    let no_yocto_outcome = bob
+        .call(common.contract.id(), "storage_withdraw")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    assert!(no_yocto_outcome.is_failure());
+
+    let withdraw_outcome = bob
+        .call(common.contract.id(), "storage_withdraw")
+        .args_json(json!({}))
+        .deposit(NearToken::from_yoctonear(1))
+        .transact()
+        .await?;
+    assert!(withdraw_outcome.is_success());
+    let balance: Value = withdraw_outcome.json()?;
+    assert_eq!(yocto(&balance["available"]), 0);
+    assert_eq!(
+        yocto(&balance["total"]),
+        min + prepaid.as_yoctonear() - available_after_delete
+    );
+
+    Ok(())
+}
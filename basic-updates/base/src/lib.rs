@@ -1,14 +1,21 @@
-use near_sdk::near;
+use near_sdk::{near, BorshStorageKey};
 
+use near_sdk::borsh::BorshSerialize;
+
+use migration_kit::{paginate, Page};
 use near_sdk::collections::Vector;
-use near_sdk::json_types::{U128, U64};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
 
 use near_sdk::{env, AccountId, NearToken};
 
 const POINT_ONE: NearToken = NearToken::from_millinear(100);
 
-const MESSAGES_PREFIX: &[u8] = b"m";
-const PAYMENTS_PREFIX: &[u8] = b"p";
+#[derive(BorshSerialize, BorshStorageKey)]
+#[borsh(crate = "near_sdk::borsh")]
+pub enum StorageKey {
+    Messages,
+    Payments,
+}
 
 #[near(serializers=[json, borsh])]
 pub struct PostedMessage {
@@ -26,8 +33,8 @@ pub struct GuestBook {
 impl Default for GuestBook {
     fn default() -> Self {
         Self {
-            messages: Vector::new(MESSAGES_PREFIX),
-            payments: Vector::new(PAYMENTS_PREFIX),
+            messages: Vector::new(StorageKey::Messages),
+            payments: Vector::new(StorageKey::Payments),
         }
     }
 }
@@ -49,24 +56,29 @@ impl GuestBook {
         self.payments.push(&payment);
     }
 
-    pub fn get_messages(&self, from_index: Option<U128>, limit: Option<U64>) -> Vec<PostedMessage> {
-        let from = u128::from(from_index.unwrap_or(U128(0)));
-
-        self.messages
-            .iter()
-            .skip(from as usize)
-            .take(u64::from(limit.unwrap_or(U64::from(10))) as usize)
-            .collect()
+    pub fn get_messages(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> Page<PostedMessage> {
+        paginate(self.messages.len(), from_index, limit, cursor, |idx| {
+            self.messages.get(idx)
+        })
     }
 
-    pub fn get_payments(&self, from_index: Option<U128>, limit: Option<U64>) -> Vec<U128> {
-        let from = u128::from(from_index.unwrap_or(U128(0)));
+    pub fn get_payments(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> Page<U128> {
+        paginate(self.payments.len(), from_index, limit, cursor, |idx| {
+            self.payments.get(idx).map(|x| U128(x.as_yoctonear()))
+        })
+    }
 
-        self.payments
-            .iter()
-            .skip(from as usize)
-            .take(u64::from(limit.unwrap_or(U64::from(10))) as usize)
-            .map(|x| U128(x.as_yoctonear()))
-            .collect()
+    pub fn total_messages(&self) -> U64 {
+        U64(self.messages.len())
     }
 }
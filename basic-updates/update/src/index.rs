@@ -0,0 +1,58 @@
+use near_sdk::collections::Vector;
+use near_sdk::{env, near, AccountId, NearToken};
+
+use crate::*;
+
+/// Conditions the messages of a query must all meet, unset ones match every message
+#[near(serializers = [json])]
+#[derive(Default)]
+pub struct MessageFilter {
+    pub premium: Option<bool>,
+    pub sender: Option<AccountId>,
+    /// Smallest payment, inclusive
+    pub min_payment: Option<NearToken>,
+    /// Largest payment, inclusive
+    pub max_payment: Option<NearToken>,
+}
+
+impl MessageFilter {
+    pub(crate) fn matches(&self, message: &PostedMessage) -> bool {
+        self.premium
+            .is_none_or(|premium| message.premium == premium)
+            && self
+                .sender
+                .as_ref()
+                .is_none_or(|sender| &message.sender == sender)
+            && self.min_payment.is_none_or(|min| message.payment >= min)
+            && self.max_payment.is_none_or(|max| message.payment <= max)
+    }
+}
+
+// Each sender has its own vector of message indexes, under a prefix derived from its account
+fn sender_index_new(sender: &AccountId) -> Vector<u64> {
+    Vector::new(StorageKey::SenderMessagesOf {
+        account_hash: env::sha256_array(sender.as_bytes()),
+    })
+}
+
+impl GuestBook {
+    /// Pushes a message and records its index in the indexes it belongs to
+    pub(crate) fn push_message(&mut self, message: &PostedMessage) {
+        let idx = self.messages.len();
+        self.messages.push(message);
+
+        if message.premium {
+            self.premium_messages.push(&idx);
+        }
+        let mut sent = self.sender_index(&message.sender);
+        sent.push(&idx);
+        self.sender_messages.insert(&message.sender, &sent);
+    }
+
+    /// Indexes of the messages posted by `sender`, empty if it never posted
+    pub(crate) fn sender_index(&self, sender: &AccountId) -> Vector<u64> {
+        self.sender_messages
+            .get(sender)
+            .unwrap_or_else(|| sender_index_new(sender))
+    }
+}
@@ -1,15 +1,32 @@
-use near_sdk::near;
+use near_sdk::{near, BorshStorageKey};
 
-use near_sdk::collections::Vector;
-use near_sdk::json_types::{U128, U64};
+use near_sdk::borsh::BorshSerialize;
 
-use near_sdk::{env, AccountId, NearToken};
+use migration_kit::{paginate_ordered, Order, Page};
+use near_sdk::collections::{LookupMap, Vector};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
 
+use near_sdk::{env, AccountId, CryptoHash, NearToken};
+
+pub use index::MessageFilter;
+
+mod index;
 mod migrate;
 
 const POINT_ONE: NearToken = NearToken::from_millinear(100);
 
-const MESSAGES_PREFIX: &[u8] = b"m";
+// Variants are never removed nor reordered, so no collection reuses the prefix of an older one
+#[derive(BorshSerialize, BorshStorageKey)]
+#[borsh(crate = "near_sdk::borsh")]
+pub enum StorageKey {
+    Messages,
+    // payments of the base contract, removed by the migration
+    Payments,
+    Quarantine,
+    PremiumMessages,
+    SenderMessages,
+    SenderMessagesOf { account_hash: CryptoHash },
+}
 
 #[near(serializers=[json, borsh])]
 pub struct PostedMessage {
@@ -22,12 +39,21 @@ pub struct PostedMessage {
 #[near(contract_state)]
 pub struct GuestBook {
     messages: Vector<PostedMessage>,
+    // entries set aside by the migration, see `PaymentPolicy::Quarantine`
+    quarantine: Vector<migration_kit::Quarantined<migrate::OldPostedMessage>>,
+    // indexes of the premium messages, oldest first
+    premium_messages: Vector<u64>,
+    // indexes of the messages of each sender, oldest first
+    sender_messages: LookupMap<AccountId, Vector<u64>>,
 }
 
 impl Default for GuestBook {
     fn default() -> Self {
         Self {
-            messages: Vector::new(MESSAGES_PREFIX),
+            messages: Vector::new(StorageKey::Messages),
+            quarantine: Vector::new(StorageKey::Quarantine),
+            premium_messages: Vector::new(StorageKey::PremiumMessages),
+            sender_messages: LookupMap::new(StorageKey::SenderMessages),
         }
     }
 }
@@ -36,6 +62,11 @@ impl Default for GuestBook {
 impl GuestBook {
     #[payable]
     pub fn add_message(&mut self, text: String) {
+        assert!(
+            !migrate::migration_in_progress(),
+            "Migration in progress, try again later"
+        );
+
         let payment = env::attached_deposit();
         let sender = env::predecessor_account_id();
         let premium = payment >= POINT_ONE;
@@ -45,16 +76,55 @@ impl GuestBook {
             premium,
             text,
         };
-        self.messages.push(&message);
+        self.push_message(&message);
     }
 
-    pub fn get_messages(&self, from_index: Option<U128>, limit: Option<U64>) -> Vec<PostedMessage> {
-        let from = u128::from(from_index.unwrap_or(U128(0)));
+    /// Messages meeting `filter`, oldest first unless `order` says otherwise.
+    ///
+    /// A query on a sender or on premium messages only walks the index of those messages,
+    /// other filters are checked while walking, so their pages can hold fewer than `limit`
+    /// messages before the last one. A cursor must be passed back with the same filter.
+    pub fn get_messages(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+        order: Option<Order>,
+        filter: Option<MessageFilter>,
+    ) -> Page<PostedMessage> {
+        let order = order.unwrap_or_default();
+        let filter = filter.unwrap_or_default();
+        let matching = |idx| {
+            self.messages
+                .get(idx)
+                .filter(|message| filter.matches(message))
+        };
+
+        match (&filter.sender, filter.premium) {
+            (Some(sender), _) => {
+                let sent = self.sender_index(sender);
+                paginate_ordered(sent.len(), order, from_index, limit, cursor, |pos| {
+                    matching(sent.get(pos)?)
+                })
+            }
+            (None, Some(true)) => {
+                let premium = &self.premium_messages;
+                paginate_ordered(premium.len(), order, from_index, limit, cursor, |pos| {
+                    matching(premium.get(pos)?)
+                })
+            }
+            _ => paginate_ordered(
+                self.messages.len(),
+                order,
+                from_index,
+                limit,
+                cursor,
+                matching,
+            ),
+        }
+    }
 
-        self.messages
-            .iter()
-            .skip(from as usize)
-            .take(u64::from(limit.unwrap_or(U64::from(10))) as usize)
-            .collect()
+    pub fn total_messages(&self) -> U64 {
+        U64(self.messages.len())
     }
 }
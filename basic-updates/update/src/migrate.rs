@@ -1,6 +1,28 @@
+use std::collections::BTreeSet;
+
+use migration_kit::dry_run::{record_size, storage_read_gas, storage_write_gas, ENTRY_KEY_LEN};
+use migration_kit::{
+    paginate, vector_prefix, DryRunReport, GarbageCollection, PaymentMergeEstimate, PaymentPolicy,
+    PrefixGuard, Quarantined,
+};
+use near_sdk::{Gas, IntoStorageKey};
+
 use crate::*;
 
-#[near(serializers=[borsh])]
+// While a migration is in flight the old state is kept under this key
+const MIGRATION_KEY: &[u8] = b"MIGRATION";
+
+// Number of messages migrated per call when no batch size is given
+const DEFAULT_BATCH_SIZE: u64 = 100;
+
+// Index entries are u64 message indexes, the entries of a sender's index are stored under
+// its prefix, the variant byte and the hash of its account, then a u64 index
+const INDEX_ENTRY_LEN: u64 = 8;
+const SENDER_ENTRY_KEY_LEN: u64 = 1 + 32 + 8;
+// The index of a sender, a length and its prefix, is stored under the borsh account id
+const SENDER_INDEX_LEN: u64 = 8 + 4 + 1 + 32;
+
+#[near(serializers=[borsh, json])]
 pub struct OldPostedMessage {
     pub premium: bool,
     pub sender: AccountId,
@@ -13,39 +35,311 @@ pub struct OldState {
     payments: Vector<NearToken>,
 }
 
+impl OldState {
+    // Whether the old messages share the prefix of the new ones, which then overwrite them
+    // in place, rather than being stored under the prefix of an older contract, e.g. `b"m"`
+    fn messages_in_place(&self) -> bool {
+        vector_prefix(&self.messages) == StorageKey::Messages.into_storage_key()
+    }
+}
+
+// The old state and how far its migration got
+#[near(serializers=[borsh])]
+struct PendingMigration {
+    old_state: OldState,
+    payment_policy: PaymentPolicy,
+    // index of the next old message to migrate
+    cursor: u64,
+    missing_payments: u64,
+}
+
+impl PendingMigration {
+    // Old entries the batches go through: every message, then with the quarantine policy the
+    // payments past the last message, which have no message to be migrated with
+    fn entries(&self) -> u64 {
+        let messages = self.old_state.messages.len();
+        match self.payment_policy {
+            PaymentPolicy::Quarantine => messages.max(self.old_state.payments.len()),
+            _ => messages,
+        }
+    }
+}
+
+#[near(serializers=[json])]
+pub struct MigrationProgress {
+    pub migrated: U64,
+    pub total: U64,
+    pub done: bool,
+}
+
+pub(crate) fn migration_in_progress() -> bool {
+    env::storage_has_key(MIGRATION_KEY)
+}
+
+fn pending_migration_read() -> Option<PendingMigration> {
+    migration_kit::read_key(MIGRATION_KEY).unwrap_or_else(|err| err.panic())
+}
+
 #[near]
 impl GuestBook {
     #[private]
     #[init(ignore_state)]
-    pub fn migrate() -> Self {
+    pub fn migrate(batch_size: Option<U64>, payment_policy: Option<PaymentPolicy>) -> Self {
+        assert!(
+            !migration_in_progress(),
+            "Migration already in progress, continue it with migrate_batch"
+        );
+
         // retrieve the current state from the contract
-        let mut old_state: OldState = env::state_read().expect("failed");
+        let old_state: OldState = migration_kit::expect_state();
+
+        // the old vectors are live under the prefixes they were stored with
+        let mut prefixes = PrefixGuard::new()
+            .live_vector(&old_state.messages)
+            .live_vector(&old_state.payments);
+
+        // a strict migration fails right away if the messages and payments do not pair up
+        let payment_policy = payment_policy.unwrap_or_default();
+        payment_policy.check_lengths(old_state.messages.len(), old_state.payments.len());
+
+        // new messages vector to hold the migrated messages, it overwrites the old messages
+        // one by one when they share its prefix, while no other collection may touch them
+        let messages_prefix = if old_state.messages_in_place() {
+            prefixes.replace(StorageKey::Messages)
+        } else {
+            prefixes.create(StorageKey::Messages)
+        };
+        let messages = Vector::new(messages_prefix.unwrap_or_else(|err| err.panic()));
+
+        // keep the old state around until every message has been moved
+        migration_kit::write_key(
+            MIGRATION_KEY,
+            &PendingMigration {
+                old_state,
+                payment_policy,
+                cursor: 0,
+                missing_payments: 0,
+            },
+        );
+
+        let mut contract = Self {
+            messages,
+            quarantine: Vector::new(
+                prefixes
+                    .create(StorageKey::Quarantine)
+                    .unwrap_or_else(|err| err.panic()),
+            ),
+            // the indexes are built as the messages are migrated
+            premium_messages: Vector::new(
+                prefixes
+                    .create(StorageKey::PremiumMessages)
+                    .unwrap_or_else(|err| err.panic()),
+            ),
+            sender_messages: LookupMap::new(
+                prefixes
+                    .create(StorageKey::SenderMessages)
+                    .unwrap_or_else(|err| err.panic()),
+            ),
+        };
+        contract.migrate_batch(batch_size);
+        contract
+    }
+
+    #[private]
+    pub fn migrate_batch(&mut self, batch_size: Option<U64>) -> MigrationProgress {
+        let mut pending = pending_migration_read().expect("No migration in progress");
+        let policy = pending.payment_policy;
+
+        let messages = pending.old_state.messages.len();
+        let total = pending.entries();
+        let from = pending.cursor;
+        let to = total.min(from + u64::from(batch_size.unwrap_or(U64(DEFAULT_BATCH_SIZE))));
+
+        // iterate through the next batch of old messages migrating them to the new version
+        for idx in from..to.min(messages) {
+            let posted = pending
+                .old_state
+                .messages
+                .get(idx)
+                .expect("failed to get message");
+            let payment = pending.old_state.payments.get(idx);
+            if payment.is_none() {
+                pending.missing_payments += 1;
+            }
+
+            match policy.resolve(idx, payment) {
+                // push the new message to the new messages vector
+                Some(payment) => self.push_message(&PostedMessage {
+                    payment,
+                    premium: posted.premium,
+                    sender: posted.sender,
+                    text: posted.text,
+                }),
+                None => self.quarantine.push(&Quarantined {
+                    index: U64(idx),
+                    message: Some(posted),
+                    payment: None,
+                }),
+            }
+        }
+
+        // quarantined messages leave old messages past the migrated ones when they share their
+        // prefix, removed by the batch that read them rather than all at once by the last one
+        if pending.old_state.messages_in_place() {
+            for idx in self.messages.len().max(from)..to.min(messages) {
+                let key = [
+                    StorageKey::Messages.into_storage_key(),
+                    idx.to_le_bytes().to_vec(),
+                ];
+                env::storage_remove(&key.concat());
+            }
+        }
+
+        // payments past the last message are quarantined in the batches too, so that no call
+        // goes through more than `batch_size` entries
+        for idx in messages.max(from)..to {
+            self.quarantine.push(&Quarantined {
+                index: U64(idx),
+                message: None,
+                payment: pending.old_state.payments.get(idx),
+            });
+        }
+
+        pending.cursor = to;
+
+        let done = to == total;
+        if done {
+            self.finish_migration(&pending);
+            env::storage_remove(MIGRATION_KEY);
+        } else {
+            migration_kit::write_key(MIGRATION_KEY, &pending);
+        }
+
+        near_sdk::log!("Migrated {} of {} entries", to, total);
+
+        MigrationProgress {
+            migrated: U64(to),
+            total: U64(total),
+            done,
+        }
+    }
+
+    pub fn get_migration_progress(&self) -> Option<MigrationProgress> {
+        let pending = pending_migration_read()?;
+
+        Some(MigrationProgress {
+            migrated: U64(pending.cursor),
+            total: U64(pending.entries()),
+            done: false,
+        })
+    }
+
+    /// Messages and payments set aside by a migration with the `quarantine` policy
+    pub fn get_quarantined(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> Page<Quarantined<OldPostedMessage>> {
+        paginate(self.quarantine.len(), from_index, limit, cursor, |idx| {
+            self.quarantine.get(idx)
+        })
+    }
 
-        // new messages vector to hold the migrated messages
-        let mut new_messages: Vector<PostedMessage> = Vector::new(MESSAGES_PREFIX);
+    /// Removes up to `limit` entries left behind by the migration, 100 by default
+    #[private]
+    pub fn collect_garbage(&mut self, limit: Option<U64>) -> GarbageCollection {
+        migration_kit::collect_garbage(limit.unwrap_or(U64(DEFAULT_BATCH_SIZE)).0)
+    }
 
-        // iterate through the state migrating it to the new version
-        for (idx, posted) in old_state.messages.iter().enumerate() {
-            // get the payment and remove it from the old state payments vector so it won't be left in the new state
-            let payment = old_state.payments.get(idx as u64)
-                .expect("failed to get payment")
-                .clone();
+    /// Checks the stored state can be migrated with `payment_policy` and estimates the cost
+    /// of migrating a page of its messages, without modifying anything.
+    ///
+    /// Each page counts the first message of a sender in it as creating its index, so the
+    /// estimates of several pages can count the index of a sender more than once.
+    pub fn migration_dry_run(
+        payment_policy: Option<PaymentPolicy>,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> DryRunReport {
+        if migration_in_progress() {
+            return DryRunReport::fail("Migration already in progress");
+        }
+        let old_state: OldState = match migration_kit::read_state() {
+            Ok(old_state) => old_state,
+            Err(err) => return DryRunReport::fail(err),
+        };
 
-            // push the new message to the new messages vector
-            new_messages.push(&PostedMessage {
-                payment,
-                premium: posted.premium,
-                sender: posted.sender.clone(),
-                text: posted.text.clone(),
-            })
+        let messages = old_state.messages.len();
+        let mut estimate = PaymentMergeEstimate::new(
+            payment_policy.unwrap_or_default(),
+            messages,
+            old_state.payments.len(),
+        );
+        if !old_state.messages_in_place() {
+            estimate = estimate.moving_messages();
         }
+        let mut senders = BTreeSet::new();
 
-        // remove the payments from the old state
-        old_state.payments.clear();
+        let page = paginate(messages, from_index, limit, cursor, |idx| {
+            let posted = old_state.messages.get(idx)?;
+            let len = near_sdk::borsh::to_vec(&posted).map_or(0, |data| data.len() as u64);
 
-        // return the new state
-        Self {
-            messages: new_messages,
+            // a migrated message is indexed, the first of a sender creating its index
+            if estimate.add_message(idx, len) {
+                let (delta, gas) = index_cost(&posted, senders.insert(posted.sender.clone()));
+                estimate.add(delta, gas);
+            }
+            None::<()>
+        });
+
+        // then the payments are retired, counted with the last page
+        if page.next_cursor.is_none() {
+            estimate.add_payments();
         }
+        estimate.report(page.next_cursor)
+    }
+}
+
+// Storage and gas indexing a migrated message takes
+fn index_cost(posted: &OldPostedMessage, new_sender: bool) -> (i64, Gas) {
+    let sender_key_len = 1 + 4 + posted.sender.len() as u64;
+    let mut storage_delta = record_size(SENDER_ENTRY_KEY_LEN, INDEX_ENTRY_LEN);
+    let mut gas = storage_read_gas(sender_key_len, SENDER_INDEX_LEN).as_gas()
+        + storage_write_gas(SENDER_ENTRY_KEY_LEN, INDEX_ENTRY_LEN, 0).as_gas();
+
+    if new_sender {
+        storage_delta += record_size(sender_key_len, SENDER_INDEX_LEN);
+        gas += storage_write_gas(sender_key_len, SENDER_INDEX_LEN, 0).as_gas();
+    } else {
+        gas += storage_write_gas(sender_key_len, SENDER_INDEX_LEN, SENDER_INDEX_LEN).as_gas();
+    }
+    if posted.premium {
+        storage_delta += record_size(ENTRY_KEY_LEN, INDEX_ENTRY_LEN);
+        gas += storage_write_gas(ENTRY_KEY_LEN, INDEX_ENTRY_LEN, 0).as_gas();
+    }
+    (storage_delta as i64, Gas::from_gas(gas))
+}
+
+impl GuestBook {
+    fn finish_migration(&mut self, pending: &PendingMigration) {
+        let policy = pending.payment_policy;
+        let old_state = &pending.old_state;
+        let total = old_state.messages.len();
+
+        // payments past the last message have nothing to be migrated with, the batches
+        // quarantined them with the quarantine policy
+        let orphaned_payments = old_state.payments.len().saturating_sub(total);
+        migration_kit::emit_payment_mismatch(policy, pending.missing_payments, orphaned_payments);
+
+        // old messages under another prefix are all left behind
+        if !old_state.messages_in_place() {
+            migration_kit::retire_vector(vector_prefix(&old_state.messages), total);
+        }
+
+        // the payments are left for `collect_garbage`, clearing them all at once could run
+        // out of gas on a large guest book
+        migration_kit::retire_vector(vector_prefix(&old_state.payments), old_state.payments.len());
     }
 }
@@ -1,16 +1,28 @@
 use rstest::{fixture, rstest};
 use std::fs;
 
-use near_workspaces::{types::NearToken, Account, Contract};
+use migration_kit::Page;
+use near_workspaces::network::Sandbox;
+use near_workspaces::operations::Function;
+use near_workspaces::types::{Gas, NearToken};
+use near_workspaces::{Account, Contract, Worker};
 use serde_json::json;
 
-use near_sdk::{json_types::U128, AccountId};
+use near_sdk::{
+    json_types::{I64, U128, U64},
+    AccountId,
+};
 
 const FIVE_NEAR: NearToken = NearToken::from_near(5);
 const ONE_TENTH_NEAR: NearToken = NearToken::from_millinear(100);
 const NINE_HUNDREDTH_NEAR: NearToken = NearToken::from_millinear(90);
 
+// Prefixes of the vectors, the borsh encoding of their `StorageKey` variant
+const MESSAGES_PREFIX: &[u8] = &[0];
+const PAYMENTS_PREFIX: &[u8] = &[1];
+
 struct Common {
+    sandbox: Worker<Sandbox>,
     contract: Contract,
     alice: Account,
     guest_book: Account,
@@ -67,6 +79,7 @@ async fn base_contract() -> Common {
     assert!(alice_first_message_outcome.is_success());
 
     Common {
+        sandbox,
         contract,
         alice,
         guest_book: guest_book_account,
@@ -92,7 +105,8 @@ async fn test_basic_updates_base_contract_returns(
         .view("get_messages")
         .args_json(json!({}))
         .await?
-        .json()?;
+        .json::<Page<_>>()?
+        .items;
 
     assert_eq!(
         messages_vec,
@@ -114,7 +128,8 @@ async fn test_basic_updates_base_contract_returns(
         .view("get_payments")
         .args_json(json!({}))
         .await?
-        .json()?;
+        .json::<Page<_>>()?
+        .items;
 
     assert_eq!(
         payments_vec,
@@ -167,7 +182,8 @@ async fn test_basic_updates_migration(
         .view("get_messages")
         .args_json(json!({}))
         .await?
-        .json()?;
+        .json::<Page<_>>()?
+        .items;
     assert_eq!(
         messages_vec,
         vec![
@@ -193,3 +209,952 @@ async fn test_basic_updates_migration(
     assert!(get_payments_result.is_err());
     Ok(())
 }
+
+#[rstest]
+#[tokio::test]
+async fn test_basic_updates_batched_migration(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+
+    // grow the guest book well beyond a single migration batch
+    const EXTRA_MESSAGES: u128 = 60;
+    for batch in 0..(EXTRA_MESSAGES / 20) {
+        let mut transaction = base_contract.alice.batch(base_contract.contract.id());
+        for i in 0..20 {
+            let idx = batch * 20 + i;
+            transaction = transaction.call(
+                Function::new("add_message")
+                    .args_json(json!({"text": format!("message {idx}")}))
+                    .deposit(NearToken::from_yoctonear(idx + 1))
+                    .gas(Gas::from_tgas(10)),
+            );
+        }
+        assert!(transaction.transact().await?.is_success());
+    }
+    let total = EXTRA_MESSAGES as u64 + 2;
+
+    fs::create_dir_all("../../target/near/update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+
+    let migrated_contract = base_contract
+        .guest_book
+        .deploy(&updated_contract_wasm)
+        .await
+        .unwrap()
+        .into_result()
+        .unwrap();
+
+    #[derive(near_sdk::serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(crate = "near_sdk::serde")]
+    pub struct MigrationProgress {
+        pub migrated: U64,
+        pub total: U64,
+        pub done: bool,
+    }
+
+    let migrate_call_outcome = base_contract
+        .guest_book
+        .call(migrated_contract.id(), "migrate")
+        .args_json(json!({"batch_size": "15"}))
+        .transact()
+        .await?;
+    assert!(migrate_call_outcome.is_success());
+
+    let progress: Option<MigrationProgress> = migrated_contract
+        .view("get_migration_progress")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(
+        progress,
+        Some(MigrationProgress {
+            migrated: U64(15),
+            total: U64(total),
+            done: false,
+        })
+    );
+
+    // writes are rejected while the migration is in flight
+    let add_message_outcome = base_contract
+        .alice
+        .call(migrated_contract.id(), "add_message")
+        .args_json(json!({"text": "too early"}))
+        .transact()
+        .await?;
+    assert!(add_message_outcome.is_failure());
+
+    // restarting the migration would read the new state as the old one
+    let migrate_again_outcome = base_contract
+        .guest_book
+        .call(migrated_contract.id(), "migrate")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    assert!(migrate_again_outcome.is_failure());
+
+    // only the contract itself can move the migration forward
+    let alice_batch_outcome = base_contract
+        .alice
+        .call(migrated_contract.id(), "migrate_batch")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    assert!(alice_batch_outcome.is_failure());
+
+    let mut calls = 1;
+    loop {
+        let progress: MigrationProgress = base_contract
+            .guest_book
+            .call(migrated_contract.id(), "migrate_batch")
+            .args_json(json!({"batch_size": "15"}))
+            .transact()
+            .await?
+            .json()?;
+        calls += 1;
+        if progress.done {
+            assert_eq!(progress.migrated, U64(total));
+            break;
+        }
+    }
+    assert_eq!(calls, total.div_ceil(15));
+
+    let progress: Option<MigrationProgress> = migrated_contract
+        .view("get_migration_progress")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(progress, None);
+
+    #[derive(near_sdk::serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(crate = "near_sdk::serde")]
+    pub struct PostedMessage {
+        pub payment: NearToken,
+        pub premium: bool,
+        pub sender: AccountId,
+        pub text: String,
+    }
+    let messages_vec: Vec<PostedMessage> = migrated_contract
+        .view("get_messages")
+        .args_json(json!({"from_index": "2", "limit": "100"}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+    assert_eq!(messages_vec.len() as u128, EXTRA_MESSAGES);
+    for (idx, message) in messages_vec.iter().enumerate() {
+        assert_eq!(
+            message,
+            &PostedMessage {
+                payment: NearToken::from_yoctonear(idx as u128 + 1),
+                premium: false,
+                sender: base_contract.alice.id().clone(),
+                text: format!("message {idx}"),
+            }
+        );
+    }
+
+    // the old payments vector and the migration bookkeeping are gone
+    collect_garbage(&migrated_contract).await?;
+    let state = migrated_contract.view_state().await?;
+    assert!(state.keys().all(|key| !key.starts_with(PAYMENTS_PREFIX)));
+    assert!(!state.contains_key(b"MIGRATION".as_slice()));
+
+    // the guest book accepts messages again
+    let add_message_outcome = base_contract
+        .alice
+        .call(migrated_contract.id(), "add_message")
+        .args_json(json!({"text": "welcome back"}))
+        .transact()
+        .await?;
+    assert!(add_message_outcome.is_success());
+
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_basic_updates_migration_dry_run(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+
+    fs::create_dir_all("../../target/near/update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+
+    let migrated_contract = base_contract
+        .guest_book
+        .deploy(&updated_contract_wasm)
+        .await
+        .unwrap()
+        .into_result()
+        .unwrap();
+
+    #[derive(near_sdk::serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(crate = "near_sdk::serde")]
+    pub struct DryRunReport {
+        pub status: String,
+        pub error: Option<String>,
+        pub messages: U64,
+        pub payments: U64,
+        pub missing_payments: U64,
+        pub orphaned_payments: U64,
+        pub storage_delta: I64,
+        pub estimated_gas: Gas,
+        pub retired_keys: U64,
+        pub reclaimable_bytes: U64,
+        pub next_cursor: Option<String>,
+    }
+    let storage_before = migrated_contract.view_account().await?.storage_usage;
+
+    let report: DryRunReport = migrated_contract
+        .view("migration_dry_run")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(report.status, "pass");
+    assert_eq!(report.error, None);
+    assert_eq!(report.messages, U64(2));
+    assert_eq!(report.payments, U64(2));
+    assert_eq!(report.missing_payments, U64(0));
+    assert_eq!(report.orphaned_payments, U64(0));
+    // the messages grow by their payments and are indexed, the payments are retired
+    assert!(report.storage_delta.0 > 0);
+    assert_eq!(report.retired_keys, U64(2));
+    assert!(report.estimated_gas > Gas::from_gas(0));
+    assert_eq!(report.next_cursor, None);
+
+    // a page estimates its own messages, the last one also counting the payments
+    let first_page: DryRunReport = migrated_contract
+        .view("migration_dry_run")
+        .args_json(json!({"limit": "1"}))
+        .await?
+        .json()?;
+
+    assert_eq!(first_page.messages, U64(2));
+    assert!(first_page.next_cursor.is_some());
+
+    let last_page: DryRunReport = migrated_contract
+        .view("migration_dry_run")
+        .args_json(json!({"cursor": first_page.next_cursor}))
+        .await?
+        .json()?;
+
+    assert_eq!(last_page.next_cursor, None);
+    assert_eq!(
+        first_page.storage_delta.0 + last_page.storage_delta.0,
+        report.storage_delta.0
+    );
+    assert_eq!(
+        first_page.estimated_gas.as_gas() + last_page.estimated_gas.as_gas(),
+        report.estimated_gas.as_gas()
+    );
+
+    // the dry run does not touch the state
+    assert_eq!(
+        migrated_contract.view_account().await?.storage_usage,
+        storage_before
+    );
+
+    let migrate_call_outcome = base_contract
+        .guest_book
+        .call(migrated_contract.id(), "migrate")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+
+    assert!(migrate_call_outcome.is_success());
+
+    // the estimate ignores the contract struct itself, which changes by a few bytes,
+    // and the record listing the retired payments
+    let storage_after = migrated_contract.view_account().await?.storage_usage;
+    let storage_delta = storage_after as i64 - storage_before as i64;
+    assert!((storage_delta - report.storage_delta.0).abs() < 64);
+
+    // the payments are then collected, along with the record listing them
+    collect_garbage(&migrated_contract).await?;
+    let storage_collected = migrated_contract.view_account().await?.storage_usage;
+    let reclaimed = storage_after - storage_collected;
+    assert!(reclaimed >= report.reclaimable_bytes.0);
+    assert!(reclaimed - report.reclaimable_bytes.0 < 64);
+
+    // the migrated state no longer has the old layout
+    let report: DryRunReport = migrated_contract
+        .view("migration_dry_run")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(report.status, "fail");
+    assert!(report.error.is_some());
+    Ok(())
+}
+
+// NEP-297 events logged by a transaction
+fn events(logs: &[&str]) -> Vec<near_sdk::serde_json::Value> {
+    logs.iter()
+        .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+        .map(|event| near_sdk::serde_json::from_str(event).unwrap())
+        .collect()
+}
+
+// Overwrites the lengths of the base vectors, leaving their entries as they are
+async fn patch_lengths(base_contract: &Common, messages: u64, payments: u64) {
+    let state = near_sdk::borsh::to_vec(&(
+        messages,
+        MESSAGES_PREFIX.to_vec(),
+        payments,
+        PAYMENTS_PREFIX.to_vec(),
+    ))
+    .unwrap();
+    base_contract
+        .sandbox
+        .patch_state(base_contract.contract.id(), b"STATE", &state)
+        .await
+        .unwrap();
+}
+
+// Calls `collect_garbage` until nothing is left, returning the events it logged
+async fn collect_garbage(
+    contract: &Contract,
+) -> Result<Vec<near_sdk::serde_json::Value>, Box<dyn std::error::Error>> {
+    let mut logged = vec![];
+    loop {
+        let outcome = contract
+            .call("collect_garbage")
+            .args_json(json!({}))
+            .transact()
+            .await?;
+        logged.extend(events(&outcome.logs()));
+
+        let collection: near_sdk::serde_json::Value = outcome.json()?;
+        if collection["done"] == true {
+            return Ok(logged);
+        }
+    }
+}
+
+async fn deploy_update(base_contract: &Common) -> Contract {
+    fs::create_dir_all("../../target/near/update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+
+    base_contract
+        .guest_book
+        .deploy(&updated_contract_wasm)
+        .await
+        .unwrap()
+        .into_result()
+        .unwrap()
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_basic_updates_strict_policy_rejects_mismatch(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+    patch_lengths(&base_contract, 2, 1).await;
+    let migrated_contract = deploy_update(&base_contract).await;
+
+    let dry_run: near_sdk::serde_json::Value = migrated_contract
+        .view("migration_dry_run")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(dry_run["status"], "fail");
+    assert_eq!(dry_run["missing_payments"], "1");
+
+    let migrate_call_outcome = base_contract
+        .guest_book
+        .call(migrated_contract.id(), "migrate")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+
+    assert!(migrate_call_outcome.is_failure());
+
+    // nothing was migrated
+    let state = migrated_contract.view_state().await?;
+    assert!(!state.contains_key(b"MIGRATION".as_slice()));
+    assert!(migrated_contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await
+        .is_err());
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_basic_updates_default_to_zero_policy(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+    patch_lengths(&base_contract, 2, 1).await;
+    let migrated_contract = deploy_update(&base_contract).await;
+
+    let migrate_call_outcome = base_contract
+        .guest_book
+        .call(migrated_contract.id(), "migrate")
+        .args_json(json!({"payment_policy": "default-to-zero"}))
+        .transact()
+        .await?;
+
+    assert!(migrate_call_outcome.is_success());
+    assert_eq!(
+        events(&migrate_call_outcome.logs()),
+        vec![json!({
+            "standard": "migration-kit",
+            "version": "1.0.0",
+            "event": "payment_mismatch",
+            "data": [{"policy": "default-to-zero", "missing_payments": "1", "orphaned_payments": "0"}],
+        })]
+    );
+
+    #[derive(near_sdk::serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(crate = "near_sdk::serde")]
+    pub struct PostedMessage {
+        pub payment: NearToken,
+        pub premium: bool,
+        pub sender: AccountId,
+        pub text: String,
+    }
+    let messages_vec: Vec<PostedMessage> = migrated_contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(messages_vec.len(), 2);
+    assert_eq!(messages_vec[0].payment, NINE_HUNDREDTH_NEAR);
+    assert_eq!(messages_vec[1].payment, NearToken::from_near(0));
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_basic_updates_quarantine_policy(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+    patch_lengths(&base_contract, 2, 1).await;
+    let migrated_contract = deploy_update(&base_contract).await;
+
+    let migrate_call_outcome = base_contract
+        .guest_book
+        .call(migrated_contract.id(), "migrate")
+        .args_json(json!({"payment_policy": "quarantine", "batch_size": "1"}))
+        .transact()
+        .await?;
+
+    assert!(migrate_call_outcome.is_success());
+
+    let migrate_batch_outcome = base_contract
+        .guest_book
+        .call(migrated_contract.id(), "migrate_batch")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+
+    assert!(migrate_batch_outcome.is_success());
+    assert_eq!(
+        events(&migrate_batch_outcome.logs())[0]["data"],
+        json!([{"policy": "quarantine", "missing_payments": "1", "orphaned_payments": "0"}])
+    );
+
+    #[derive(near_sdk::serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(crate = "near_sdk::serde")]
+    pub struct OldPostedMessage {
+        pub premium: bool,
+        pub sender: AccountId,
+        pub text: String,
+    }
+    #[derive(near_sdk::serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(crate = "near_sdk::serde")]
+    pub struct Quarantined {
+        pub index: U64,
+        pub message: Option<OldPostedMessage>,
+        pub payment: Option<NearToken>,
+    }
+    let quarantined: Vec<Quarantined> = migrated_contract
+        .view("get_quarantined")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(
+        quarantined,
+        vec![Quarantined {
+            index: U64(1),
+            message: Some(OldPostedMessage {
+                premium: true,
+                sender: base_contract.alice.id().clone(),
+                text: "bye".to_string(),
+            }),
+            payment: None,
+        }]
+    );
+
+    let messages_vec: Vec<near_sdk::serde_json::Value> = migrated_contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(messages_vec.len(), 1);
+
+    // the quarantined message no longer lingers under the messages prefix
+    collect_garbage(&migrated_contract).await?;
+    let state = migrated_contract.view_state().await?;
+    let mut old_message_key = MESSAGES_PREFIX.to_vec();
+    old_message_key.extend_from_slice(&1u64.to_le_bytes());
+    assert!(!state.contains_key(&old_message_key));
+    assert!(state.keys().all(|key| !key.starts_with(PAYMENTS_PREFIX)));
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_basic_updates_quarantine_orphaned_payments(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+    patch_lengths(&base_contract, 1, 2).await;
+    let migrated_contract = deploy_update(&base_contract).await;
+
+    let migrate_call_outcome = base_contract
+        .guest_book
+        .call(migrated_contract.id(), "migrate")
+        .args_json(json!({"payment_policy": "quarantine", "batch_size": "1"}))
+        .transact()
+        .await?;
+
+    assert!(migrate_call_outcome.is_success());
+
+    // the orphaned payment is an entry of its own, left to the next batch
+    let progress: near_sdk::serde_json::Value = migrated_contract
+        .view("get_migration_progress")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(
+        progress,
+        json!({"migrated": "1", "total": "2", "done": false})
+    );
+
+    let migrate_batch_outcome = base_contract
+        .guest_book
+        .call(migrated_contract.id(), "migrate_batch")
+        .args_json(json!({"batch_size": "1"}))
+        .transact()
+        .await?;
+
+    assert!(migrate_batch_outcome.is_success());
+    assert_eq!(
+        events(&migrate_batch_outcome.logs())[0]["data"],
+        json!([{"policy": "quarantine", "missing_payments": "0", "orphaned_payments": "1"}])
+    );
+
+    let quarantined: Vec<near_sdk::serde_json::Value> = migrated_contract
+        .view("get_quarantined")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(
+        quarantined,
+        vec![json!({
+            "index": "1",
+            "message": null,
+            "payment": ONE_TENTH_NEAR.as_yoctonear().to_string(),
+        })]
+    );
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_basic_updates_collect_garbage(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+    let migrated_contract = deploy_update(&base_contract).await;
+
+    let migrate_outcome = base_contract
+        .guest_book
+        .call(migrated_contract.id(), "migrate")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    assert!(migrate_outcome.is_success());
+
+    // the migration leaves the payments behind for the garbage collection
+    let payment_keys = |state: &std::collections::HashMap<Vec<u8>, Vec<u8>>| {
+        state
+            .keys()
+            .filter(|key| key.starts_with(PAYMENTS_PREFIX))
+            .count()
+    };
+    let state = migrated_contract.view_state().await?;
+    assert_eq!(payment_keys(&state), 2);
+    assert!(state.contains_key(b"RETIRED".as_slice()));
+
+    let storage_before = migrated_contract.view_account().await?.storage_usage;
+
+    // removes a single payment per call
+    let outcome = migrated_contract
+        .call("collect_garbage")
+        .args_json(json!({"limit": "1"}))
+        .transact()
+        .await?;
+    let first_events = events(&outcome.logs());
+    let collection: near_sdk::serde_json::Value = outcome.json()?;
+    assert_eq!(collection["removed_keys"], "1");
+    assert_eq!(collection["remaining_keys"], "1");
+    assert_eq!(collection["done"], false);
+    assert_eq!(payment_keys(&migrated_contract.view_state().await?), 1);
+
+    let mut logged = first_events;
+    logged.extend(collect_garbage(&migrated_contract).await?);
+    let storage_after = migrated_contract.view_account().await?.storage_usage;
+
+    // the events account for all the storage freed
+    assert_eq!(logged.len(), 2);
+    let reclaimed: u64 = logged
+        .iter()
+        .map(|event| {
+            assert_eq!(event["standard"], "migration-kit");
+            assert_eq!(event["event"], "storage_reclaimed");
+            event["data"][0]["reclaimed_bytes"]
+                .as_str()
+                .unwrap()
+                .parse::<u64>()
+                .unwrap()
+        })
+        .sum();
+    assert_eq!(reclaimed, storage_before - storage_after);
+
+    // nothing is left behind but the migrated messages
+    let state = migrated_contract.view_state().await?;
+    assert_eq!(payment_keys(&state), 0);
+    assert!(!state.contains_key(b"RETIRED".as_slice()));
+    assert_eq!(
+        state
+            .keys()
+            .filter(|key| key.starts_with(MESSAGES_PREFIX))
+            .count(),
+        2
+    );
+
+    // only the contract can collect its garbage
+    let outcome = base_contract
+        .alice
+        .call(migrated_contract.id(), "collect_garbage")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    assert!(outcome.is_failure());
+    Ok(())
+}
+
+// Moves the base vectors under `b"m"` and `b"p"`, the prefixes of the first base contracts,
+// marking the texts and doubling the payments so the migrated values tell where they were read
+async fn patch_original_prefixes(base_contract: &Common) {
+    let sandbox = &base_contract.sandbox;
+    let id = base_contract.contract.id();
+
+    for (key, value) in base_contract.contract.view_state().await.unwrap() {
+        let (prefix, value) = match key.split_first() {
+            Some((0, _)) if key.len() == 9 => {
+                let (premium, sender, text): (bool, String, String) =
+                    near_sdk::borsh::from_slice(&value).unwrap();
+                let value = (premium, sender, format!("{text} (m)"));
+                (b"m", near_sdk::borsh::to_vec(&value).unwrap())
+            }
+            Some((1, _)) if key.len() == 9 => {
+                let payment = u128::from_le_bytes(value.try_into().unwrap());
+                (b"p", (2 * payment).to_le_bytes().to_vec())
+            }
+            _ => continue,
+        };
+        let key = [prefix.as_slice(), &key[1..]].concat();
+        sandbox.patch_state(id, &key, &value).await.unwrap();
+    }
+
+    let state = near_sdk::borsh::to_vec(&(2u64, b"m".to_vec(), 2u64, b"p".to_vec())).unwrap();
+    sandbox.patch_state(id, b"STATE", &state).await.unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_basic_updates_original_prefixes(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+    patch_original_prefixes(&base_contract).await;
+    let migrated_contract = deploy_update(&base_contract).await;
+
+    // the old messages move to the prefix of the new ones
+    let report: near_sdk::serde_json::Value = migrated_contract
+        .view("migration_dry_run")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(report["status"], "pass");
+    assert_eq!(report["retired_keys"], "4");
+
+    let migrate_outcome = base_contract
+        .guest_book
+        .call(migrated_contract.id(), "migrate")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    assert!(migrate_outcome.is_success());
+
+    let messages: Vec<near_sdk::serde_json::Value> = migrated_contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["text"], "hello (m)");
+    assert_eq!(
+        messages[0]["payment"],
+        (2 * NINE_HUNDREDTH_NEAR.as_yoctonear()).to_string()
+    );
+    assert_eq!(messages[1]["text"], "bye (m)");
+    assert_eq!(
+        messages[1]["payment"],
+        (2 * ONE_TENTH_NEAR.as_yoctonear()).to_string()
+    );
+
+    // both old vectors are retired, and collected without touching the new messages
+    collect_garbage(&migrated_contract).await?;
+
+    let state = migrated_contract.view_state().await?;
+    assert!(state
+        .keys()
+        .all(|key| !key.starts_with(b"m") && !key.starts_with(b"p")));
+
+    let messages: Vec<near_sdk::serde_json::Value> = migrated_contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(messages.len(), 2);
+    Ok(())
+}
+
+// Gas burnt by `get_messages` called in a transaction, views do not report it
+async fn get_messages_gas(
+    caller: &Account,
+    contract: &Contract,
+    args: near_sdk::serde_json::Value,
+) -> Result<(Gas, Page<near_sdk::serde_json::Value>), Box<dyn std::error::Error>> {
+    let outcome = caller
+        .call(contract.id(), "get_messages")
+        .args_json(args)
+        .transact()
+        .await?;
+    let gas = outcome.total_gas_burnt;
+    Ok((gas, outcome.json()?))
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_basic_updates_deep_page_gas(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+
+    for batch in 0..3 {
+        let mut transaction = base_contract.alice.batch(base_contract.contract.id());
+        for i in 0..20 {
+            transaction = transaction.call(
+                Function::new("add_message")
+                    .args_json(json!({"text": format!("message {}", batch * 20 + i)}))
+                    .deposit(ONE_TENTH_NEAR)
+                    .gas(Gas::from_tgas(10)),
+            );
+        }
+        assert!(transaction.transact().await?.is_success());
+    }
+
+    let migrated_contract = deploy_update(&base_contract).await;
+    let migrate_outcome = base_contract
+        .guest_book
+        .call(migrated_contract.id(), "migrate")
+        .args_json(json!({"batch_size": "100"}))
+        .max_gas()
+        .transact()
+        .await?;
+    assert!(migrate_outcome.is_success());
+
+    let total: U64 = migrated_contract
+        .view("total_messages")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(total, U64(62));
+
+    // a deep page costs as much as the first one, the messages before it are never read
+    let (first_gas, first_page) =
+        get_messages_gas(&base_contract.alice, &migrated_contract, json!({})).await?;
+    let (deep_gas, deep_page) = get_messages_gas(
+        &base_contract.alice,
+        &migrated_contract,
+        json!({"from_index": "50"}),
+    )
+    .await?;
+    assert_eq!(first_page.items.len(), 10);
+    assert_eq!(deep_page.items.len(), 10);
+    assert_eq!(deep_page.items[0]["text"], "message 48");
+    assert!(deep_gas.as_gas() < first_gas.as_gas() * 11 / 10);
+
+    // following the cursors reads every message once
+    let mut texts = vec![];
+    let mut cursor = None;
+    loop {
+        let page: Page<near_sdk::serde_json::Value> = migrated_contract
+            .view("get_messages")
+            .args_json(json!({"limit": "25", "cursor": cursor}))
+            .await?
+            .json()?;
+        texts.extend(
+            page.items
+                .into_iter()
+                .map(|message| message["text"].clone()),
+        );
+        cursor = page.next_cursor;
+        if cursor.is_none() {
+            break;
+        }
+    }
+    assert_eq!(texts.len(), 62);
+    assert_eq!(texts[61], "message 59");
+
+    let too_large_page = migrated_contract
+        .view("get_messages")
+        .args_json(json!({"limit": "101"}))
+        .await;
+    assert!(too_large_page.is_err());
+    Ok(())
+}
+
+// Texts of the messages `get_messages` returns for `args`, following every cursor
+async fn message_texts(
+    contract: &Contract,
+    args: near_sdk::serde_json::Value,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut texts = vec![];
+    let mut args = args;
+    loop {
+        let page: Page<near_sdk::serde_json::Value> = contract
+            .view("get_messages")
+            .args_json(args.clone())
+            .await?
+            .json()?;
+        texts.extend(
+            page.items
+                .iter()
+                .map(|message| message["text"].as_str().unwrap().to_string()),
+        );
+        match page.next_cursor {
+            Some(cursor) => args["cursor"] = json!(cursor),
+            None => return Ok(texts),
+        }
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_basic_updates_filtered_queries(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+    let guest_book = base_contract.guest_book.id();
+    let alice = base_contract.alice.id();
+
+    // the migration indexes the messages written by the base contract
+    let migrated_contract = deploy_update(&base_contract).await;
+    let migrate_outcome = base_contract
+        .guest_book
+        .call(migrated_contract.id(), "migrate")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    assert!(migrate_outcome.is_success());
+
+    // and the new messages are indexed as they are added
+    for (sender, text, payment) in [
+        (&base_contract.guest_book, "new", ONE_TENTH_NEAR),
+        (&base_contract.alice, "newest", NINE_HUNDREDTH_NEAR),
+    ] {
+        let add_message_outcome = sender
+            .call(migrated_contract.id(), "add_message")
+            .args_json(json!({"text": text}))
+            .deposit(payment)
+            .transact()
+            .await?;
+        assert!(add_message_outcome.is_success());
+    }
+
+    let cases = [
+        (json!({}), vec!["hello", "bye", "new", "newest"]),
+        (
+            json!({"order": "newest_first"}),
+            vec!["newest", "new", "bye", "hello"],
+        ),
+        (json!({"filter": {"sender": alice}}), vec!["bye", "newest"]),
+        (
+            json!({"order": "newest_first", "filter": {"sender": alice}}),
+            vec!["newest", "bye"],
+        ),
+        (json!({"filter": {"premium": true}}), vec!["bye", "new"]),
+        (
+            json!({"filter": {"premium": false}}),
+            vec!["hello", "newest"],
+        ),
+        (
+            json!({"filter": {"sender": guest_book, "premium": true}}),
+            vec!["new"],
+        ),
+        (
+            json!({"filter": {"min_payment": ONE_TENTH_NEAR}}),
+            vec!["bye", "new"],
+        ),
+        (
+            json!({"filter": {"max_payment": NINE_HUNDREDTH_NEAR}}),
+            vec!["hello", "newest"],
+        ),
+        (json!({"filter": {"sender": "carol.test.near"}}), vec![]),
+    ];
+    for (args, expected) in cases {
+        assert_eq!(
+            message_texts(&migrated_contract, args.clone()).await?,
+            expected,
+            "{args}"
+        );
+    }
+
+    // a page of the premium index holds a premium message even when the newest are not
+    let page: Page<near_sdk::serde_json::Value> = migrated_contract
+        .view("get_messages")
+        .args_json(json!({"limit": "1", "order": "newest_first", "filter": {"premium": true}}))
+        .await?
+        .json()?;
+    assert_eq!(page.items[0]["text"], "new");
+    assert!(page.next_cursor.is_some());
+    Ok(())
+}
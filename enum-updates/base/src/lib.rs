@@ -1,7 +1,10 @@
-use near_sdk::near;
+use near_sdk::{near, BorshStorageKey};
 
-use near_sdk::collections::Vector;
-use near_sdk::json_types::{U64, U128};
+use near_sdk::borsh::BorshSerialize;
+
+use migration_kit::{paginate, Page};
+use near_sdk::collections::{LookupMap, Vector};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
 
 use near_sdk::{env, AccountId, NearToken};
 
@@ -10,15 +13,28 @@ mod versioned_msg;
 
 const POINT_ONE: NearToken = NearToken::from_millinear(100);
 
+#[derive(BorshSerialize, BorshStorageKey)]
+#[borsh(crate = "near_sdk::borsh")]
+pub enum StorageKey {
+    Messages,
+    Payments,
+}
+
 #[near(contract_state)]
 pub struct GuestBook {
     messages: Vector<VersionedPostedMessage>,
 }
 
+// Payments of the messages by index, `PostedMessageV1` has no field for them. Kept apart
+// from the state so its layout stays the one of the contracts already deployed
+fn payments() -> LookupMap<u64, NearToken> {
+    LookupMap::new(StorageKey::Payments)
+}
+
 impl Default for GuestBook {
     fn default() -> Self {
         Self {
-            messages: Vector::new(b"m"),
+            messages: Vector::new(StorageKey::Messages),
         }
     }
 }
@@ -35,6 +51,7 @@ impl GuestBook {
             premium,
             text,
         });
+        payments().insert(&self.messages.len(), &payment);
         self.messages.push(&message);
     }
 
@@ -42,14 +59,14 @@ impl GuestBook {
         &self,
         from_index: Option<U128>,
         limit: Option<U64>,
-    ) -> Vec<PostedMessageV1> {
-        let from = u128::from(from_index.unwrap_or(U128(0)));
-
-        self.messages
-            .iter()
-            .skip(from as usize)
-            .take(u64::from(limit.unwrap_or(U64::from(10))) as usize)
-            .map(|message| message.into())
-            .collect()
+        cursor: Option<Base64VecU8>,
+    ) -> Page<PostedMessageV1> {
+        paginate(self.messages.len(), from_index, limit, cursor, |idx| {
+            self.messages.get(idx).map(|message| message.into())
+        })
+    }
+
+    pub fn total_messages(&self) -> U64 {
+        U64(self.messages.len())
     }
 }
@@ -0,0 +1,91 @@
+use near_sdk::{near, BorshStorageKey};
+
+use near_sdk::borsh::BorshSerialize;
+
+use migration_kit::{paginate, Page};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+
+use near_sdk::{env, AccountId, NearToken};
+
+const POINT_ONE: NearToken = NearToken::from_millinear(100);
+
+#[derive(BorshSerialize, BorshStorageKey)]
+#[borsh(crate = "near_sdk::borsh")]
+pub enum StorageKey {
+    Messages,
+}
+
+#[near(serializers=[borsh, json])]
+pub struct PostedMessage {
+    pub payment: NearToken,
+    pub premium: bool,
+    pub sender: AccountId,
+    pub text: String,
+}
+
+#[near(serializers=[borsh])]
+pub struct GuestBookV1 {
+    messages: Vector<PostedMessage>,
+}
+
+// The contract state itself is versioned, updates add a variant instead of migrating it
+#[near(contract_state)]
+pub enum VersionedGuestBook {
+    V1(GuestBookV1),
+}
+
+impl Default for VersionedGuestBook {
+    fn default() -> Self {
+        VersionedGuestBook::V1(GuestBookV1 {
+            messages: Vector::new(StorageKey::Messages),
+        })
+    }
+}
+
+impl VersionedGuestBook {
+    fn latest(&self) -> &GuestBookV1 {
+        match self {
+            VersionedGuestBook::V1(guest_book) => guest_book,
+        }
+    }
+
+    fn latest_mut(&mut self) -> &mut GuestBookV1 {
+        match self {
+            VersionedGuestBook::V1(guest_book) => guest_book,
+        }
+    }
+}
+
+#[near]
+impl VersionedGuestBook {
+    #[payable]
+    pub fn add_message(&mut self, text: String) {
+        let payment = env::attached_deposit();
+        let sender = env::predecessor_account_id();
+        let premium = payment >= POINT_ONE;
+        let message = PostedMessage {
+            payment,
+            premium,
+            sender,
+            text,
+        };
+        self.latest_mut().messages.push(&message);
+    }
+
+    pub fn get_messages(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> Page<PostedMessage> {
+        let messages = &self.latest().messages;
+        paginate(messages.len(), from_index, limit, cursor, |idx| {
+            messages.get(idx)
+        })
+    }
+
+    pub fn total_messages(&self) -> U64 {
+        U64(self.latest().messages.len())
+    }
+}
@@ -0,0 +1,134 @@
+use near_sdk::{near, BorshStorageKey};
+
+use near_sdk::borsh::BorshSerialize;
+
+use migration_kit::{paginate, Page};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+
+use near_sdk::{env, AccountId, NearToken};
+
+const POINT_ONE: NearToken = NearToken::from_millinear(100);
+
+#[derive(BorshSerialize, BorshStorageKey)]
+#[borsh(crate = "near_sdk::borsh")]
+pub enum StorageKey {
+    Messages,
+}
+
+#[near(serializers=[borsh, json])]
+pub struct PostedMessage {
+    pub payment: NearToken,
+    pub premium: bool,
+    pub sender: AccountId,
+    pub text: String,
+}
+
+#[near(serializers=[borsh])]
+pub struct GuestBookV1 {
+    messages: Vector<PostedMessage>,
+}
+
+#[near(serializers=[borsh])]
+pub struct GuestBookV2 {
+    messages: Vector<PostedMessage>,
+    // minimum payment for a message to be premium
+    premium_threshold: NearToken,
+}
+
+impl From<GuestBookV1> for GuestBookV2 {
+    fn from(GuestBookV1 { messages }: GuestBookV1) -> Self {
+        Self {
+            messages,
+            premium_threshold: POINT_ONE,
+        }
+    }
+}
+
+// Any known version is loaded as is, the first call writing the state stores it as the latest
+#[near(contract_state)]
+pub enum VersionedGuestBook {
+    V1(GuestBookV1),
+    V2(GuestBookV2),
+}
+
+impl Default for VersionedGuestBook {
+    fn default() -> Self {
+        VersionedGuestBook::V2(GuestBookV2 {
+            messages: Vector::new(StorageKey::Messages),
+            premium_threshold: POINT_ONE,
+        })
+    }
+}
+
+impl VersionedGuestBook {
+    fn messages(&self) -> &Vector<PostedMessage> {
+        match self {
+            VersionedGuestBook::V1(guest_book) => &guest_book.messages,
+            VersionedGuestBook::V2(guest_book) => &guest_book.messages,
+        }
+    }
+
+    fn premium_threshold(&self) -> NearToken {
+        match self {
+            VersionedGuestBook::V1(_) => POINT_ONE,
+            VersionedGuestBook::V2(guest_book) => guest_book.premium_threshold,
+        }
+    }
+
+    // Upgrades the state in memory, it is written back as the latest version
+    fn latest_mut(&mut self) -> &mut GuestBookV2 {
+        if let VersionedGuestBook::V1(guest_book) = self {
+            let messages =
+                std::mem::replace(&mut guest_book.messages, Vector::new(StorageKey::Messages));
+            *self = VersionedGuestBook::V2(GuestBookV1 { messages }.into());
+        }
+
+        match self {
+            VersionedGuestBook::V2(guest_book) => guest_book,
+            VersionedGuestBook::V1(_) => unreachable!(),
+        }
+    }
+}
+
+#[near]
+impl VersionedGuestBook {
+    #[payable]
+    pub fn add_message(&mut self, text: String) {
+        let payment = env::attached_deposit();
+        let sender = env::predecessor_account_id();
+        let premium = payment >= self.premium_threshold();
+        let message = PostedMessage {
+            payment,
+            premium,
+            sender,
+            text,
+        };
+        self.latest_mut().messages.push(&message);
+    }
+
+    pub fn get_messages(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> Page<PostedMessage> {
+        let messages = self.messages();
+        paginate(messages.len(), from_index, limit, cursor, |idx| {
+            messages.get(idx)
+        })
+    }
+
+    pub fn total_messages(&self) -> U64 {
+        U64(self.messages().len())
+    }
+
+    pub fn get_premium_threshold(&self) -> NearToken {
+        self.premium_threshold()
+    }
+
+    #[private]
+    pub fn set_premium_threshold(&mut self, premium_threshold: NearToken) {
+        self.latest_mut().premium_threshold = premium_threshold;
+    }
+}
@@ -0,0 +1,154 @@
+use migration_kit::Page;
+use near_workspaces::types::NearToken;
+use serde_json::json;
+use std::fs;
+
+use near_sdk::AccountId;
+
+const FIVE_NEAR: NearToken = NearToken::from_near(5);
+const ONE_TENTH_NEAR: NearToken = NearToken::from_millinear(100);
+const FIVE_HUNDREDTH_NEAR: NearToken = NearToken::from_millinear(50);
+const ONE_HUNDREDTH_NEAR: NearToken = NearToken::from_millinear(10);
+
+#[tokio::test]
+async fn test_enum_updates_versioned_state() -> Result<(), Box<dyn std::error::Error>> {
+    let sandbox = near_workspaces::sandbox().await.unwrap();
+
+    fs::create_dir_all("../../target/near/enums_state_base").unwrap();
+    let base_contract_wasm = near_workspaces::compile_project("../state-base")
+        .await
+        .unwrap();
+
+    let root = sandbox.root_account().unwrap();
+    let guest_book_account = root
+        .create_subaccount("gbook")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    let alice = root
+        .create_subaccount("alice")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    let contract = guest_book_account
+        .deploy(&base_contract_wasm)
+        .await
+        .unwrap()
+        .into_result()
+        .unwrap();
+
+    let add_message_outcome = alice
+        .call(contract.id(), "add_message")
+        .args_json(json!({"text": "hello"}))
+        .deposit(ONE_TENTH_NEAR)
+        .transact()
+        .await?;
+    assert!(add_message_outcome.is_success());
+
+    // the state is stored as its first variant
+    let state = contract.view_state().await?;
+    assert_eq!(state[b"STATE".as_slice()][0], 0);
+
+    fs::create_dir_all("../../target/near/enums_state_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+
+    // no migration is called, the update reads the V1 state as is
+    let updated_contract = guest_book_account
+        .deploy(&updated_contract_wasm)
+        .await
+        .unwrap()
+        .into_result()
+        .unwrap();
+
+    #[derive(near_sdk::serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(crate = "near_sdk::serde")]
+    pub struct PostedMessage {
+        pub payment: NearToken,
+        pub premium: bool,
+        pub sender: AccountId,
+        pub text: String,
+    }
+    let messages_vec: Vec<PostedMessage> = updated_contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+    assert_eq!(
+        messages_vec,
+        vec![PostedMessage {
+            payment: ONE_TENTH_NEAR,
+            premium: true,
+            sender: alice.id().clone(),
+            text: "hello".to_string(),
+        }]
+    );
+
+    let premium_threshold: NearToken = updated_contract
+        .view("get_premium_threshold")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(premium_threshold, ONE_TENTH_NEAR);
+
+    // views upgrade the state in memory only
+    let state = updated_contract.view_state().await?;
+    assert_eq!(state[b"STATE".as_slice()][0], 0);
+
+    // the first call writing the state stores it as V2
+    let set_threshold_outcome = updated_contract
+        .call("set_premium_threshold")
+        .args_json(json!({"premium_threshold": ONE_HUNDREDTH_NEAR}))
+        .transact()
+        .await?;
+    assert!(set_threshold_outcome.is_success());
+
+    let state = updated_contract.view_state().await?;
+    assert_eq!(state[b"STATE".as_slice()][0], 1);
+
+    let add_message_outcome = alice
+        .call(updated_contract.id(), "add_message")
+        .args_json(json!({"text": "bye"}))
+        .deposit(FIVE_HUNDREDTH_NEAR)
+        .transact()
+        .await?;
+    assert!(add_message_outcome.is_success());
+
+    let messages_vec: Vec<PostedMessage> = updated_contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+    assert_eq!(
+        messages_vec,
+        vec![
+            PostedMessage {
+                payment: ONE_TENTH_NEAR,
+                premium: true,
+                sender: alice.id().clone(),
+                text: "hello".to_string(),
+            },
+            PostedMessage {
+                payment: FIVE_HUNDREDTH_NEAR,
+                premium: true,
+                sender: alice.id().clone(),
+                text: "bye".to_string(),
+            },
+        ]
+    );
+
+    // only the contract sets the threshold
+    let set_threshold_outcome = alice
+        .call(updated_contract.id(), "set_premium_threshold")
+        .args_json(json!({"premium_threshold": FIVE_NEAR}))
+        .transact()
+        .await?;
+    assert!(set_threshold_outcome.is_failure());
+    Ok(())
+}
@@ -1,24 +1,48 @@
-use near_sdk::near;
+use near_sdk::{near, BorshStorageKey};
 
-use near_sdk::collections::Vector;
-use near_sdk::json_types::{U64, U128};
+use near_sdk::borsh::BorshSerialize;
+
+use near_sdk::collections::{LookupMap, Vector};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
 
 use near_sdk::{env, AccountId, NearToken};
 
-use versioned_msg::{PostedMessageV2, VersionedPostedMessage};
+use migration_kit::{paginate, vector_prefix, Page, VariantScan};
+
+use versioned_msg::{PostedMessageV2, VersionCounts, VersionedPostedMessage};
 mod versioned_msg;
 
 const POINT_ONE: NearToken = NearToken::from_millinear(100);
 
+// Number of messages upgraded per call when no limit is given
+const DEFAULT_UPGRADE_LIMIT: u64 = 100;
+
+// Number of messages still stored as V1, kept under its own key since the state layout
+// is the base's one. Until it is first written every stored message is a V1 message
+const V1_LEFT_KEY: &[u8] = b"V1_LEFT";
+
+#[derive(BorshSerialize, BorshStorageKey)]
+#[borsh(crate = "near_sdk::borsh")]
+pub enum StorageKey {
+    Messages,
+    Payments,
+}
+
 #[near(contract_state)]
 pub struct GuestBook {
     messages: Vector<VersionedPostedMessage>,
 }
 
+// Payments of the V1 messages by index, removed once they are rewritten as V2. Kept apart
+// from the state like in the base, whose state layout this contract reads
+fn payments() -> LookupMap<u64, NearToken> {
+    LookupMap::new(StorageKey::Payments)
+}
+
 impl Default for GuestBook {
     fn default() -> Self {
         Self {
-            messages: Vector::new(b"m"),
+            messages: Vector::new(StorageKey::Messages),
         }
     }
 }
@@ -36,6 +60,7 @@ impl GuestBook {
             premium,
             text,
         });
+        self.track_v1_left();
         self.messages.push(&message);
     }
 
@@ -43,14 +68,122 @@ impl GuestBook {
         &self,
         from_index: Option<U128>,
         limit: Option<U64>,
-    ) -> Vec<PostedMessageV2> {
-        let from = u128::from(from_index.unwrap_or(U128(0)));
-
-        self.messages
-            .iter()
-            .skip(from as usize)
-            .take(u64::from(limit.unwrap_or(U64::from(10))) as usize)
-            .map(|message| message.into())
-            .collect()
+        cursor: Option<Base64VecU8>,
+    ) -> Page<PostedMessageV2> {
+        paginate(self.messages.len(), from_index, limit, cursor, |idx| {
+            self.messages
+                .get(idx)
+                .map(|message| self.to_latest(idx, message))
+        })
+    }
+
+    pub fn total_messages(&self) -> U64 {
+        U64(self.messages.len())
+    }
+
+    /// Replaces the text of a message, which is rewritten as the latest version
+    pub fn edit_message(&mut self, index: U64, text: String) {
+        let message = self.messages.get(index.0).expect("Message not found");
+        let mut message = self.to_latest(index.0, message);
+        assert_eq!(
+            message.sender,
+            env::predecessor_account_id(),
+            "Only the sender can edit a message"
+        );
+
+        message.text = text;
+        self.write_latest(index.0, message);
+    }
+
+    /// Rewrites the messages stored on an older version as the latest one, going through
+    /// at most `limit` messages from `from_index`. Returns the number of messages rewritten.
+    /// Only the contract account can call it, since the contract pays for the rewritten messages
+    #[private]
+    pub fn upgrade_messages(&mut self, from_index: Option<U64>, limit: Option<U64>) -> U64 {
+        let from = from_index.unwrap_or(U64(0)).0;
+        let to = self
+            .messages
+            .len()
+            .min(from.saturating_add(limit.unwrap_or(U64(DEFAULT_UPGRADE_LIMIT)).0));
+
+        let mut upgraded = 0;
+        for idx in from..to {
+            let message = self.messages.get(idx).expect("failed to get message");
+            if !message.is_latest() {
+                let message = self.to_latest(idx, message);
+                self.write_latest(idx, message);
+                upgraded += 1;
+            }
+        }
+        U64(upgraded)
+    }
+
+    /// How many messages are stored on each version, an old version can be
+    /// dropped once it has no message left
+    pub fn get_version_counts(&self) -> VersionCounts {
+        let v1 = self.v1_left();
+
+        VersionCounts {
+            v1: U64(v1),
+            v2: U64(self.messages.len() - v1),
+        }
+    }
+
+    /// Checks the raw stored messages for the variant at position `discriminant`,
+    /// run it before deploying code whose `VersionedPostedMessage` no longer has it.
+    /// Follow `next_cursor` until the last page, which tells whether the variant is retirable,
+    /// the counts of the previous pages are kept in the state until then.
+    /// Deploying such code does not check the scan, it is up to the deployer to run it
+    #[private]
+    pub fn scan_variant(
+        &mut self,
+        discriminant: u8,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> VariantScan {
+        migration_kit::scan_variant(
+            vector_prefix(&self.messages),
+            self.messages.len(),
+            discriminant,
+            from_index,
+            limit,
+            cursor,
+        )
+        .unwrap_or_else(|err| err.panic())
+    }
+}
+
+impl GuestBook {
+    // Converts a stored message to the latest version, V1 messages take their payment
+    // from the side collection
+    fn to_latest(&self, index: u64, message: VersionedPostedMessage) -> PostedMessageV2 {
+        message.into_latest(|| payments().get(&index).unwrap_or(NearToken::from_near(0)))
+    }
+
+    // Stores a message as the latest version, which holds its payment itself
+    fn write_latest(&mut self, index: u64, message: PostedMessageV2) {
+        self.track_v1_left();
+        let previous = self
+            .messages
+            .replace(index, &VersionedPostedMessage::V2(message));
+        payments().remove(&index);
+
+        if !previous.is_latest() {
+            migration_kit::write_key(V1_LEFT_KEY, &(self.v1_left() - 1));
+        }
+    }
+
+    fn v1_left(&self) -> u64 {
+        migration_kit::read_key(V1_LEFT_KEY)
+            .unwrap_or_else(|err| err.panic())
+            .unwrap_or_else(|| self.messages.len())
+    }
+
+    // Writes the counter before the first change to the messages, while they are all V1
+    fn track_v1_left(&self) {
+        if !env::storage_has_key(V1_LEFT_KEY) {
+            migration_kit::write_key(V1_LEFT_KEY, &self.messages.len());
+        }
     }
 }
@@ -21,16 +21,29 @@ pub enum VersionedPostedMessage {
     V2(PostedMessageV2),
 }
 
-impl From<VersionedPostedMessage> for PostedMessageV2 {
-    fn from(message: VersionedPostedMessage) -> Self {
-        match message {
+impl VersionedPostedMessage {
+    /// Converts the message to the latest version, `v1_payment` gives the payment of a V1 message
+    pub fn into_latest(self, v1_payment: impl FnOnce() -> NearToken) -> PostedMessageV2 {
+        match self {
             VersionedPostedMessage::V2(posted) => posted,
             VersionedPostedMessage::V1(posted) => PostedMessageV2 {
-                payment: NearToken::from_near(0),
+                payment: v1_payment(),
                 premium: posted.premium,
                 sender: posted.sender,
                 text: posted.text,
             },
         }
     }
+
+    /// Whether the message is stored as the latest version
+    pub fn is_latest(&self) -> bool {
+        matches!(self, VersionedPostedMessage::V2(_))
+    }
+}
+
+/// Number of stored messages on each version
+#[near(serializers=[json])]
+pub struct VersionCounts {
+    pub v1: U64,
+    pub v2: U64,
 }
@@ -1,13 +1,19 @@
-use near_workspaces::types::NearToken;
+use migration_kit::Page;
+use near_workspaces::operations::Function;
+use near_workspaces::types::{Gas, NearToken};
 use serde_json::json;
 use std::fs;
 
+use near_sdk::json_types::U64;
 use near_sdk::AccountId;
 
 const FIVE_NEAR: NearToken = NearToken::from_near(5);
 const ONE_NEAR: NearToken = NearToken::from_near(1);
 const NINE_HUNDREDTH_NEAR: NearToken = NearToken::from_millinear(90);
 
+// Prefix of the payments recorded apart, the borsh encoding of `StorageKey::Payments`
+const PAYMENTS_PREFIX: &[u8] = &[1];
+
 #[tokio::test]
 async fn test_enum_updates_migration() -> Result<(), Box<dyn std::error::Error>> {
     let sandbox = near_workspaces::sandbox().await.unwrap();
@@ -16,7 +22,13 @@ async fn test_enum_updates_migration() -> Result<(), Box<dyn std::error::Error>>
     let base_contract_wasm = near_workspaces::compile_project("../base").await.unwrap();
 
     let root = sandbox.root_account().unwrap();
-    let guest_book_account = root.create_subaccount("gbook").initial_balance(FIVE_NEAR).transact().await.unwrap().unwrap();
+    let guest_book_account = root
+        .create_subaccount("gbook")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
 
     let contract = guest_book_account
         .deploy(&base_contract_wasm)
@@ -25,7 +37,13 @@ async fn test_enum_updates_migration() -> Result<(), Box<dyn std::error::Error>>
         .into_result()
         .unwrap();
 
-    let alice = root.create_subaccount("alice").initial_balance(FIVE_NEAR).transact().await.unwrap().unwrap();
+    let alice = root
+        .create_subaccount("alice")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
 
     let guest_book_message_outcome = guest_book_account
         .call(contract.id(), "add_message")
@@ -70,19 +88,20 @@ async fn test_enum_updates_migration() -> Result<(), Box<dyn std::error::Error>>
         .view("get_messages")
         .args_json(json!({}))
         .await?
-        .json()?;
+        .json::<Page<_>>()?
+        .items;
 
     assert_eq!(
         messages_vec,
         vec![
             PostedMessageV2 {
-                payment: NearToken::from_near(0),
+                payment: NINE_HUNDREDTH_NEAR,
                 premium: false,
                 sender: guest_book_account.id().clone(),
                 text: "hello".to_string(),
             },
             PostedMessageV2 {
-                payment: NearToken::from_near(0),
+                payment: ONE_NEAR,
                 premium: true,
                 sender: alice.id().clone(),
                 text: "bye".to_string(),
@@ -103,19 +122,20 @@ async fn test_enum_updates_migration() -> Result<(), Box<dyn std::error::Error>>
         .view("get_messages")
         .args_json(json!({}))
         .await?
-        .json()?;
+        .json::<Page<_>>()?
+        .items;
 
     assert_eq!(
         messages_vec,
         vec![
             PostedMessageV2 {
-                payment: NearToken::from_near(0),
+                payment: NINE_HUNDREDTH_NEAR,
                 premium: false,
                 sender: guest_book_account.id().clone(),
                 text: "hello".to_string(),
             },
             PostedMessageV2 {
-                payment: NearToken::from_near(0),
+                payment: ONE_NEAR,
                 premium: true,
                 sender: alice.id().clone(),
                 text: "bye".to_string(),
@@ -130,3 +150,224 @@ async fn test_enum_updates_migration() -> Result<(), Box<dyn std::error::Error>>
     );
     Ok(())
 }
+
+#[tokio::test]
+async fn test_enum_updates_upgrade_on_write() -> Result<(), Box<dyn std::error::Error>> {
+    let sandbox = near_workspaces::sandbox().await.unwrap();
+
+    fs::create_dir_all("../../target/near/enums_base").unwrap();
+    let base_contract_wasm = near_workspaces::compile_project("../base").await.unwrap();
+
+    let root = sandbox.root_account().unwrap();
+    let guest_book_account = root
+        .create_subaccount("gbook")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    let alice = root
+        .create_subaccount("alice")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    let contract = guest_book_account
+        .deploy(&base_contract_wasm)
+        .await
+        .unwrap()
+        .into_result()
+        .unwrap();
+
+    for (text, payment) in [("first", 0), ("second", 90), ("third", 0)] {
+        let add_message_outcome = alice
+            .call(contract.id(), "add_message")
+            .args_json(json!({"text": text}))
+            .deposit(NearToken::from_millinear(payment))
+            .transact()
+            .await?;
+        assert!(add_message_outcome.is_success());
+    }
+
+    fs::create_dir_all("../../target/near/enums_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+
+    let updated_contract = guest_book_account
+        .deploy(&updated_contract_wasm)
+        .await
+        .unwrap()
+        .into_result()
+        .unwrap();
+
+    let version_counts: serde_json::Value = updated_contract
+        .view("get_version_counts")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(version_counts, json!({"v1": "3", "v2": "0"}));
+
+    // reading the messages does not rewrite them
+    let messages_vec: Vec<serde_json::Value> = updated_contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+    assert_eq!(messages_vec.len(), 3);
+
+    // editing a message rewrites it as the latest version
+    let edit_outcome = alice
+        .call(updated_contract.id(), "edit_message")
+        .args_json(json!({"index": "1", "text": "edited"}))
+        .transact()
+        .await?;
+    assert!(edit_outcome.is_success());
+
+    let version_counts: serde_json::Value = updated_contract
+        .view("get_version_counts")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(version_counts, json!({"v1": "2", "v2": "1"}));
+
+    // only the sender can edit
+    let edit_outcome = guest_book_account
+        .call(updated_contract.id(), "edit_message")
+        .args_json(json!({"index": "0", "text": "not mine"}))
+        .transact()
+        .await?;
+    assert!(edit_outcome.is_failure());
+
+    // the contract pays for the rewritten messages, so only its account can upgrade them
+    let upgrade_outcome = alice
+        .call(updated_contract.id(), "upgrade_messages")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    assert!(upgrade_outcome.is_failure());
+
+    // explicit upgrades skip the messages already on the latest version
+    let upgraded: near_sdk::json_types::U64 = guest_book_account
+        .call(updated_contract.id(), "upgrade_messages")
+        .args_json(json!({"from_index": "1", "limit": "10"}))
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(upgraded.0, 1);
+
+    let upgraded: near_sdk::json_types::U64 = guest_book_account
+        .call(updated_contract.id(), "upgrade_messages")
+        .args_json(json!({}))
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(upgraded.0, 1);
+
+    let version_counts: serde_json::Value = updated_contract
+        .view("get_version_counts")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(version_counts, json!({"v1": "0", "v2": "3"}));
+
+    let messages_vec: Vec<serde_json::Value> = updated_contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+    let texts: Vec<&str> = messages_vec
+        .iter()
+        .map(|message| message["text"].as_str().unwrap())
+        .collect();
+    assert_eq!(texts, vec!["first", "edited", "third"]);
+
+    // the rewritten messages keep their payment, no longer recorded apart
+    assert_eq!(
+        messages_vec[1]["payment"],
+        NINE_HUNDREDTH_NEAR.as_yoctonear().to_string()
+    );
+    let state = updated_contract.view_state().await?;
+    assert!(state.keys().all(|key| !key.starts_with(PAYMENTS_PREFIX)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_enum_updates_deep_page_gas() -> Result<(), Box<dyn std::error::Error>> {
+    let sandbox = near_workspaces::sandbox().await.unwrap();
+
+    fs::create_dir_all("../../target/near/enums_base").unwrap();
+    let base_contract_wasm = near_workspaces::compile_project("../base").await.unwrap();
+
+    let root = sandbox.root_account().unwrap();
+    let guest_book_account = root
+        .create_subaccount("gbook")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    let alice = root
+        .create_subaccount("alice")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    let contract = guest_book_account
+        .deploy(&base_contract_wasm)
+        .await
+        .unwrap()
+        .into_result()
+        .unwrap();
+
+    for batch in 0..3 {
+        let mut transaction = alice.batch(contract.id());
+        for i in 0..20 {
+            transaction = transaction.call(
+                Function::new("add_message")
+                    .args_json(json!({"text": format!("message {}", batch * 20 + i)}))
+                    .deposit(NINE_HUNDREDTH_NEAR)
+                    .gas(Gas::from_tgas(10)),
+            );
+        }
+        assert!(transaction.transact().await?.is_success());
+    }
+
+    fs::create_dir_all("../../target/near/enums_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+    let updated_contract = guest_book_account
+        .deploy(&updated_contract_wasm)
+        .await
+        .unwrap()
+        .into_result()
+        .unwrap();
+
+    let total: U64 = updated_contract
+        .view("total_messages")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(total, U64(60));
+
+    // views do not report their gas, so the pages are read in transactions
+    let mut gas = vec![];
+    for args in [json!({}), json!({"from_index": "50"})] {
+        let outcome = alice
+            .call(updated_contract.id(), "get_messages")
+            .args_json(args)
+            .transact()
+            .await?;
+        gas.push(outcome.total_gas_burnt.as_gas());
+
+        let page: Page<serde_json::Value> = outcome.json()?;
+        assert_eq!(page.items.len(), 10);
+        assert_eq!(page.items[0]["payment"], json!(NINE_HUNDREDTH_NEAR));
+    }
+    // the V1 messages before the deep page are never read nor upgraded
+    assert!(gas[1] < gas[0] * 11 / 10);
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use near_sdk::{near, BorshStorageKey};
+
+use near_sdk::borsh::BorshSerialize;
+
+use migration_kit::{paginate, Page};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+
+use near_sdk::{env, NearToken};
+
+use versioned_msg::{PostedMessageV2, VersionedPostedMessage};
+mod versioned_msg;
+
+const POINT_ONE: NearToken = NearToken::from_millinear(100);
+
+#[derive(BorshSerialize, BorshStorageKey)]
+#[borsh(crate = "near_sdk::borsh")]
+pub enum StorageKey {
+    Messages,
+}
+
+#[near(contract_state)]
+pub struct GuestBook {
+    messages: Vector<VersionedPostedMessage>,
+}
+
+impl Default for GuestBook {
+    fn default() -> Self {
+        Self {
+            messages: Vector::new(StorageKey::Messages),
+        }
+    }
+}
+
+#[near]
+impl GuestBook {
+    #[payable]
+    pub fn add_message(&mut self, text: String) {
+        let payment = env::attached_deposit();
+        let sender = env::predecessor_account_id();
+        let premium = payment >= POINT_ONE;
+        let message = VersionedPostedMessage::V2(PostedMessageV2 {
+            payment,
+            sender,
+            premium,
+            text,
+        });
+        self.messages.push(&message);
+    }
+
+    pub fn get_messages(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> Page<PostedMessageV2> {
+        paginate(self.messages.len(), from_index, limit, cursor, |idx| {
+            self.messages.get(idx).map(|message| message.into())
+        })
+    }
+
+    pub fn total_messages(&self) -> U64 {
+        U64(self.messages.len())
+    }
+
+    /// Replaces the text of a message
+    pub fn edit_message(&mut self, index: U64, text: String) {
+        let mut message: PostedMessageV2 = self
+            .messages
+            .get(index.0)
+            .expect("Message not found")
+            .into();
+        assert_eq!(
+            message.sender,
+            env::predecessor_account_id(),
+            "Only the sender can edit a message"
+        );
+
+        message.text = text;
+        self.messages
+            .replace(index.0, &VersionedPostedMessage::V2(message));
+    }
+}
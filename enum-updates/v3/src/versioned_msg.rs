@@ -0,0 +1,29 @@
+use crate::*;
+
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::AccountId;
+
+#[near(serializers=[borsh, json])]
+pub struct PostedMessageV2 {
+    pub payment: NearToken,
+    pub premium: bool,
+    pub sender: AccountId,
+    pub text: String,
+}
+
+// `V1` was removed once no stored message used it. Borsh writes the position of the
+// variant, so `V2` keeps its discriminant explicitly and 0 must never be given to a new variant
+#[derive(BorshSerialize, BorshDeserialize)]
+#[borsh(crate = "near_sdk::borsh", use_discriminant = true)]
+#[repr(u8)]
+pub enum VersionedPostedMessage {
+    V2(PostedMessageV2) = 1,
+}
+
+impl From<VersionedPostedMessage> for PostedMessageV2 {
+    fn from(message: VersionedPostedMessage) -> Self {
+        match message {
+            VersionedPostedMessage::V2(posted) => posted,
+        }
+    }
+}
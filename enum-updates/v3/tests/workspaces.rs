@@ -0,0 +1,157 @@
+use migration_kit::Page;
+use near_workspaces::types::NearToken;
+use near_workspaces::{Account, Contract};
+use serde_json::json;
+use std::fs;
+
+const FIVE_NEAR: NearToken = NearToken::from_near(5);
+const ONE_NEAR: NearToken = NearToken::from_near(1);
+
+async fn deploy(account: &Account, project: &str, target: &str) -> Contract {
+    fs::create_dir_all(format!("../../target/near/{target}")).unwrap();
+    let wasm = near_workspaces::compile_project(project).await.unwrap();
+
+    account.deploy(&wasm).await.unwrap().into_result().unwrap()
+}
+
+async fn texts(
+    contract: &Contract,
+    from_index: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let messages_vec: Vec<serde_json::Value> = contract
+        .view("get_messages")
+        .args_json(json!({"from_index": from_index}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    Ok(messages_vec
+        .iter()
+        .map(|message| message["text"].as_str().unwrap().to_string())
+        .collect())
+}
+
+#[tokio::test]
+async fn test_enum_updates_retire_v1() -> Result<(), Box<dyn std::error::Error>> {
+    let sandbox = near_workspaces::sandbox().await.unwrap();
+
+    let root = sandbox.root_account().unwrap();
+    let guest_book_account = root
+        .create_subaccount("gbook")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    let alice = root
+        .create_subaccount("alice")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    // two V1 messages, then a V2 one once the update is deployed
+    let contract = deploy(&guest_book_account, "../base", "enums_base").await;
+    for text in ["first", "second"] {
+        let add_message_outcome = alice
+            .call(contract.id(), "add_message")
+            .args_json(json!({"text": text}))
+            .transact()
+            .await?;
+        assert!(add_message_outcome.is_success());
+    }
+
+    let contract = deploy(&guest_book_account, "../update", "enums_update").await;
+    let add_message_outcome = alice
+        .call(contract.id(), "add_message")
+        .args_json(json!({"text": "third"}))
+        .deposit(ONE_NEAR)
+        .transact()
+        .await?;
+    assert!(add_message_outcome.is_success());
+
+    // only the first message gets upgraded, the second one straggles as V1
+    let upgrade_outcome = guest_book_account
+        .call(contract.id(), "upgrade_messages")
+        .args_json(json!({"limit": "1"}))
+        .transact()
+        .await?;
+    assert!(upgrade_outcome.is_success());
+
+    // the scan goes page by page, the last page reports the counts of the whole vector
+    let scan: serde_json::Value = guest_book_account
+        .call(contract.id(), "scan_variant")
+        .args_json(json!({"discriminant": 0, "limit": "2"}))
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(scan["remaining"], "1");
+    assert!(scan["next_cursor"].is_string());
+
+    let scan: serde_json::Value = guest_book_account
+        .call(contract.id(), "scan_variant")
+        .args_json(json!({"discriminant": 0, "limit": "2", "cursor": scan["next_cursor"]}))
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(
+        scan,
+        json!({
+            "discriminant": 0,
+            "remaining": "1",
+            "first_index": "1",
+            "retirable": false,
+            "next_cursor": null
+        })
+    );
+
+    // deploying V3 anyway: the straggler can no longer be decoded
+    let contract = deploy(&guest_book_account, "./", "enums_v3").await;
+    assert!(contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await
+        .is_err());
+    assert_eq!(texts(&contract, "2").await?, vec!["third"]);
+
+    // the guarded path: upgrade the stragglers until the scan allows retiring V1
+    let contract = deploy(&guest_book_account, "../update", "enums_update").await;
+    let upgrade_outcome = guest_book_account
+        .call(contract.id(), "upgrade_messages")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    assert!(upgrade_outcome.is_success());
+
+    let scan: serde_json::Value = guest_book_account
+        .call(contract.id(), "scan_variant")
+        .args_json(json!({"discriminant": 0}))
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(scan["retirable"], true);
+
+    let contract = deploy(&guest_book_account, "./", "enums_v3").await;
+    assert_eq!(
+        texts(&contract, "0").await?,
+        vec!["first", "second", "third"]
+    );
+
+    // V2 keeps its discriminant, messages written by V3 are read by the update and back
+    let add_message_outcome = alice
+        .call(contract.id(), "add_message")
+        .args_json(json!({"text": "fourth"}))
+        .transact()
+        .await?;
+    assert!(add_message_outcome.is_success());
+
+    let contract = deploy(&guest_book_account, "../update", "enums_update").await;
+    let version_counts: serde_json::Value = contract
+        .view("get_version_counts")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(version_counts, json!({"v1": "0", "v2": "4"}));
+    Ok(())
+}
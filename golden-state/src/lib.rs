@@ -0,0 +1,58 @@
+//! Raw contract states written by the historical builds of the examples, kept under `golden/`
+//! so every later build can be checked against them.
+//!
+//! A golden file maps the hex encoded storage keys of a contract to their hex encoded values.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use serde_json::{Map, Value};
+
+/// Storage of a contract, sorted by key
+pub type State = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// Set to rewrite the golden files with the states captured by the tests
+pub const UPDATE_ENV: &str = "GOLDEN_UPDATE";
+
+pub fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("golden")
+        .join(format!("{name}.json"))
+}
+
+pub fn read_golden(name: &str) -> State {
+    let path = golden_path(name);
+    let data = fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("Cannot read {}: {err}", path.display()));
+    let entries: Map<String, Value> = serde_json::from_str(&data)
+        .unwrap_or_else(|err| panic!("Cannot parse {}: {err}", path.display()));
+
+    entries
+        .into_iter()
+        .map(|(key, value)| {
+            let value = value.as_str().expect("golden values are hex strings");
+            (from_hex(&key), from_hex(value))
+        })
+        .collect()
+}
+
+pub fn write_golden(name: &str, state: &State) {
+    let entries: Map<String, Value> = state
+        .iter()
+        .map(|(key, value)| (to_hex(key), Value::String(to_hex(value))))
+        .collect();
+
+    let data = serde_json::to_string_pretty(&entries).unwrap();
+    fs::write(golden_path(name), data + "\n").unwrap();
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&hex[idx..idx + 2], 16).expect("invalid hex"))
+        .collect()
+}
@@ -0,0 +1,404 @@
+use std::fs;
+
+use golden_state::{read_golden, write_golden, State, UPDATE_ENV};
+use near_workspaces::network::Sandbox;
+use near_workspaces::types::{Gas, NearToken};
+use near_workspaces::{Account, Contract, Worker};
+use serde_json::{json, Value};
+
+const FIVE_NEAR: NearToken = NearToken::from_near(5);
+const ONE_TENTH_NEAR: NearToken = NearToken::from_millinear(100);
+const NINE_HUNDREDTH_NEAR: NearToken = NearToken::from_millinear(90);
+
+struct Common {
+    sandbox: Worker<Sandbox>,
+    alice: Account,
+    bob: Account,
+}
+
+async fn setup() -> Common {
+    let sandbox = near_workspaces::sandbox().await.unwrap();
+    let root = sandbox.root_account().unwrap();
+
+    let alice = root
+        .create_subaccount("alice")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    let bob = root
+        .create_subaccount("bob")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+
+    Common {
+        sandbox,
+        alice,
+        bob,
+    }
+}
+
+// `project` is relative to the repo root, `target` is the name of its wasm
+async fn compile(project: &str, target: &str) -> Vec<u8> {
+    fs::create_dir_all(format!("../target/near/{target}")).unwrap();
+    near_workspaces::compile_project(&format!("../{project}"))
+        .await
+        .unwrap()
+}
+
+// Deploys a historical build, writes the fixture messages and checks the raw state it
+// stored against its golden file
+async fn capture(
+    common: &Common,
+    project: &str,
+    target: &str,
+    init: Option<(&str, Value)>,
+    golden: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let contract = common
+        .sandbox
+        .dev_deploy(&compile(project, target).await)
+        .await?;
+    if let Some((method, args)) = init {
+        let init_outcome = contract.call(method).args_json(args).transact().await?;
+        assert!(init_outcome.is_success(), "{init_outcome:?}");
+    }
+
+    for (sender, text, payment) in [
+        (&common.alice, "hello", NINE_HUNDREDTH_NEAR),
+        (&common.bob, "bye", ONE_TENTH_NEAR),
+    ] {
+        let add_message_outcome = sender
+            .call(contract.id(), "add_message")
+            .args_json(json!({"text": text}))
+            .deposit(payment)
+            .transact()
+            .await?;
+        assert!(add_message_outcome.is_success(), "{add_message_outcome:?}");
+    }
+
+    let state: State = contract.view_state().await?.into_iter().collect();
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        write_golden(golden, &state);
+    } else {
+        assert_eq!(
+            state,
+            read_golden(golden),
+            "{project} no longer writes the state of {golden}.json, set {UPDATE_ENV} to update it"
+        );
+    }
+    Ok(())
+}
+
+// Deploys a later build on an account holding the state of a golden file
+async fn replay(
+    common: &Common,
+    golden: &str,
+    project: &str,
+    target: &str,
+) -> Result<Contract, Box<dyn std::error::Error>> {
+    let contract = common
+        .sandbox
+        .dev_deploy(&compile(project, target).await)
+        .await?;
+    for (key, value) in read_golden(golden) {
+        common
+            .sandbox
+            .patch_state(contract.id(), &key, &value)
+            .await?;
+    }
+    Ok(contract)
+}
+
+async fn get_messages(contract: &Contract) -> Result<Value, Box<dyn std::error::Error>> {
+    Ok(contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Value>()?["items"]
+        .take())
+}
+
+// The fixture messages, as returned by the builds that merged payments into messages
+fn messages_with_payments(common: &Common) -> Value {
+    json!([
+        {
+            "payment": NINE_HUNDREDTH_NEAR,
+            "premium": false,
+            "sender": common.alice.id(),
+            "text": "hello",
+        },
+        {
+            "payment": ONE_TENTH_NEAR,
+            "premium": true,
+            "sender": common.bob.id(),
+            "text": "bye",
+        },
+    ])
+}
+
+#[tokio::test]
+async fn test_golden_basic_updates() -> Result<(), Box<dyn std::error::Error>> {
+    let common = setup().await;
+    capture(&common, "basic-updates/base", "base", None, "basic-base").await?;
+
+    let contract = replay(&common, "basic-base", "basic-updates/update", "update").await?;
+    let migrate_outcome = contract
+        .call("migrate")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    assert!(migrate_outcome.is_success(), "{migrate_outcome:?}");
+
+    assert_eq!(
+        get_messages(&contract).await?,
+        messages_with_payments(&common)
+    );
+
+    // the original base stored its vectors under b"m" and b"p"
+    let contract = replay(&common, "basic-original", "basic-updates/update", "update").await?;
+    let migrate_outcome = contract
+        .call("migrate")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    assert!(migrate_outcome.is_success(), "{migrate_outcome:?}");
+
+    assert_eq!(
+        get_messages(&contract).await?,
+        messages_with_payments(&common)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_golden_enum_updates() -> Result<(), Box<dyn std::error::Error>> {
+    let common = setup().await;
+    capture(
+        &common,
+        "enum-updates/base",
+        "enums_base",
+        None,
+        "enum-base",
+    )
+    .await?;
+    capture(
+        &common,
+        "enum-updates/update",
+        "enums_update",
+        None,
+        "enum-update",
+    )
+    .await?;
+
+    // V1 messages written by the base are read as is, with their payment
+    let contract = replay(&common, "enum-base", "enum-updates/update", "enums_update").await?;
+    assert_eq!(
+        get_messages(&contract).await?,
+        messages_with_payments(&common)
+    );
+
+    // the original base stored its messages under b"m" and recorded no payment
+    let contract = replay(
+        &common,
+        "enum-original",
+        "enum-updates/update",
+        "enums_update",
+    )
+    .await?;
+    let mut unpaid = messages_with_payments(&common);
+    for message in unpaid.as_array_mut().unwrap() {
+        message["payment"] = json!(NearToken::from_near(0));
+    }
+    assert_eq!(get_messages(&contract).await?, unpaid);
+
+    let scan_outcome = contract
+        .call("scan_variant")
+        .args_json(json!({"discriminant": 0}))
+        .transact()
+        .await?;
+    assert!(scan_outcome.is_success(), "{scan_outcome:?}");
+    let scan: Value = scan_outcome.json()?;
+    assert_eq!(scan["remaining"], "2");
+    assert_eq!(scan["retirable"], false);
+
+    let contract = replay(&common, "enum-update", "enum-updates/v3", "enums_v3").await?;
+    assert_eq!(
+        get_messages(&contract).await?,
+        messages_with_payments(&common)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_golden_enum_state_updates() -> Result<(), Box<dyn std::error::Error>> {
+    let common = setup().await;
+    capture(
+        &common,
+        "enum-updates/state-base",
+        "enums_state_base",
+        None,
+        "enum-state-base",
+    )
+    .await?;
+
+    let contract = replay(
+        &common,
+        "enum-state-base",
+        "enum-updates/state-update",
+        "enums_state_update",
+    )
+    .await?;
+    assert_eq!(
+        get_messages(&contract).await?,
+        messages_with_payments(&common)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_golden_self_updates() -> Result<(), Box<dyn std::error::Error>> {
+    let common = setup().await;
+    let init = json!({"manager": common.alice.id(), "update_delay": null});
+    capture(
+        &common,
+        "self-updates/base",
+        "self_base",
+        Some(("init", init)),
+        "self-base",
+    )
+    .await?;
+
+    // the original base stored its vectors under b"m" and b"p"
+    for golden in ["self-base", "self-original"] {
+        let contract = replay(&common, golden, "self-updates/update", "self_update").await?;
+        let migrate_outcome = contract
+            .call("migrate")
+            .args_json(json!({}))
+            .transact()
+            .await?;
+        assert!(migrate_outcome.is_success(), "{migrate_outcome:?}");
+
+        assert_eq!(
+            get_messages(&contract).await?,
+            messages_with_payments(&common)
+        );
+    }
+    Ok(())
+}
+
+// Calls `unsafe_migrate` until the state reached the latest version
+async fn unsafe_migrate(
+    contract: &Contract,
+    args: Value,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for _ in 0..3 {
+        let migrate_outcome = contract
+            .call("unsafe_migrate")
+            .args_json(args.clone())
+            .gas(Gas::from_tgas(300))
+            .transact()
+            .await?;
+        assert!(migrate_outcome.is_success(), "{migrate_outcome:?}");
+
+        let result: Value = migrate_outcome.json()?;
+        if result == "done" || result["status"] == "done" {
+            return Ok(());
+        }
+    }
+    Err("the migration did not finish".into())
+}
+
+#[tokio::test]
+async fn test_golden_advanced_updates() -> Result<(), Box<dyn std::error::Error>> {
+    let common = setup().await;
+    capture(
+        &common,
+        "advanced-multi-version-updates/v1",
+        "advanced_v1",
+        None,
+        "advanced-v1",
+    )
+    .await?;
+    let init = json!({"owner": common.alice.id()});
+    capture(
+        &common,
+        "advanced-multi-version-updates/v2",
+        "advanced_v2",
+        Some(("new", init)),
+        "advanced-v2",
+    )
+    .await?;
+
+    let owner_args = json!({"migration_args": {"owner": common.alice.id()}});
+    let messages_without_payments = json!([
+        {"premium": false, "sender": common.alice.id(), "text": "hello"},
+        {"premium": true, "sender": common.bob.id(), "text": "bye"},
+    ]);
+
+    // V1 -> V2
+    let contract = replay(
+        &common,
+        "advanced-v1",
+        "advanced-multi-version-updates/v2",
+        "advanced_v2",
+    )
+    .await?;
+    unsafe_migrate(&contract, owner_args.clone()).await?;
+    assert_eq!(get_messages(&contract).await?, messages_without_payments);
+
+    // V1 -> V3
+    let contract = replay(
+        &common,
+        "advanced-v1",
+        "advanced-multi-version-updates/v3",
+        "advanced_v3",
+    )
+    .await?;
+    unsafe_migrate(&contract, owner_args).await?;
+    assert_eq!(
+        get_messages(&contract).await?,
+        messages_with_payments(&common)
+    );
+
+    // V2 -> V3
+    let contract = replay(
+        &common,
+        "advanced-v2",
+        "advanced-multi-version-updates/v3",
+        "advanced_v3",
+    )
+    .await?;
+    unsafe_migrate(&contract, json!({})).await?;
+    assert_eq!(
+        get_messages(&contract).await?,
+        messages_with_payments(&common)
+    );
+
+    // V2 -> V3 -> V4, indexing the messages by sender, whose views list their ids
+    let contract = replay(
+        &common,
+        "advanced-v2",
+        "advanced-multi-version-updates/v4",
+        "advanced_v4",
+    )
+    .await?;
+    unsafe_migrate(&contract, json!({})).await?;
+    let mut listed = messages_with_payments(&common);
+    for (id, message) in listed.as_array_mut().unwrap().iter_mut().enumerate() {
+        message["id"] = json!(id.to_string());
+        message["edited"] = json!(false);
+    }
+    assert_eq!(get_messages(&contract).await?, listed);
+    let by_sender = contract
+        .view("get_messages_by_sender")
+        .args_json(json!({"sender": common.bob.id()}))
+        .await?
+        .json::<Value>()?;
+    assert_eq!(by_sender["items"], json!([listed[1]]));
+    Ok(())
+}
@@ -0,0 +1,285 @@
+//! Derive macros re-exported by `migration-kit`.
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, Path, Type};
+
+/// Generates a migration chain from the enum listing the historical state layouts.
+///
+/// Each variant names a version and wraps the contract struct stored in that version,
+/// oldest first. Every hop converts the previous layout into the next one, either with
+/// its `From` implementation or with the function given by `#[migrate(with = path)]`.
+///
+/// ```ignore
+/// #[derive(StateMigrations)]
+/// #[migrations(version = StateVersion)]
+/// enum GuestBookState {
+///     V1(GuestBookV1),
+///     #[migrate(with = add_owner)]
+///     V2(GuestBookV2),
+///     V3(GuestBookV3), // requires `impl From<GuestBookV2> for GuestBookV3`
+/// }
+/// ```
+///
+/// Hops needing parameters, like the account to set as owner, declare them with
+/// `#[migrations(args = MigrationArgs)]`: the `with` functions then take `(old, &MigrationArgs)`
+/// and the generated migration methods take `&MigrationArgs`.
+///
+/// The derive emits the `StateVersion` enum (named by `#[migrations(version = ...)]`),
+/// stored under `migration_kit::VERSION_KEY` and defaulting to the first version when
+/// missing, plus `read`, `write`, `migrate_step`, `migrate_within_gas` and `migrate_to_latest`
+/// on the annotated enum.
+#[proc_macro_derive(StateMigrations, attributes(migrations, migrate))]
+pub fn derive_state_migrations(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+struct Version {
+    ident: Ident,
+    state: Type,
+    with: Option<Path>,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "StateMigrations can only be derived for enums",
+        ));
+    };
+
+    let mut version_ident = format_ident!("StateVersion");
+    let mut args_ty: Option<Type> = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("migrations"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("version") {
+                version_ident = meta.value()?.parse()?;
+                Ok(())
+            } else if meta.path.is_ident("args") {
+                args_ty = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `version = Ident` or `args = Type`"))
+            }
+        })?;
+    }
+
+    // With `args`, every generated entry point and `with` function takes them by reference
+    let (args_param, args_arg) = match &args_ty {
+        Some(ty) => (quote! { args: &#ty }, quote! { args }),
+        None => (quote! {}, quote! {}),
+    };
+
+    let mut versions = Vec::new();
+    for variant in &data.variants {
+        let state = match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => fields.unnamed[0].ty.clone(),
+            _ => {
+                return Err(syn::Error::new(
+                    variant.span(),
+                    "each version must wrap exactly one state type, e.g. `V1(GuestBookV1)`",
+                ))
+            }
+        };
+
+        let mut with = None;
+        for attr in variant
+            .attrs
+            .iter()
+            .filter(|a| a.path().is_ident("migrate"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("with") {
+                    with = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `with = path::to::function`"))
+                }
+            })?;
+        }
+        if with.is_some() && versions.is_empty() {
+            return Err(syn::Error::new(
+                variant.span(),
+                "the first version has no previous layout to migrate from",
+            ));
+        }
+
+        versions.push(Version {
+            ident: variant.ident.clone(),
+            state,
+            with,
+        });
+    }
+
+    let Some(latest) = versions.last() else {
+        return Err(syn::Error::new(
+            input.span(),
+            "at least one state version is required",
+        ));
+    };
+
+    let vis = &input.vis;
+    let name = &input.ident;
+    let first = &versions[0].ident;
+    let latest = &latest.ident;
+    let idents: Vec<_> = versions.iter().map(|v| &v.ident).collect();
+
+    let nexts = versions.windows(2).map(|pair| {
+        let (prev, next) = (&pair[0].ident, &pair[1].ident);
+        quote! { #version_ident::#prev => ::core::option::Option::Some(#version_ident::#next), }
+    });
+
+    let hops: Vec<_> = versions
+        .windows(2)
+        .map(|pair| {
+            let (prev, next) = (&pair[0], &pair[1]);
+            let (prev_ident, prev_state) = (&prev.ident, &prev.state);
+            let (next_ident, next_state) = (&next.ident, &next.state);
+
+            // A missing hop fails to compile, pointing at the version lacking it
+            let convert = match (&next.with, &args_ty) {
+                (Some(with), Some(args_ty)) => quote_spanned! {with.span()=>
+                    let convert: fn(#prev_state, &#args_ty) -> #next_state = #with;
+                    let new_state = convert(old_state, args);
+                },
+                (Some(with), None) => quote_spanned! {with.span()=>
+                    let convert: fn(#prev_state) -> #next_state = #with;
+                    let new_state = convert(old_state);
+                },
+                (None, _) => quote_spanned! {next_state.span()=>
+                    let new_state = <#next_state as ::core::convert::From<#prev_state>>::from(old_state);
+                },
+            };
+
+            quote! {
+                #version_ident::#prev_ident => {
+                    let old_state: #prev_state = ::migration_kit::expect_state();
+                    #convert
+                    ::near_sdk::env::state_write(&new_state);
+                    #version_ident::#next_ident.write();
+                    ::core::option::Option::Some(#version_ident::#next_ident)
+                }
+            }
+        })
+        .collect();
+
+    Ok(quote! {
+        #[::near_sdk::near(serializers = [borsh, json])]
+        #[derive(
+            ::core::fmt::Debug,
+            ::core::clone::Clone,
+            ::core::marker::Copy,
+            ::core::cmp::PartialEq,
+            ::core::cmp::Eq,
+        )]
+        #vis enum #version_ident {
+            #(#idents,)*
+        }
+
+        #[automatically_derived]
+        #[allow(dead_code)]
+        impl #version_ident {
+            pub const LATEST: Self = Self::#latest;
+
+            /// Reads the stored version, states written before versioning are the first one
+            pub fn read() -> Self {
+                ::migration_kit::state_version_read()
+                    .unwrap_or_else(|err| err.panic())
+                    .unwrap_or(Self::#first)
+            }
+
+            pub fn write(&self) {
+                ::migration_kit::state_version_write(self);
+            }
+
+            /// The version the next hop migrates to, `None` for the latest one
+            pub fn next(&self) -> ::core::option::Option<Self> {
+                match self {
+                    #(#nexts)*
+                    #version_ident::#latest => ::core::option::Option::None,
+                }
+            }
+        }
+
+        #[automatically_derived]
+        #[allow(dead_code)]
+        impl #name {
+            /// Reads the stored state using the layout of the stored version
+            pub fn read() -> Self {
+                match #version_ident::read() {
+                    #(#version_ident::#idents => #name::#idents(::migration_kit::expect_state()),)*
+                }
+            }
+
+            /// The version of this state layout
+            pub fn version(&self) -> #version_ident {
+                match self {
+                    #(#name::#idents(_) => #version_ident::#idents,)*
+                }
+            }
+
+            /// Writes the state along with its version
+            pub fn write(&self) {
+                match self {
+                    #(#name::#idents(state) => ::near_sdk::env::state_write(state),)*
+                }
+                self.version().write();
+            }
+
+            /// Migrates the stored state one version forward, returning the version reached
+            /// or `None` if it already was the latest one
+            pub fn migrate_step(#args_param) -> ::core::option::Option<#version_ident> {
+                match #version_ident::read() {
+                    #(#hops)*
+                    #version_ident::#latest => ::core::option::Option::None,
+                }
+            }
+
+            /// Applies hops while the remaining prepaid gas stays above `gas_margin`
+            pub fn migrate_within_gas(
+                gas_margin: ::near_sdk::Gas,
+                #args_param
+            ) -> ::migration_kit::MigrationReport<#version_ident> {
+                let start_gas = ::near_sdk::env::used_gas();
+                let start_version = #version_ident::read();
+                let mut end_version = start_version;
+                let mut hops_applied = 0;
+
+                let status = loop {
+                    if end_version == #version_ident::LATEST {
+                        break ::migration_kit::MigrationStatus::Done;
+                    }
+                    let remaining = ::near_sdk::env::prepaid_gas()
+                        .saturating_sub(::near_sdk::env::used_gas());
+                    if remaining <= gas_margin {
+                        break ::migration_kit::MigrationStatus::NeedsMigration;
+                    }
+                    end_version = Self::migrate_step(#args_arg).unwrap_or(#version_ident::LATEST);
+                    hops_applied += 1;
+                };
+
+                ::migration_kit::MigrationReport {
+                    start_version,
+                    end_version,
+                    hops_applied,
+                    gas_used: ::near_sdk::env::used_gas().saturating_sub(start_gas),
+                    status,
+                }
+            }
+
+            /// Applies every pending hop, returning the latest version
+            pub fn migrate_to_latest(#args_param) -> #version_ident {
+                while Self::migrate_step(#args_arg).is_some() {}
+                #version_ident::LATEST
+            }
+        }
+    })
+}
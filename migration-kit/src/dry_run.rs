@@ -0,0 +1,239 @@
+use near_sdk::json_types::{Base64VecU8, I64, U64};
+use near_sdk::{near, Gas};
+
+use crate::PaymentPolicy;
+
+/// Bytes the protocol charges for every storage record on top of its key and value
+pub const STORAGE_RECORD_OVERHEAD: u64 = 40;
+/// Key length of a vector entry under a one byte prefix, followed by its u64 index
+pub const ENTRY_KEY_LEN: u64 = 9;
+
+const PAYMENT_LEN: u64 = 16;
+// A quarantined entry adds its index and the tags of its two options
+const QUARANTINE_OVERHEAD: u64 = 10;
+
+// Storage costs from the protocol fee config, the wasm execution around them is not counted
+const STORAGE_READ_BASE: u64 = 56_356_845_750;
+const STORAGE_READ_KEY_BYTE: u64 = 30_952_533;
+const STORAGE_READ_VALUE_BYTE: u64 = 5_611_005;
+const STORAGE_WRITE_BASE: u64 = 64_196_736_000;
+const STORAGE_WRITE_KEY_BYTE: u64 = 70_482_867;
+const STORAGE_WRITE_VALUE_BYTE: u64 = 31_018_539;
+const STORAGE_WRITE_EVICTED_BYTE: u64 = 32_117_307;
+const STORAGE_REMOVE_BASE: u64 = 53_473_030_500;
+const STORAGE_REMOVE_KEY_BYTE: u64 = 38_220_384;
+const STORAGE_REMOVE_RET_VALUE_BYTE: u64 = 11_531_556;
+
+#[near(serializers = [json])]
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DryRunStatus {
+    /// The stored state can be migrated
+    Pass,
+    /// Calling the migration would fail, see the report error
+    Fail,
+}
+
+/// What a migration would do to the stored state, computed without modifying it
+#[near(serializers = [json])]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DryRunReport {
+    pub status: DryRunStatus,
+    pub error: Option<String>,
+    pub messages: U64,
+    pub payments: U64,
+    /// Messages without a payment at their index
+    pub missing_payments: U64,
+    /// Payments past the last message
+    pub orphaned_payments: U64,
+    /// Estimated change of the storage used by the migration, in bytes
+    pub storage_delta: I64,
+    /// Estimated gas spent on storage operations by the whole migration
+    pub estimated_gas: Gas,
+    /// Old entries the migration leaves for `collect_garbage`
+    pub retired_keys: U64,
+    /// Bytes `collect_garbage` frees once it removed the retired entries
+    pub reclaimable_bytes: U64,
+    /// Passed back as `cursor` to estimate the next messages, `None` on the last page.
+    /// The estimates of a paginated dry run only cover the messages of their page, add
+    /// them up for the whole migration.
+    pub next_cursor: Option<Base64VecU8>,
+}
+
+impl DryRunReport {
+    /// Report of a state the migration cannot even read
+    pub fn fail(error: impl ToString) -> Self {
+        Self {
+            status: DryRunStatus::Fail,
+            error: Some(error.to_string()),
+            messages: U64(0),
+            payments: U64(0),
+            missing_payments: U64(0),
+            orphaned_payments: U64(0),
+            storage_delta: I64(0),
+            estimated_gas: Gas::from_gas(0),
+            retired_keys: U64(0),
+            reclaimable_bytes: U64(0),
+            next_cursor: None,
+        }
+    }
+}
+
+/// Estimates a migration merging a vector of payments into the vector of messages they pair
+/// with by index, one page of messages at a time so a view can estimate any number of them.
+///
+/// The payments are retired rather than removed by the migration, their storage is counted
+/// apart as what `collect_garbage` reclaims.
+#[derive(Debug, Clone)]
+pub struct PaymentMergeEstimate {
+    policy: PaymentPolicy,
+    messages: u64,
+    payments: u64,
+    // the migrated messages are written under a new prefix, rather than over the old ones
+    moved: bool,
+    storage_delta: i64,
+    gas: u64,
+    retired_keys: u64,
+    reclaimable_bytes: u64,
+}
+
+impl PaymentMergeEstimate {
+    pub fn new(policy: PaymentPolicy, messages: u64, payments: u64) -> Self {
+        Self {
+            policy,
+            messages,
+            payments,
+            moved: false,
+            storage_delta: 0,
+            gas: 0,
+            retired_keys: 0,
+            reclaimable_bytes: 0,
+        }
+    }
+
+    /// The migrated messages are written under another prefix than the old ones, which are
+    /// all retired
+    pub fn moving_messages(mut self) -> Self {
+        self.moved = true;
+        self
+    }
+
+    /// Counts the message at `index`, `len` bytes long, returning whether it is migrated
+    /// rather than quarantined.
+    ///
+    /// A message with a payment is rewritten with it, in place unless the messages move,
+    /// the others move to the quarantine under that policy.
+    pub fn add_message(&mut self, index: u64, len: u64) -> bool {
+        self.gas += storage_read_gas(ENTRY_KEY_LEN, len).as_gas();
+        let migrated = index < self.payments || self.policy == PaymentPolicy::DefaultToZero;
+
+        if migrated {
+            self.gas += storage_read_gas(ENTRY_KEY_LEN, PAYMENT_LEN).as_gas();
+            if self.moved {
+                self.gas += storage_write_gas(ENTRY_KEY_LEN, len + PAYMENT_LEN, 0).as_gas();
+                self.storage_delta += record_size(ENTRY_KEY_LEN, len + PAYMENT_LEN) as i64;
+            } else {
+                self.gas += storage_write_gas(ENTRY_KEY_LEN, len + PAYMENT_LEN, len).as_gas();
+                self.storage_delta += PAYMENT_LEN as i64;
+            }
+        } else {
+            let entry_len = len + QUARANTINE_OVERHEAD;
+            self.gas += storage_write_gas(ENTRY_KEY_LEN, entry_len, 0).as_gas();
+            self.storage_delta += record_size(ENTRY_KEY_LEN, entry_len) as i64;
+            // left past the migrated messages, the old entry is removed by the migration
+            if !self.moved {
+                self.gas += storage_remove_gas(ENTRY_KEY_LEN, len).as_gas();
+                self.storage_delta -= record_size(ENTRY_KEY_LEN, len) as i64;
+            }
+        }
+
+        if self.moved {
+            self.retired_keys += 1;
+            self.reclaimable_bytes += record_size(ENTRY_KEY_LEN, len);
+        }
+        migrated
+    }
+
+    /// Counts storage and gas the migration spends besides moving the payments
+    pub fn add(&mut self, storage_delta: i64, gas: Gas) {
+        self.storage_delta += storage_delta;
+        self.gas += gas.as_gas();
+    }
+
+    /// Counts the payments, retired once merged and quarantined under that policy when past
+    /// the last message, to be done once per dry run
+    pub fn add_payments(&mut self) {
+        let orphaned_payments = self.payments.saturating_sub(self.messages);
+        self.retired_keys += self.payments;
+        self.reclaimable_bytes += self.payments * record_size(ENTRY_KEY_LEN, PAYMENT_LEN);
+
+        if self.policy == PaymentPolicy::Quarantine {
+            let entry_len = PAYMENT_LEN + QUARANTINE_OVERHEAD;
+            self.gas += orphaned_payments * storage_write_gas(ENTRY_KEY_LEN, entry_len, 0).as_gas();
+            self.storage_delta +=
+                (orphaned_payments * record_size(ENTRY_KEY_LEN, entry_len)) as i64;
+        }
+    }
+
+    /// Report of the messages counted so far, failing a strict migration unless every
+    /// message has a payment
+    pub fn report(self, next_cursor: Option<Base64VecU8>) -> DryRunReport {
+        let (status, error) =
+            if self.policy == PaymentPolicy::Strict && self.messages != self.payments {
+                (
+                    DryRunStatus::Fail,
+                    Some(format!(
+                        "{} messages but {} payments, migrate with another payment policy",
+                        self.messages, self.payments
+                    )),
+                )
+            } else {
+                (DryRunStatus::Pass, None)
+            };
+
+        DryRunReport {
+            status,
+            error,
+            messages: U64(self.messages),
+            payments: U64(self.payments),
+            missing_payments: U64(self.messages.saturating_sub(self.payments)),
+            orphaned_payments: U64(self.payments.saturating_sub(self.messages)),
+            storage_delta: I64(self.storage_delta),
+            estimated_gas: Gas::from_gas(self.gas),
+            retired_keys: U64(self.retired_keys),
+            reclaimable_bytes: U64(self.reclaimable_bytes),
+            next_cursor,
+        }
+    }
+}
+
+/// Storage used by a record with the given key and value lengths
+pub fn record_size(key_len: u64, value_len: u64) -> u64 {
+    STORAGE_RECORD_OVERHEAD + key_len + value_len
+}
+
+/// Gas charged for reading a value
+pub fn storage_read_gas(key_len: u64, value_len: u64) -> Gas {
+    Gas::from_gas(
+        STORAGE_READ_BASE + key_len * STORAGE_READ_KEY_BYTE + value_len * STORAGE_READ_VALUE_BYTE,
+    )
+}
+
+/// Gas charged for writing a value, replacing one of `evicted_len` bytes if any
+pub fn storage_write_gas(key_len: u64, value_len: u64, evicted_len: u64) -> Gas {
+    Gas::from_gas(
+        STORAGE_WRITE_BASE
+            + key_len * STORAGE_WRITE_KEY_BYTE
+            + value_len * STORAGE_WRITE_VALUE_BYTE
+            + evicted_len * STORAGE_WRITE_EVICTED_BYTE,
+    )
+}
+
+/// Gas charged for removing a value
+pub fn storage_remove_gas(key_len: u64, value_len: u64) -> Gas {
+    Gas::from_gas(
+        STORAGE_REMOVE_BASE
+            + key_len * STORAGE_REMOVE_KEY_BYTE
+            + value_len * STORAGE_REMOVE_RET_VALUE_BYTE,
+    )
+}
@@ -0,0 +1,17 @@
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+
+/// Standard of the events logged by the migrations
+pub const EVENT_STANDARD: &str = "migration-kit";
+pub const EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+/// Logs a [NEP-297](https://github.com/near/NEPs/blob/master/neps/nep-0297.md) event
+pub fn emit_event<T: Serialize>(event: &str, data: &[T]) {
+    let event = json!({
+        "standard": EVENT_STANDARD,
+        "version": EVENT_STANDARD_VERSION,
+        "event": event,
+        "data": data,
+    });
+    near_sdk::log!("EVENT_JSON:{}", event);
+}
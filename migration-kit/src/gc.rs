@@ -0,0 +1,97 @@
+use near_sdk::json_types::U64;
+use near_sdk::{env, near, IntoStorageKey};
+
+use crate::events::emit_event;
+use crate::state::{read_key, write_key};
+
+/// Key holding the collections retired by migrations whose entries are not removed yet
+pub const RETIRED_KEY: &[u8] = b"RETIRED";
+
+/// A legacy `Vector` no longer part of the state, its entries are removed from the last one
+#[near(serializers = [borsh, json])]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetiredVector {
+    pub prefix: Vec<u8>,
+    /// Number of entries left to remove
+    pub len: U64,
+}
+
+/// Result of a [`collect_garbage`] call, also the data of the `storage_reclaimed` event
+#[near(serializers = [json])]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GarbageCollection {
+    pub removed_keys: U64,
+    /// Storage freed by the call, in bytes
+    pub reclaimed_bytes: U64,
+    pub remaining_keys: U64,
+    pub done: bool,
+}
+
+/// The retired vectors with entries left
+pub fn retired_vectors() -> Vec<RetiredVector> {
+    read_key(RETIRED_KEY)
+        .unwrap_or_else(|err| err.panic())
+        .unwrap_or_default()
+}
+
+fn retired_vectors_write(retired: Vec<RetiredVector>) {
+    if retired.is_empty() {
+        env::storage_remove(RETIRED_KEY);
+    } else {
+        write_key(RETIRED_KEY, &retired);
+    }
+}
+
+/// Schedules the removal of the `len` entries of the legacy `Vector` stored under `prefix`.
+///
+/// Migrations retire the collections they drop instead of clearing them, so the removal
+/// can be spread over several [`collect_garbage`] calls.
+pub fn retire_vector(prefix: impl IntoStorageKey, len: u64) {
+    if len == 0 {
+        return;
+    }
+
+    let mut retired = retired_vectors();
+    retired.push(RetiredVector {
+        prefix: prefix.into_storage_key(),
+        len: U64(len),
+    });
+    retired_vectors_write(retired);
+}
+
+/// Removes up to `limit` entries of the retired vectors and logs a `storage_reclaimed` event
+/// with the storage freed
+pub fn collect_garbage(limit: u64) -> GarbageCollection {
+    let storage_before = env::storage_usage();
+    let mut retired = retired_vectors();
+    let mut removed_keys = 0;
+
+    while let Some(vector) = retired.last_mut() {
+        if removed_keys == limit {
+            break;
+        }
+
+        let idx = vector.len.0 - 1;
+        env::storage_remove(&[vector.prefix.as_slice(), &idx.to_le_bytes()].concat());
+        vector.len = U64(idx);
+        removed_keys += 1;
+
+        if idx == 0 {
+            retired.pop();
+        }
+    }
+
+    let remaining_keys = retired.iter().map(|vector| vector.len.0).sum();
+    retired_vectors_write(retired);
+
+    let collection = GarbageCollection {
+        removed_keys: U64(removed_keys),
+        reclaimed_bytes: U64(storage_before.saturating_sub(env::storage_usage())),
+        remaining_keys: U64(remaining_keys),
+        done: remaining_keys == 0,
+    };
+    if removed_keys > 0 {
+        emit_event("storage_reclaimed", &[&collection]);
+    }
+    collection
+}
@@ -0,0 +1,78 @@
+use near_sdk::json_types::U64;
+use near_sdk::{env, near, NearToken};
+
+use crate::events::emit_event;
+
+/// How a migration handles messages and payments that do not pair up
+#[near(serializers = [borsh, json])]
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaymentPolicy {
+    /// Abort the migration
+    #[default]
+    Strict,
+    /// Migrate messages without a payment with a zero payment, drop the orphaned payments
+    DefaultToZero,
+    /// Move the mismatched messages and payments to a separate collection
+    Quarantine,
+}
+
+/// A message without payment or a payment without message, set aside by [`PaymentPolicy::Quarantine`]
+#[near(serializers = [borsh, json])]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quarantined<M> {
+    /// Index of the entry in the old state
+    pub index: U64,
+    pub message: Option<M>,
+    pub payment: Option<NearToken>,
+}
+
+/// Data of the `payment_mismatch` event
+#[near(serializers = [json])]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentMismatch {
+    pub policy: PaymentPolicy,
+    pub missing_payments: U64,
+    pub orphaned_payments: U64,
+}
+
+impl PaymentPolicy {
+    /// The payment to migrate the message at `index` with, or `None` if it must be quarantined
+    pub fn resolve(self, index: u64, payment: Option<NearToken>) -> Option<NearToken> {
+        match (payment, self) {
+            (Some(payment), _) => Some(payment),
+            (None, PaymentPolicy::Strict) => {
+                env::panic_str(&format!("The message {} has no payment", index))
+            }
+            (None, PaymentPolicy::DefaultToZero) => Some(NearToken::from_near(0)),
+            (None, PaymentPolicy::Quarantine) => None,
+        }
+    }
+
+    /// Aborts under [`PaymentPolicy::Strict`] unless there are as many payments as messages,
+    /// so a strict migration fails before migrating anything
+    pub fn check_lengths(self, messages: u64, payments: u64) {
+        if self == PaymentPolicy::Strict && messages != payments {
+            env::panic_str(&format!(
+                "{} messages but {} payments, migrate with another payment policy",
+                messages, payments
+            ));
+        }
+    }
+}
+
+/// Logs a `payment_mismatch` event, unless every message had its payment
+pub fn emit_payment_mismatch(policy: PaymentPolicy, missing_payments: u64, orphaned_payments: u64) {
+    if missing_payments == 0 && orphaned_payments == 0 {
+        return;
+    }
+
+    emit_event(
+        "payment_mismatch",
+        &[PaymentMismatch {
+            policy,
+            missing_payments: U64(missing_payments),
+            orphaned_payments: U64(orphaned_payments),
+        }],
+    );
+}
@@ -0,0 +1,56 @@
+//! Building blocks shared by the update & migration examples.
+//!
+//! - [`state`]: typed readers for the contract state and raw storage keys
+//! - [`version`]: the `VERSION` key recording which state layout is stored
+//! - [`dry_run`]: the report of a migration simulated from a view, and storage cost estimates
+//! - [`integrity`]: the policy applied to messages and payments that do not pair up
+//! - [`events`]: NEP-297 events logged by the migrations
+//! - [`pagination`]: pages of collections read by index, with a continuation cursor
+//! - [`prefixes`]: a guard against collections created over the keys of live ones
+//! - [`gc`]: collections dropped by migrations, removed in bounded batches afterwards
+//! - [`report`]: the summary returned by migrations applying several hops per call
+//! - [`upgrade`]: a promise builder deploying new code on self and migrating its state
+//! - [`staging`]: code uploaded in chunks and checked against its sha256 before being deployed
+//! - [`timelock`]: updates proposed ahead of time and deployable once a delay elapsed
+//! - [`variants`]: checks no stored entry uses an enum variant before removing it
+//! - [`StateMigrations`]: derives the version enum and migration chain from the state layouts
+pub mod dry_run;
+pub mod events;
+pub mod gc;
+pub mod integrity;
+pub mod pagination;
+pub mod prefixes;
+pub mod report;
+pub mod staging;
+pub mod state;
+pub mod timelock;
+pub mod upgrade;
+pub mod variants;
+pub mod version;
+
+pub use dry_run::{DryRunReport, DryRunStatus, PaymentMergeEstimate};
+pub use events::emit_event;
+pub use gc::{
+    collect_garbage, retire_vector, retired_vectors, GarbageCollection, RetiredVector, RETIRED_KEY,
+};
+pub use integrity::{emit_payment_mismatch, PaymentMismatch, PaymentPolicy, Quarantined};
+pub use migration_kit_macros::StateMigrations;
+pub use pagination::{paginate, paginate_ordered, Order, Page, DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+pub use prefixes::{vector_prefix, PrefixError, PrefixGuard};
+pub use report::{MigrationReport, MigrationStatus};
+pub use staging::{
+    declared_code_hash, discard_staged_code, stage_code_chunk, staged_code_hash, take_staged_code,
+    StagingError, STAGED_CHUNK_KEY, STAGED_CODE_HASH_KEY, STAGED_CODE_KEY,
+};
+pub use state::{expect_state, read_key, read_state, write_key, StateError, STATE_KEY};
+pub use timelock::{
+    cancel_update, pending_updates, propose_update, set_update_delay, take_executable_update,
+    update_delay, TimelockError, UpdateProposal, UPDATE_DELAY_KEY, UPDATE_PROPOSALS_KEY,
+};
+pub use upgrade::{
+    resolve_update, stash_code, stashed_code_hash, DeployAndMigrate, CODE_KEY, PENDING_CODE_KEY,
+};
+pub use variants::{
+    scan_variant, ScanError, VariantScan, DEFAULT_SCAN_LIMIT, MAX_SCAN_LIMIT, VARIANT_SCAN_KEY,
+};
+pub use version::{state_version_read, state_version_write, VERSION_KEY};
@@ -0,0 +1,106 @@
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::{env, near, require};
+
+/// Number of entries in a page when no `limit` is given
+pub const DEFAULT_PAGE_LIMIT: u64 = 10;
+/// Largest `limit` accepted, so any page can be read within the gas of a view
+pub const MAX_PAGE_LIMIT: u64 = 100;
+
+/// Entries of a collection and where the following ones start
+#[near(serializers = [json])]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Passed back as `cursor` to read the next page, `None` on the last one
+    pub next_cursor: Option<Base64VecU8>,
+}
+
+pub(crate) fn cursor_encode(index: u64) -> Base64VecU8 {
+    Base64VecU8(index.to_le_bytes().to_vec())
+}
+
+pub(crate) fn cursor_decode(cursor: &Base64VecU8) -> u64 {
+    let bytes = <[u8; 8]>::try_from(cursor.0.as_slice())
+        .unwrap_or_else(|_| env::panic_str("Invalid cursor"));
+    u64::from_le_bytes(bytes)
+}
+
+/// Which end of a collection pages start from
+#[near(serializers = [json])]
+#[serde(rename_all = "snake_case")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    #[default]
+    OldestFirst,
+    NewestFirst,
+}
+
+/// Reads the page of a collection of `len` entries starting at `cursor`, or at `from_index`
+/// when no cursor is given.
+///
+/// Entries are read one by one with `get`, so the cost of a page does not depend on how many
+/// entries come before it, unlike `iter().skip(from)` which loads every skipped entry.
+pub fn paginate<T>(
+    len: u64,
+    from_index: Option<U128>,
+    limit: Option<U64>,
+    cursor: Option<Base64VecU8>,
+    get: impl FnMut(u64) -> Option<T>,
+) -> Page<T> {
+    paginate_ordered(len, Order::OldestFirst, from_index, limit, cursor, get)
+}
+
+/// Same as [`paginate`] in the given `order`. With [`Order::NewestFirst`], `from_index` counts
+/// from the last entry.
+///
+/// `get` may return `None` to leave an entry out of the page, at most `limit` entries are
+/// visited either way, so filtered pages can hold fewer entries while `next_cursor` is set.
+pub fn paginate_ordered<T>(
+    len: u64,
+    order: Order,
+    from_index: Option<U128>,
+    limit: Option<U64>,
+    cursor: Option<Base64VecU8>,
+    get: impl FnMut(u64) -> Option<T>,
+) -> Page<T> {
+    require!(
+        from_index.is_none() || cursor.is_none(),
+        "Pass either from_index or cursor"
+    );
+    let limit = limit.map_or(DEFAULT_PAGE_LIMIT, u64::from);
+    require!(
+        limit <= MAX_PAGE_LIMIT,
+        format!("limit must not exceed {}", MAX_PAGE_LIMIT)
+    );
+    let skipped = from_index.map_or(0, |from_index| {
+        u64::try_from(from_index.0).unwrap_or(u64::MAX)
+    });
+
+    match order {
+        // the cursor is the index of the next entry
+        Order::OldestFirst => {
+            let from = cursor
+                .map_or(skipped, |cursor| cursor_decode(&cursor))
+                .min(len);
+            let to = len.min(from.saturating_add(limit));
+
+            Page {
+                items: (from..to).filter_map(get).collect(),
+                next_cursor: (to < len).then(|| cursor_encode(to)),
+            }
+        }
+        // the cursor is the index right after the next entry, so it stays valid when new
+        // entries are pushed
+        Order::NewestFirst => {
+            let to = cursor
+                .map_or(len.saturating_sub(skipped), |cursor| cursor_decode(&cursor))
+                .min(len);
+            let from = to.saturating_sub(limit);
+
+            Page {
+                items: (from..to).rev().filter_map(get).collect(),
+                next_cursor: (from > 0).then(|| cursor_encode(from)),
+            }
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+
+use near_sdk::borsh::{self, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::{env, IntoStorageKey};
+
+use crate::gc::{retired_vectors, RETIRED_KEY};
+use crate::staging::{STAGED_CHUNK_KEY, STAGED_CODE_HASH_KEY, STAGED_CODE_KEY};
+use crate::state::STATE_KEY;
+use crate::timelock::{UPDATE_DELAY_KEY, UPDATE_PROPOSALS_KEY};
+use crate::upgrade::{CODE_KEY, PENDING_CODE_KEY};
+use crate::variants::VARIANT_SCAN_KEY;
+use crate::version::VERSION_KEY;
+
+// Keys written by the kit itself, no collection may share them
+const KIT_KEYS: [&[u8]; 11] = [
+    STATE_KEY,
+    VERSION_KEY,
+    CODE_KEY,
+    PENDING_CODE_KEY,
+    STAGED_CODE_KEY,
+    STAGED_CODE_HASH_KEY,
+    STAGED_CHUNK_KEY,
+    UPDATE_DELAY_KEY,
+    UPDATE_PROPOSALS_KEY,
+    RETIRED_KEY,
+    VARIANT_SCAN_KEY,
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PrefixError {
+    /// Keys of the new collection could collide with the ones of a live collection
+    Overlap { prefix: Vec<u8>, live: Vec<u8> },
+    /// The collection to replace in place is not live
+    NotLive { prefix: Vec<u8> },
+}
+
+impl PrefixError {
+    /// Aborts the execution using the error as panic message
+    pub fn panic(&self) -> ! {
+        env::panic_str(&self.to_string())
+    }
+}
+
+impl fmt::Display for PrefixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrefixError::Overlap { prefix, live } => write!(
+                f,
+                "The prefix {:?} overlaps the live prefix {:?}",
+                prefix, live
+            ),
+            PrefixError::NotLive { prefix } => {
+                write!(f, "No live collection has the prefix {:?}", prefix)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PrefixError {}
+
+/// Checks the collections created by a migration do not share keys with the ones still alive.
+///
+/// Two prefixes overlap when one starts with the other, since their collections could then
+/// write the same key. The keys used by the kit and the retired collections not collected yet
+/// are always considered alive.
+pub struct PrefixGuard {
+    live: Vec<Vec<u8>>,
+}
+
+impl Default for PrefixGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PrefixGuard {
+    pub fn new() -> Self {
+        let kit_keys = KIT_KEYS.iter().map(|key| key.to_vec());
+        let retired = retired_vectors().into_iter().map(|vector| vector.prefix);
+
+        Self {
+            live: kit_keys.chain(retired).collect(),
+        }
+    }
+
+    /// Registers the prefix of a collection of the stored state
+    pub fn live(mut self, key: impl IntoStorageKey) -> Self {
+        self.live.push(key.into_storage_key());
+        self
+    }
+
+    /// Registers the prefix of a legacy `Vector` of the stored state, as it was stored rather
+    /// than the one the current code would give it
+    pub fn live_vector<T: BorshSerialize>(self, vector: &Vector<T>) -> Self {
+        self.live(vector_prefix(vector))
+    }
+
+    /// Returns the prefix for a new collection, unless it overlaps a live one
+    pub fn create<K: IntoStorageKey>(&mut self, key: K) -> Result<Vec<u8>, PrefixError> {
+        let prefix = key.into_storage_key();
+        if let Some(live) = self
+            .live
+            .iter()
+            .find(|live| live.starts_with(&prefix) || prefix.starts_with(live))
+        {
+            return Err(PrefixError::Overlap {
+                prefix,
+                live: live.clone(),
+            });
+        }
+
+        self.live.push(prefix.clone());
+        Ok(prefix)
+    }
+
+    /// Returns the prefix of a live collection for a new one rewriting it in place,
+    /// entry by entry, while it is being read
+    pub fn replace<K: IntoStorageKey>(&mut self, key: K) -> Result<Vec<u8>, PrefixError> {
+        let prefix = key.into_storage_key();
+        let idx = self
+            .live
+            .iter()
+            .position(|live| live == &prefix)
+            .ok_or_else(|| PrefixError::NotLive {
+                prefix: prefix.clone(),
+            })?;
+
+        self.live.remove(idx);
+        self.create(prefix)
+    }
+}
+
+/// Prefix of a legacy `Vector`, which it does not expose, read from its borsh encoding
+pub fn vector_prefix<T: BorshSerialize>(vector: &Vector<T>) -> Vec<u8> {
+    let (_len, prefix): (u64, Vec<u8>) = borsh::to_vec(vector)
+        .ok()
+        .and_then(|data| borsh::from_slice(&data).ok())
+        .unwrap_or_else(|| env::panic_str("Cannot read the prefix of the vector"));
+    prefix
+}
@@ -0,0 +1,22 @@
+use near_sdk::{near, Gas};
+
+#[near(serializers = [json])]
+#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationStatus {
+    /// The state reached the latest version
+    Done,
+    /// The gas ran out before the latest version, the migration must be called again
+    NeedsMigration,
+}
+
+/// Outcome of a migration call applying as many hops as the gas allowed
+#[near(serializers = [json])]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MigrationReport<V> {
+    pub start_version: V,
+    pub end_version: V,
+    pub hops_applied: u32,
+    pub gas_used: Gas,
+    pub status: MigrationStatus,
+}
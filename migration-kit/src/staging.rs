@@ -0,0 +1,145 @@
+use std::fmt;
+
+use near_sdk::json_types::Base58CryptoHash;
+use near_sdk::{env, near, CryptoHash};
+
+use crate::state::{read_key, write_key};
+
+/// Key holding how many chunks and bytes of code were uploaded so far
+pub const STAGED_CODE_KEY: &[u8] = b"STAGED_CODE";
+/// Key holding the sha256 the complete staged code must match
+pub const STAGED_CODE_HASH_KEY: &[u8] = b"STAGED_CODE_HASH";
+/// Prefix of the uploaded chunks, each stored under it followed by its u32 index
+pub const STAGED_CHUNK_KEY: &[u8] = b"STAGED_CHUNK";
+
+// The chunks are stored apart, so staging one does not read and rewrite the previous ones
+#[near(serializers = [borsh])]
+#[derive(Default)]
+struct StagedCode {
+    chunks: u32,
+    len: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StagingError {
+    /// No code was staged
+    NothingStaged,
+    /// The staged code does not hash to the declared value, the upload is partial or corrupt
+    HashMismatch {
+        declared: Base58CryptoHash,
+        staged: Base58CryptoHash,
+    },
+}
+
+impl StagingError {
+    /// Aborts the execution using the error as panic message
+    pub fn panic(&self) -> ! {
+        env::panic_str(&self.to_string())
+    }
+}
+
+impl fmt::Display for StagingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StagingError::NothingStaged => write!(f, "No code is staged"),
+            StagingError::HashMismatch { declared, staged } => write!(
+                f,
+                "The staged code hashes to {}, expected {}",
+                String::from(staged),
+                String::from(declared)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StagingError {}
+
+fn declared_hash_read() -> Option<CryptoHash> {
+    env::storage_read(STAGED_CODE_HASH_KEY).map(|hash| {
+        hash.try_into()
+            .unwrap_or_else(|_| env::panic_str("Cannot read the staged code hash"))
+    })
+}
+
+fn staged_read() -> Option<StagedCode> {
+    read_key(STAGED_CODE_KEY).unwrap_or_else(|err| err.panic())
+}
+
+fn chunk_key(idx: u32) -> Vec<u8> {
+    [STAGED_CHUNK_KEY, &idx.to_le_bytes()].concat()
+}
+
+// The chunks staged so far, concatenated
+fn staged_code() -> Option<Vec<u8>> {
+    let staged = staged_read()?;
+    let mut code = Vec::with_capacity(staged.len as usize);
+    for idx in 0..staged.chunks {
+        let chunk = env::storage_read(&chunk_key(idx))
+            .unwrap_or_else(|| env::panic_str("A staged chunk is missing"));
+        code.extend_from_slice(&chunk);
+    }
+    Some(code)
+}
+
+/// Appends `chunk` to the staged code, returning the number of bytes staged.
+///
+/// A `code_hash` different from the one of the code being staged discards it and
+/// starts a new upload. Each chunk is written under its own key, so staging a chunk
+/// costs the same whatever was staged before it.
+pub fn stage_code_chunk(code_hash: CryptoHash, chunk: &[u8]) -> u64 {
+    let mut staged = match declared_hash_read() {
+        Some(declared) if declared == code_hash => staged_read().unwrap_or_default(),
+        _ => {
+            discard_staged_code();
+            env::storage_write(STAGED_CODE_HASH_KEY, &code_hash);
+            StagedCode::default()
+        }
+    };
+
+    env::storage_write(&chunk_key(staged.chunks), chunk);
+    staged.chunks += 1;
+    staged.len += chunk.len() as u64;
+    write_key(STAGED_CODE_KEY, &staged);
+    staged.len
+}
+
+/// Hash of the code staged so far, matching the declared one once the upload is complete
+pub fn staged_code_hash() -> Option<Base58CryptoHash> {
+    staged_code().map(|code| env::sha256_array(code).into())
+}
+
+/// Hash the staged code must match to be deployed
+pub fn declared_code_hash() -> Option<Base58CryptoHash> {
+    declared_hash_read().map(Base58CryptoHash::from)
+}
+
+/// Removes the staged chunks and the declared hash
+pub fn discard_staged_code() {
+    if let Some(staged) = staged_read() {
+        for idx in 0..staged.chunks {
+            env::storage_remove(&chunk_key(idx));
+        }
+    }
+    env::storage_remove(STAGED_CODE_KEY);
+    env::storage_remove(STAGED_CODE_HASH_KEY);
+}
+
+/// Removes the staged code from storage and returns it, if it matches its declared hash.
+/// The chunks are only concatenated here, once.
+///
+/// Nothing is removed on failure, so a partial upload can still be completed.
+pub fn take_staged_code() -> Result<Vec<u8>, StagingError> {
+    let declared = declared_hash_read().ok_or(StagingError::NothingStaged)?;
+    let code = staged_code().unwrap_or_default();
+
+    let staged = env::sha256_array(&code);
+    if staged != declared {
+        return Err(StagingError::HashMismatch {
+            declared: declared.into(),
+            staged: staged.into(),
+        });
+    }
+
+    discard_staged_code();
+    Ok(code)
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::env;
+
+/// Key under which `#[near(contract_state)]` stores the contract struct
+pub const STATE_KEY: &[u8] = b"STATE";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// Nothing is stored under the key
+    Missing { key: Vec<u8> },
+    /// The stored bytes do not match the expected layout
+    Corrupted { key: Vec<u8>, reason: String },
+}
+
+impl StateError {
+    /// Aborts the execution using the error as panic message
+    pub fn panic(&self) -> ! {
+        env::panic_str(&self.to_string())
+    }
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Missing { key } => {
+                write!(
+                    f,
+                    "Nothing is stored under the key {:?}",
+                    String::from_utf8_lossy(key)
+                )
+            }
+            StateError::Corrupted { key, reason } => write!(
+                f,
+                "Cannot deserialize the value under the key {:?}: {}",
+                String::from_utf8_lossy(key),
+                reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Reads and deserializes the value stored under `key`, if any
+pub fn read_key<T: BorshDeserialize>(key: &[u8]) -> Result<Option<T>, StateError> {
+    env::storage_read(key)
+        .map(|data| {
+            borsh::from_slice(&data).map_err(|err| StateError::Corrupted {
+                key: key.to_vec(),
+                reason: err.to_string(),
+            })
+        })
+        .transpose()
+}
+
+/// Serializes `value` and stores it under `key`
+pub fn write_key<T: BorshSerialize>(key: &[u8], value: &T) {
+    let data = borsh::to_vec(value).expect("Cannot serialize the value.");
+    env::storage_write(key, &data);
+}
+
+/// Reads the contract state as `T`, usually the layout of a previous version
+pub fn read_state<T: BorshDeserialize>() -> Result<T, StateError> {
+    read_key(STATE_KEY)?.ok_or(StateError::Missing {
+        key: STATE_KEY.to_vec(),
+    })
+}
+
+/// Like [`read_state`], aborting the execution with a descriptive message on failure
+pub fn expect_state<T: BorshDeserialize>() -> T {
+    read_state().unwrap_or_else(|err| err.panic())
+}
@@ -0,0 +1,182 @@
+use std::fmt;
+
+use near_sdk::json_types::{Base58CryptoHash, Base64VecU8, U64};
+use near_sdk::{env, near, CryptoHash};
+
+use crate::state::{read_key, write_key};
+
+/// Key holding the delay, in nanoseconds, between proposing an update and executing it
+pub const UPDATE_DELAY_KEY: &[u8] = b"UPDATE_DELAY";
+/// Key holding the pending update proposals
+pub const UPDATE_PROPOSALS_KEY: &[u8] = b"UPDATE_PROPOSALS";
+
+/// Code announced ahead of its deployment
+#[near(serializers = [borsh, json])]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateProposal {
+    pub code_hash: Base58CryptoHash,
+    /// Raw arguments the migration of the code must be called with, `None` for code proposed
+    /// as the stash to roll back to, which is not migrated
+    pub migrate_args: Option<Base64VecU8>,
+    /// Block timestamp of the proposal, in nanoseconds
+    pub proposed_at: U64,
+    /// Block timestamp from which the code can be deployed, in nanoseconds
+    pub executable_at: U64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TimelockError {
+    /// No pending proposal has this code hash
+    NotProposed { code_hash: Base58CryptoHash },
+    /// The delay of the proposal has not elapsed yet
+    NotYetExecutable { executable_at: u64 },
+    /// No delay is configured, so proposals would never be consumed
+    NotTimeLocked,
+    /// The code was proposed with other migration arguments
+    MigrateArgsMismatch { code_hash: Base58CryptoHash },
+}
+
+impl TimelockError {
+    /// Aborts the execution using the error as panic message
+    pub fn panic(&self) -> ! {
+        env::panic_str(&self.to_string())
+    }
+}
+
+impl fmt::Display for TimelockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimelockError::NotProposed { code_hash } => {
+                write!(
+                    f,
+                    "No update proposed for the code {}",
+                    String::from(code_hash)
+                )
+            }
+            TimelockError::NotYetExecutable { executable_at } => write!(
+                f,
+                "The update cannot be executed before the timestamp {}",
+                executable_at
+            ),
+            TimelockError::NotTimeLocked => {
+                write!(f, "Updates are not time locked, deploy the code directly")
+            }
+            TimelockError::MigrateArgsMismatch { code_hash } => write!(
+                f,
+                "The update {} was proposed with other migrate arguments",
+                String::from(code_hash)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TimelockError {}
+
+/// Sets the delay updates must wait for, enabling the time lock
+pub fn set_update_delay(delay: u64) {
+    write_key(UPDATE_DELAY_KEY, &delay);
+}
+
+/// The configured delay, `None` if updates are not time locked
+pub fn update_delay() -> Option<u64> {
+    read_key(UPDATE_DELAY_KEY).unwrap_or_else(|err| err.panic())
+}
+
+/// The proposals not executed nor cancelled yet
+pub fn pending_updates() -> Vec<UpdateProposal> {
+    read_key(UPDATE_PROPOSALS_KEY)
+        .unwrap_or_else(|err| err.panic())
+        .unwrap_or_default()
+}
+
+fn pending_updates_write(proposals: Vec<UpdateProposal>) {
+    if proposals.is_empty() {
+        env::storage_remove(UPDATE_PROPOSALS_KEY);
+    } else {
+        write_key(UPDATE_PROPOSALS_KEY, &proposals);
+    }
+}
+
+fn remove_proposal(code_hash: CryptoHash) -> Result<UpdateProposal, TimelockError> {
+    let code_hash = Base58CryptoHash::from(code_hash);
+    let mut proposals = pending_updates();
+    let idx = proposals
+        .iter()
+        .position(|proposal| proposal.code_hash == code_hash)
+        .ok_or(TimelockError::NotProposed { code_hash })?;
+
+    let proposal = proposals.remove(idx);
+    pending_updates_write(proposals);
+    Ok(proposal)
+}
+
+/// Announces the code hashing to `code_hash` with the arguments of its migration, executable
+/// once the delay elapsed. `migrate_args` is `None` to propose the code to stash.
+///
+/// Proposing the same code again replaces its arguments and restarts its delay. Without a
+/// configured delay nothing would consume the proposal, so it is rejected.
+pub fn propose_update(
+    code_hash: CryptoHash,
+    migrate_args: Option<Vec<u8>>,
+) -> Result<UpdateProposal, TimelockError> {
+    let delay = update_delay().ok_or(TimelockError::NotTimeLocked)?;
+    let now = env::block_timestamp();
+    let proposal = UpdateProposal {
+        code_hash: code_hash.into(),
+        migrate_args: migrate_args.map(Base64VecU8),
+        proposed_at: U64(now),
+        executable_at: U64(now.saturating_add(delay)),
+    };
+
+    let mut proposals = pending_updates();
+    proposals.retain(|pending| pending.code_hash != proposal.code_hash);
+    proposals.push(proposal.clone());
+    pending_updates_write(proposals);
+
+    near_sdk::log!(
+        "Update {} proposed, executable at {}",
+        String::from(&proposal.code_hash),
+        proposal.executable_at.0
+    );
+    Ok(proposal)
+}
+
+/// Removes the proposal of the code hashing to `code_hash`
+pub fn cancel_update(code_hash: CryptoHash) -> Result<UpdateProposal, TimelockError> {
+    let proposal = remove_proposal(code_hash)?;
+    near_sdk::log!("Update {} cancelled", String::from(&proposal.code_hash));
+    Ok(proposal)
+}
+
+/// Checks `code` can be deployed with `migrate_args`, or stashed when `None`, consuming
+/// its proposal.
+///
+/// Without a configured delay every code can be deployed right away.
+pub fn take_executable_update(
+    code: &[u8],
+    migrate_args: Option<&[u8]>,
+) -> Result<Option<UpdateProposal>, TimelockError> {
+    if update_delay().is_none() {
+        return Ok(None);
+    }
+
+    let code_hash = env::sha256_array(code);
+    let proposed = Base58CryptoHash::from(code_hash);
+    let proposal = pending_updates()
+        .into_iter()
+        .find(|proposal| proposal.code_hash == proposed)
+        .ok_or(TimelockError::NotProposed {
+            code_hash: proposed,
+        })?;
+
+    if proposal.migrate_args.as_ref().map(|args| args.0.as_slice()) != migrate_args {
+        return Err(TimelockError::MigrateArgsMismatch {
+            code_hash: proposed,
+        });
+    }
+    let executable_at = proposal.executable_at.0;
+    if env::block_timestamp() < executable_at {
+        return Err(TimelockError::NotYetExecutable { executable_at });
+    }
+    remove_proposal(code_hash).map(Some)
+}
@@ -0,0 +1,156 @@
+use near_sdk::json_types::Base58CryptoHash;
+use near_sdk::{env, Gas, NearToken, Promise};
+
+/// Key holding the code the contract runs, redeployed when an update with rollback fails
+pub const CODE_KEY: &[u8] = b"CODE";
+/// Key holding the code being deployed until its update is resolved
+pub const PENDING_CODE_KEY: &[u8] = b"PENDING_CODE";
+
+const DEFAULT_MIGRATE_METHOD: &str = "migrate";
+const DEFAULT_MIGRATE_GAS: Gas = Gas::from_tgas(200); // 200 TGAS
+const RESOLVE_GAS: Gas = Gas::from_tgas(30); // 30 TGAS
+
+/// Deploys new code on the current account and calls its migration method.
+///
+/// By default the deploy and the `migrate` call share a single receipt, so a failing
+/// migration also reverts the deploy. Use [`DeployAndMigrate::then_migrate`] to call it
+/// from a separate receipt instead.
+pub struct DeployAndMigrate {
+    code: Vec<u8>,
+    method: String,
+    args: Vec<u8>,
+    gas: Gas,
+    separate_receipt: bool,
+    resolve_method: Option<String>,
+}
+
+impl DeployAndMigrate {
+    pub fn new(code: Vec<u8>) -> Self {
+        Self {
+            code,
+            method: DEFAULT_MIGRATE_METHOD.to_string(),
+            args: vec![],
+            gas: DEFAULT_MIGRATE_GAS,
+            separate_receipt: false,
+            resolve_method: None,
+        }
+    }
+
+    /// Method called on the new code after deploying it, `migrate` by default
+    pub fn method(mut self, method: &str) -> Self {
+        self.method = method.to_string();
+        self
+    }
+
+    /// Raw arguments passed to the migration method, none by default
+    pub fn args(mut self, args: Vec<u8>) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Gas attached to the migration call, 200 TGas by default
+    pub fn gas(mut self, gas: Gas) -> Self {
+        self.gas = gas;
+        self
+    }
+
+    /// Calls the migration method from a receipt executed after the deploy
+    pub fn then_migrate(mut self) -> Self {
+        self.separate_receipt = true;
+        self
+    }
+
+    /// Calls the migration method from a separate receipt, then `method` on the current
+    /// account once it finished, which must call [`resolve_update`] to redeploy the code
+    /// stashed with [`stash_code`] if the migration failed.
+    ///
+    /// A migration sharing the receipt of the deploy is already reverted with it, so only
+    /// a migration on a separate receipt needs a rollback.
+    pub fn with_rollback(mut self, method: &str) -> Self {
+        self.separate_receipt = true;
+        self.resolve_method = Some(method.to_string());
+        self
+    }
+
+    /// Panics if the update has a rollback but no code was stashed to roll back to
+    pub fn build(self) -> Promise {
+        if self.resolve_method.is_some() {
+            if !env::storage_has_key(CODE_KEY) {
+                env::panic_str("No code to roll back to, stash the running code first");
+            }
+            env::storage_write(PENDING_CODE_KEY, &self.code);
+        }
+
+        let deploy = Promise::new(env::current_account_id()).deploy_contract(self.code);
+
+        let migrate = if self.separate_receipt {
+            deploy.then(Promise::new(env::current_account_id()).function_call(
+                self.method,
+                self.args,
+                NearToken::from_near(0),
+                self.gas,
+            ))
+        } else {
+            deploy.function_call(self.method, self.args, NearToken::from_near(0), self.gas)
+        };
+
+        match self.resolve_method {
+            Some(method) => migrate.then(Promise::new(env::current_account_id()).function_call(
+                method,
+                vec![],
+                NearToken::from_near(0),
+                RESOLVE_GAS,
+            )),
+            None => migrate,
+        }
+    }
+}
+
+/// Settles an update built [`with_rollback`](DeployAndMigrate::with_rollback), returning
+/// whether the migration succeeded.
+///
+/// On success the pending code replaces the stashed one, as the code now running. On failure
+/// the migration ran on a separate receipt, after the deploy succeeded, so the stashed code
+/// is deployed again and the contract keeps running the code matching its state.
+pub fn resolve_update() -> bool {
+    if near_sdk::is_promise_success() {
+        if let Some(code) = env::storage_read(PENDING_CODE_KEY) {
+            env::storage_write(CODE_KEY, &code);
+            env::storage_remove(PENDING_CODE_KEY);
+        }
+        near_sdk::log!("Update succeeded.");
+        return true;
+    }
+
+    env::storage_remove(PENDING_CODE_KEY);
+    match env::storage_read(CODE_KEY) {
+        Some(code) => {
+            near_sdk::log!("Migration failed, redeploying the previous code.");
+            Promise::new(env::current_account_id())
+                .deploy_contract(code)
+                .detach();
+        }
+        None => near_sdk::log!("Migration failed, no previous code was stashed."),
+    }
+    false
+}
+
+/// Stashes `code` under [`CODE_KEY`] as the code to roll back to, which must be the code the
+/// contract runs since it cannot read it from its account.
+///
+/// Only the first code is stashed this way, the caller must check it is the running code as it
+/// would check an update. From then on only [`resolve_update`] replaces the stash, with the code
+/// it deployed, so panics if a code is already stashed.
+///
+/// The stash stays in storage, so the account locks its storage cost, about 0.01 NEAR per kB.
+pub fn stash_code(code: &[u8]) {
+    if env::storage_has_key(CODE_KEY) {
+        env::panic_str("A code is already stashed, only a resolved update replaces it");
+    }
+    env::storage_write(CODE_KEY, code);
+}
+
+/// Hash of the stashed code, if any
+pub fn stashed_code_hash() -> Option<Base58CryptoHash> {
+    env::storage_read(CODE_KEY).map(|code| env::sha256_array(code).into())
+}
@@ -0,0 +1,160 @@
+use std::fmt;
+
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::{env, near, require, IntoStorageKey};
+
+use crate::pagination::{cursor_decode, cursor_encode};
+use crate::state::{read_key, write_key};
+
+/// Key holding the progress of the variant scan under way, see [`scan_variant`]
+pub const VARIANT_SCAN_KEY: &[u8] = b"VARIANT_SCAN";
+
+/// Number of entries scanned when no `limit` is given
+pub const DEFAULT_SCAN_LIMIT: u64 = 100;
+/// Largest `limit` accepted, only the first byte of an entry is read so a scan page holds
+/// more entries than a page of decoded ones
+pub const MAX_SCAN_LIMIT: u64 = 500;
+
+/// How many entries of a legacy `Vector` of enums are still stored as a variant
+#[near(serializers = [json])]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VariantScan {
+    /// Borsh discriminant of the variant, its position in the enum
+    pub discriminant: u8,
+    /// Entries stored as the variant among the ones scanned so far
+    pub remaining: U64,
+    /// Index of the first entry stored as the variant
+    pub first_index: Option<U64>,
+    /// Whether the variant can be removed from the enum without breaking the decoding,
+    /// only set once every entry from index 0 was scanned
+    pub retirable: bool,
+    /// Passed back as `cursor` to scan the next entries, `None` on the last page
+    pub next_cursor: Option<Base64VecU8>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScanError {
+    /// An entry within the length of the vector is not stored
+    MissingEntry { index: u64 },
+    /// The cursor does not continue the scan under way
+    StaleCursor,
+}
+
+impl ScanError {
+    /// Aborts the execution using the error as panic message
+    pub fn panic(&self) -> ! {
+        env::panic_str(&self.to_string())
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::MissingEntry { index } => {
+                write!(f, "The entry {} of the vector is missing", index)
+            }
+            ScanError::StaleCursor => {
+                write!(f, "The cursor does not continue the scan under way")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+// What the previous pages found, kept in storage so the caller cannot alter the counts
+#[near(serializers = [borsh])]
+struct ScanProgress {
+    prefix: Vec<u8>,
+    discriminant: u8,
+    start: u64,
+    next_index: u64,
+    remaining: u64,
+    first_index: Option<u64>,
+}
+
+/// Scans the legacy `Vector` of `len` entries stored under `prefix` for the ones whose
+/// borsh discriminant is `discriminant`, at most `limit` entries from `from_index`, or from
+/// `cursor` to continue the scan under way.
+///
+/// The counts of the previous pages are kept under [`VARIANT_SCAN_KEY`], so the scan of the
+/// last page reports them for the whole vector. Only the first byte of the raw entries is
+/// checked, so the scan also works on entries the current code cannot decode, while an entry
+/// missing within `len` is an error.
+///
+/// The scan only reports: nothing stops code without the variant from being deployed while
+/// entries are left, which makes every call reading them fail.
+pub fn scan_variant(
+    prefix: impl IntoStorageKey,
+    len: u64,
+    discriminant: u8,
+    from_index: Option<U128>,
+    limit: Option<U64>,
+    cursor: Option<Base64VecU8>,
+) -> Result<VariantScan, ScanError> {
+    require!(
+        from_index.is_none() || cursor.is_none(),
+        "Pass either from_index or cursor"
+    );
+    let limit = limit.map_or(DEFAULT_SCAN_LIMIT, u64::from);
+    require!(
+        limit <= MAX_SCAN_LIMIT,
+        format!("limit must not exceed {}", MAX_SCAN_LIMIT)
+    );
+    let prefix = prefix.into_storage_key();
+
+    let mut progress = match cursor {
+        Some(cursor) => {
+            let progress: ScanProgress = read_key(VARIANT_SCAN_KEY)
+                .unwrap_or_else(|err| err.panic())
+                .ok_or(ScanError::StaleCursor)?;
+            if progress.prefix != prefix
+                || progress.discriminant != discriminant
+                || progress.next_index != cursor_decode(&cursor)
+            {
+                return Err(ScanError::StaleCursor);
+            }
+            progress
+        }
+        None => {
+            let start = from_index.map_or(0, |from_index| {
+                u64::try_from(from_index.0).unwrap_or(u64::MAX)
+            });
+            ScanProgress {
+                prefix,
+                discriminant,
+                start,
+                next_index: start,
+                remaining: 0,
+                first_index: None,
+            }
+        }
+    };
+
+    let from = progress.next_index.min(len);
+    let to = len.min(from.saturating_add(limit));
+    for idx in from..to {
+        let entry = env::storage_read(&[progress.prefix.as_slice(), &idx.to_le_bytes()].concat())
+            .ok_or(ScanError::MissingEntry { index: idx })?;
+        if entry.first() == Some(&discriminant) {
+            progress.first_index.get_or_insert(idx);
+            progress.remaining += 1;
+        }
+    }
+    progress.next_index = to;
+
+    let done = to >= len;
+    if done {
+        env::storage_remove(VARIANT_SCAN_KEY);
+    } else {
+        write_key(VARIANT_SCAN_KEY, &progress);
+    }
+
+    Ok(VariantScan {
+        discriminant,
+        remaining: U64(progress.remaining),
+        first_index: progress.first_index.map(U64),
+        retirable: done && progress.start == 0 && progress.remaining == 0,
+        next_cursor: (!done).then(|| cursor_encode(to)),
+    })
+}
@@ -0,0 +1,18 @@
+use std::fmt::Debug;
+
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::state::{read_key, write_key, StateError};
+
+/// Key under which the version of the stored state is kept, next to the state itself
+pub const VERSION_KEY: &[u8] = b"VERSION";
+
+/// Reads the stored state version, `None` if the state predates versioning
+pub fn state_version_read<V: BorshDeserialize>() -> Result<Option<V>, StateError> {
+    read_key(VERSION_KEY)
+}
+
+pub fn state_version_write<V: BorshSerialize + Debug>(version: &V) {
+    write_key(VERSION_KEY, version);
+    near_sdk::log!("Migrated to version: {:?}", version);
+}
@@ -0,0 +1,976 @@
+use migration_kit::dry_run::{record_size, storage_read_gas, storage_write_gas, ENTRY_KEY_LEN};
+use migration_kit::{
+    cancel_update, pending_updates, propose_update, set_update_delay, take_executable_update,
+    TimelockError,
+};
+use migration_kit::{collect_garbage, retire_vector, retired_vectors, RetiredVector, RETIRED_KEY};
+use migration_kit::{
+    declared_code_hash, discard_staged_code, expect_state, read_key, read_state, resolve_update,
+    stage_code_chunk, staged_code_hash, stash_code, stashed_code_hash, state_version_read,
+    state_version_write, take_staged_code, write_key, DeployAndMigrate, StagingError, StateError,
+    CODE_KEY, PENDING_CODE_KEY, STAGED_CHUNK_KEY, STATE_KEY, VERSION_KEY,
+};
+use migration_kit::{
+    emit_payment_mismatch, vector_prefix, PaymentPolicy, PrefixError, PrefixGuard,
+};
+use migration_kit::{paginate, paginate_ordered, Order, Page, MAX_PAGE_LIMIT};
+use migration_kit::{scan_variant, ScanError, VariantScan, VARIANT_SCAN_KEY};
+use migration_kit::{DryRunStatus, PaymentMergeEstimate};
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::collections::Vector;
+use near_sdk::json_types::{Base64VecU8, U128, U64};
+use near_sdk::mock::MockAction;
+use near_sdk::test_utils::{get_created_receipts, get_logs, VMContextBuilder};
+use near_sdk::{
+    env, test_vm_config, testing_env, Gas, NearToken, PromiseResult, RuntimeFeesConfig,
+};
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+#[borsh(crate = "near_sdk::borsh")]
+enum StateVersion {
+    V1,
+    V2,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+#[borsh(crate = "near_sdk::borsh")]
+struct OldState {
+    messages: u64,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+#[borsh(crate = "near_sdk::borsh")]
+struct NewState {
+    messages: u64,
+    owner: String,
+}
+
+fn setup_callback(result: PromiseResult) {
+    testing_env!(
+        VMContextBuilder::new()
+            .prepaid_gas(Gas::from_tgas(300))
+            .build(),
+        test_vm_config(),
+        RuntimeFeesConfig::test(),
+        Default::default(),
+        vec![result],
+    );
+}
+
+fn setup_at(block_timestamp: u64) {
+    testing_env!(VMContextBuilder::new()
+        .block_timestamp(block_timestamp)
+        .build());
+}
+
+fn setup() {
+    testing_env!(VMContextBuilder::new()
+        .prepaid_gas(Gas::from_tgas(300))
+        .build());
+}
+
+#[test]
+fn test_state_version_round_trip() {
+    setup();
+
+    assert_eq!(state_version_read::<StateVersion>(), Ok(None));
+
+    state_version_write(&StateVersion::V2);
+
+    assert_eq!(state_version_read(), Ok(Some(StateVersion::V2)));
+    assert_eq!(get_logs(), vec!["Migrated to version: V2"]);
+}
+
+#[test]
+fn test_corrupted_state_version() {
+    setup();
+
+    env::storage_write(VERSION_KEY, &[7]);
+
+    assert!(matches!(
+        state_version_read::<StateVersion>(),
+        Err(StateError::Corrupted { key, .. }) if key == VERSION_KEY
+    ));
+}
+
+#[test]
+fn test_read_state() {
+    setup();
+
+    assert_eq!(
+        read_state::<OldState>(),
+        Err(StateError::Missing {
+            key: STATE_KEY.to_vec()
+        })
+    );
+
+    write_key(STATE_KEY, &OldState { messages: 3 });
+
+    assert_eq!(read_state(), Ok(OldState { messages: 3 }));
+    assert_eq!(expect_state::<OldState>(), OldState { messages: 3 });
+
+    // the stored state does not match the new layout
+    let err = read_state::<NewState>().unwrap_err();
+    assert!(matches!(err, StateError::Corrupted { .. }));
+    assert!(err
+        .to_string()
+        .starts_with("Cannot deserialize the value under the key \"STATE\""));
+}
+
+#[test]
+#[should_panic(expected = "Nothing is stored under the key")]
+fn test_expect_missing_state() {
+    setup();
+
+    expect_state::<OldState>();
+}
+
+#[test]
+fn test_read_key() {
+    setup();
+
+    assert_eq!(read_key::<u64>(b"k"), Ok(None));
+
+    write_key(b"k", &42u64);
+
+    assert_eq!(read_key(b"k"), Ok(Some(42u64)));
+}
+
+#[test]
+fn test_deploy_and_migrate_single_receipt() {
+    setup();
+
+    drop(DeployAndMigrate::new(vec![1, 2, 3]).build());
+
+    let receipts = get_created_receipts();
+    assert_eq!(receipts.len(), 1);
+    assert!(matches!(
+        receipts[0].actions.as_slice(),
+        [
+            MockAction::DeployContract { code, .. },
+            MockAction::FunctionCallWeight { method_name, args, attached_deposit, prepaid_gas, .. },
+        ] if code == &[1, 2, 3]
+            && method_name == b"migrate"
+            && args.is_empty()
+            && *attached_deposit == NearToken::from_near(0)
+            && *prepaid_gas == Gas::from_tgas(200)
+    ));
+}
+
+#[test]
+fn test_deploy_and_migrate_separate_receipt() {
+    setup();
+
+    drop(
+        DeployAndMigrate::new(vec![1, 2, 3])
+            .method("unsafe_migrate")
+            .args(b"{}".to_vec())
+            .gas(Gas::from_tgas(100))
+            .then_migrate()
+            .build(),
+    );
+
+    let receipts = get_created_receipts();
+    assert_eq!(receipts.len(), 2);
+    assert!(matches!(
+        receipts[0].actions.as_slice(),
+        [MockAction::DeployContract { .. }]
+    ));
+    assert_eq!(receipts[1].receipt_indices, vec![0]);
+    assert!(matches!(
+        receipts[1].actions.as_slice(),
+        [MockAction::FunctionCallWeight { method_name, args, prepaid_gas, .. }]
+            if method_name == b"unsafe_migrate"
+                && args == b"{}"
+                && *prepaid_gas == Gas::from_tgas(100)
+    ));
+}
+
+#[test]
+fn test_deploy_and_migrate_with_rollback() {
+    setup();
+    stash_code(&[1, 2, 3]);
+
+    drop(
+        DeployAndMigrate::new(vec![4, 5, 6])
+            .with_rollback("on_update")
+            .build(),
+    );
+
+    assert_eq!(env::storage_read(PENDING_CODE_KEY), Some(vec![4, 5, 6]));
+    assert_eq!(
+        stashed_code_hash(),
+        Some(env::sha256_array([1, 2, 3]).into())
+    );
+
+    // the migration runs on its own receipt, so its failure does not revert the deploy
+    let receipts = get_created_receipts();
+    assert_eq!(receipts.len(), 3);
+    assert!(matches!(
+        receipts[0].actions.as_slice(),
+        [MockAction::DeployContract { .. }]
+    ));
+    assert_eq!(receipts[1].receipt_indices, vec![0]);
+    let [MockAction::FunctionCallWeight {
+        receipt_index,
+        method_name,
+        ..
+    }] = receipts[1].actions.as_slice()
+    else {
+        panic!("expected a single function call");
+    };
+    assert_eq!(method_name, b"migrate");
+    assert_eq!(receipts[2].receipt_indices, vec![*receipt_index]);
+    assert!(matches!(
+        receipts[2].actions.as_slice(),
+        [MockAction::FunctionCallWeight { method_name, .. }] if method_name == b"on_update"
+    ));
+}
+
+#[test]
+#[should_panic(expected = "No code to roll back to")]
+fn test_deploy_and_migrate_with_rollback_needs_stash() {
+    setup();
+
+    drop(
+        DeployAndMigrate::new(vec![4, 5, 6])
+            .with_rollback("on_update")
+            .build(),
+    );
+}
+
+#[test]
+#[should_panic(expected = "A code is already stashed")]
+fn test_stash_code_only_once() {
+    setup();
+    stash_code(&[1, 2, 3]);
+
+    stash_code(&[4, 5, 6]);
+}
+
+#[test]
+fn test_resolve_successful_update() {
+    setup();
+    stash_code(&[4, 5, 6]);
+    env::storage_write(PENDING_CODE_KEY, &[1, 2, 3]);
+
+    setup_callback(PromiseResult::Successful(vec![]));
+
+    assert!(resolve_update());
+    assert_eq!(env::storage_read(CODE_KEY), Some(vec![1, 2, 3]));
+    assert!(!env::storage_has_key(PENDING_CODE_KEY));
+    assert_eq!(
+        stashed_code_hash(),
+        Some(env::sha256_array([1, 2, 3]).into())
+    );
+    assert!(get_created_receipts().is_empty());
+}
+
+#[test]
+fn test_resolve_failed_update_redeploys_previous_code() {
+    setup();
+    stash_code(&[1, 2, 3]);
+    env::storage_write(PENDING_CODE_KEY, &[4, 5, 6]);
+
+    setup_callback(PromiseResult::Failed);
+
+    assert!(!resolve_update());
+    assert_eq!(env::storage_read(CODE_KEY), Some(vec![1, 2, 3]));
+    assert!(!env::storage_has_key(PENDING_CODE_KEY));
+
+    let receipts = get_created_receipts();
+    assert_eq!(receipts.len(), 1);
+    assert!(matches!(
+        receipts[0].actions.as_slice(),
+        [MockAction::DeployContract { code, .. }] if code == &[1, 2, 3]
+    ));
+}
+
+#[test]
+fn test_resolve_failed_update_without_stash() {
+    setup();
+    env::storage_write(PENDING_CODE_KEY, &[4, 5, 6]);
+
+    setup_callback(PromiseResult::Failed);
+
+    assert!(!resolve_update());
+    assert_eq!(stashed_code_hash(), None);
+    assert!(get_created_receipts().is_empty());
+}
+
+#[test]
+fn test_stage_code_in_chunks() {
+    setup();
+    let code_hash = env::sha256_array([1, 2, 3, 4, 5]);
+
+    assert_eq!(stage_code_chunk(code_hash, &[1, 2]), 2);
+    assert_eq!(stage_code_chunk(code_hash, &[3, 4, 5]), 5);
+
+    // each chunk has its own key, staging one does not rewrite the previous ones
+    let chunk_key = |idx: u32| [STAGED_CHUNK_KEY, &idx.to_le_bytes()].concat();
+    assert_eq!(env::storage_read(&chunk_key(0)), Some(vec![1, 2]));
+    assert_eq!(env::storage_read(&chunk_key(1)), Some(vec![3, 4, 5]));
+
+    assert_eq!(declared_code_hash(), Some(code_hash.into()));
+    assert_eq!(staged_code_hash(), Some(code_hash.into()));
+    assert_eq!(take_staged_code(), Ok(vec![1, 2, 3, 4, 5]));
+
+    assert_eq!(staged_code_hash(), None);
+    assert_eq!(take_staged_code(), Err(StagingError::NothingStaged));
+}
+
+#[test]
+fn test_partial_staged_code_is_kept() {
+    setup();
+    let code_hash = env::sha256_array([1, 2, 3, 4, 5]);
+
+    stage_code_chunk(code_hash, &[1, 2]);
+
+    assert_eq!(
+        take_staged_code(),
+        Err(StagingError::HashMismatch {
+            declared: code_hash.into(),
+            staged: env::sha256_array([1, 2]).into(),
+        })
+    );
+    assert_eq!(staged_code_hash(), Some(env::sha256_array([1, 2]).into()));
+
+    stage_code_chunk(code_hash, &[3, 4, 5]);
+
+    assert_eq!(take_staged_code(), Ok(vec![1, 2, 3, 4, 5]));
+}
+
+#[test]
+fn test_staging_new_hash_restarts_upload() {
+    setup();
+
+    stage_code_chunk(env::sha256_array([1, 2, 3]), &[1, 2, 9]);
+
+    let code_hash = env::sha256_array([4, 5]);
+    assert_eq!(stage_code_chunk(code_hash, &[4, 5]), 2);
+    assert_eq!(take_staged_code(), Ok(vec![4, 5]));
+    assert!(!env::storage_has_key(
+        &[STAGED_CHUNK_KEY, &0u32.to_le_bytes()].concat()
+    ));
+
+    stage_code_chunk(code_hash, &[4]);
+    discard_staged_code();
+
+    assert_eq!(declared_code_hash(), None);
+    assert_eq!(take_staged_code(), Err(StagingError::NothingStaged));
+}
+
+#[test]
+fn test_update_without_delay_is_not_time_locked() {
+    setup();
+
+    assert_eq!(take_executable_update(&[1, 2, 3], Some(b"{}")), Ok(None));
+    // nothing would consume the proposal
+    assert_eq!(
+        propose_update(env::sha256_array([1, 2, 3]), None),
+        Err(TimelockError::NotTimeLocked)
+    );
+    assert_eq!(pending_updates(), vec![]);
+}
+
+#[test]
+fn test_time_locked_update() {
+    setup_at(1_000);
+    set_update_delay(500);
+    let code_hash = env::sha256_array([1, 2, 3]);
+
+    assert_eq!(
+        take_executable_update(&[1, 2, 3], Some(b"{}")),
+        Err(TimelockError::NotProposed {
+            code_hash: code_hash.into()
+        })
+    );
+
+    let proposal = propose_update(code_hash, Some(b"{}".to_vec())).unwrap();
+    assert_eq!(proposal.executable_at.0, 1_500);
+    assert_eq!(pending_updates(), vec![proposal.clone()]);
+
+    setup_at(1_499);
+    assert_eq!(
+        take_executable_update(&[1, 2, 3], Some(b"{}")),
+        Err(TimelockError::NotYetExecutable {
+            executable_at: 1_500
+        })
+    );
+
+    setup_at(1_500);
+    assert_eq!(
+        take_executable_update(&[1, 2, 3], Some(b"{}")),
+        Ok(Some(proposal))
+    );
+    assert_eq!(pending_updates(), vec![]);
+}
+
+#[test]
+fn test_time_locked_update_binds_migrate_args() {
+    setup_at(1_000);
+    set_update_delay(500);
+    let code_hash = env::sha256_array([1, 2, 3]);
+    let mismatch = Err(TimelockError::MigrateArgsMismatch {
+        code_hash: code_hash.into(),
+    });
+
+    propose_update(code_hash, Some(b"{}".to_vec())).unwrap();
+
+    setup_at(1_500);
+    assert_eq!(
+        take_executable_update(&[1, 2, 3], Some(b"{\"bogus\":1}")),
+        mismatch
+    );
+    // code proposed to be deployed cannot be stashed
+    assert_eq!(take_executable_update(&[1, 2, 3], None), mismatch);
+
+    // and code proposed to be stashed cannot be deployed
+    let proposal = propose_update(code_hash, None).unwrap();
+    assert_eq!(proposal.migrate_args, None);
+    setup_at(2_000);
+    assert_eq!(take_executable_update(&[1, 2, 3], Some(b"{}")), mismatch);
+    assert_eq!(take_executable_update(&[1, 2, 3], None), Ok(Some(proposal)));
+}
+
+#[test]
+fn test_cancel_update() {
+    setup_at(1_000);
+    set_update_delay(500);
+    let code_hash = env::sha256_array([1, 2, 3]);
+    let other_hash = env::sha256_array([4, 5]);
+
+    propose_update(code_hash, Some(b"{}".to_vec())).unwrap();
+    let other = propose_update(other_hash, Some(b"{}".to_vec())).unwrap();
+
+    assert!(cancel_update(code_hash).is_ok());
+    assert_eq!(pending_updates(), vec![other]);
+    assert_eq!(
+        cancel_update(code_hash),
+        Err(TimelockError::NotProposed {
+            code_hash: code_hash.into()
+        })
+    );
+
+    setup_at(2_000);
+    assert!(take_executable_update(&[1, 2, 3], Some(b"{}")).is_err());
+    assert!(take_executable_update(&[4, 5], Some(b"{}")).is_ok());
+}
+
+#[test]
+fn test_payment_policy_resolve() {
+    setup();
+    let payment = NearToken::from_millinear(100);
+
+    for policy in [
+        PaymentPolicy::Strict,
+        PaymentPolicy::DefaultToZero,
+        PaymentPolicy::Quarantine,
+    ] {
+        assert_eq!(policy.resolve(0, Some(payment)), Some(payment));
+    }
+    assert_eq!(
+        PaymentPolicy::DefaultToZero.resolve(0, None),
+        Some(NearToken::from_near(0))
+    );
+    assert_eq!(PaymentPolicy::Quarantine.resolve(0, None), None);
+
+    PaymentPolicy::DefaultToZero.check_lengths(2, 1);
+    PaymentPolicy::Quarantine.check_lengths(1, 2);
+    PaymentPolicy::Strict.check_lengths(2, 2);
+}
+
+#[test]
+#[should_panic(expected = "The message 3 has no payment")]
+fn test_strict_payment_policy_aborts_on_missing_payment() {
+    setup();
+
+    PaymentPolicy::Strict.resolve(3, None);
+}
+
+#[test]
+#[should_panic(expected = "2 messages but 3 payments")]
+fn test_strict_payment_policy_aborts_on_length_mismatch() {
+    setup();
+
+    PaymentPolicy::Strict.check_lengths(2, 3);
+}
+
+#[test]
+fn test_emit_payment_mismatch() {
+    setup();
+
+    emit_payment_mismatch(PaymentPolicy::Quarantine, 0, 0);
+    assert!(get_logs().is_empty());
+
+    emit_payment_mismatch(PaymentPolicy::Quarantine, 1, 2);
+    let logs = get_logs();
+    let event: near_sdk::serde_json::Value =
+        near_sdk::serde_json::from_str(logs[0].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+
+    assert_eq!(
+        event,
+        near_sdk::serde_json::json!({
+            "standard": "migration-kit",
+            "version": "1.0.0",
+            "event": "payment_mismatch",
+            "data": [{"policy": "quarantine", "missing_payments": "1", "orphaned_payments": "2"}],
+        })
+    );
+}
+
+#[test]
+fn test_payment_merge_estimate() {
+    let mut estimate = PaymentMergeEstimate::new(PaymentPolicy::Strict, 2, 2);
+    assert!(estimate.add_message(0, 30));
+    assert!(estimate.add_message(1, 30));
+    estimate.add_payments();
+
+    let report = estimate.report(None);
+    assert_eq!(report.status, DryRunStatus::Pass);
+    assert_eq!(report.error, None);
+    assert_eq!((report.messages, report.payments), (U64(2), U64(2)));
+
+    // each message grows by its payment, whose own record is left for `collect_garbage`
+    assert_eq!(report.storage_delta.0, 2 * 16);
+    assert_eq!(report.retired_keys, U64(2));
+    assert_eq!(
+        report.reclaimable_bytes,
+        U64(2 * record_size(ENTRY_KEY_LEN, 16))
+    );
+
+    let message_gas = storage_read_gas(ENTRY_KEY_LEN, 30).as_gas()
+        + storage_read_gas(ENTRY_KEY_LEN, 16).as_gas()
+        + storage_write_gas(ENTRY_KEY_LEN, 46, 30).as_gas();
+    assert_eq!(report.estimated_gas, Gas::from_gas(2 * message_gas));
+}
+
+#[test]
+fn test_payment_merge_estimate_moving_messages() {
+    let mut estimate = PaymentMergeEstimate::new(PaymentPolicy::Quarantine, 2, 1).moving_messages();
+    assert!(estimate.add_message(0, 30));
+    assert!(!estimate.add_message(1, 30));
+
+    // both messages are written under the new prefixes, the old ones are all retired
+    let report = estimate.report(None);
+    assert_eq!(
+        report.storage_delta.0,
+        (record_size(ENTRY_KEY_LEN, 46) + record_size(ENTRY_KEY_LEN, 40)) as i64
+    );
+    assert_eq!(report.retired_keys, U64(2));
+    assert_eq!(
+        report.reclaimable_bytes,
+        U64(2 * record_size(ENTRY_KEY_LEN, 30))
+    );
+}
+
+#[test]
+fn test_payment_merge_estimate_page() {
+    let mut estimate = PaymentMergeEstimate::new(PaymentPolicy::Strict, 3, 3);
+    assert!(estimate.add_message(0, 30));
+    estimate.add(8, Gas::from_gas(1));
+
+    // a page before the last one leaves the payments out
+    let cursor = Base64VecU8(1u64.to_le_bytes().to_vec());
+    let report = estimate.report(Some(cursor.clone()));
+    assert_eq!(report.messages, U64(3));
+    assert_eq!(report.storage_delta.0, 16 + 8);
+    assert_eq!(report.next_cursor, Some(cursor));
+}
+
+#[test]
+fn test_payment_merge_estimate_mismatch() {
+    let strict = PaymentMergeEstimate::new(PaymentPolicy::Strict, 2, 1).report(None);
+    assert_eq!(strict.status, DryRunStatus::Fail);
+    assert_eq!(
+        strict.error.as_deref(),
+        Some("2 messages but 1 payments, migrate with another payment policy")
+    );
+    assert_eq!(strict.missing_payments, U64(1));
+
+    // the message without a payment is quarantined
+    let mut quarantine = PaymentMergeEstimate::new(PaymentPolicy::Quarantine, 2, 1);
+    assert!(quarantine.add_message(0, 30));
+    assert!(!quarantine.add_message(1, 30));
+    let report = quarantine.report(None);
+    assert_eq!(report.status, DryRunStatus::Pass);
+    assert_eq!(report.storage_delta.0, 16 + 10);
+
+    // or migrated with a zero payment
+    let mut default_to_zero = PaymentMergeEstimate::new(PaymentPolicy::DefaultToZero, 2, 1);
+    assert!(default_to_zero.add_message(1, 30));
+
+    // and a payment past the last message is quarantined
+    let mut orphaned = PaymentMergeEstimate::new(PaymentPolicy::Quarantine, 1, 2);
+    orphaned.add_payments();
+    let report = orphaned.report(None);
+    assert_eq!(report.orphaned_payments, U64(1));
+    assert_eq!(
+        report.storage_delta.0,
+        record_size(ENTRY_KEY_LEN, 26) as i64
+    );
+    assert_eq!(report.retired_keys, U64(2));
+}
+
+#[test]
+fn test_prefix_guard() {
+    let mut guard = PrefixGuard::new().live(b"m").live(b"p");
+
+    assert_eq!(guard.create(b"n"), Ok(b"n".to_vec()));
+    assert_eq!(
+        guard.create(b"p"),
+        Err(PrefixError::Overlap {
+            prefix: b"p".to_vec(),
+            live: b"p".to_vec()
+        })
+    );
+    assert_eq!(
+        guard.create(b"pa".as_slice()),
+        Err(PrefixError::Overlap {
+            prefix: b"pa".to_vec(),
+            live: b"p".to_vec()
+        })
+    );
+    assert_eq!(
+        guard.create(b"n"),
+        Err(PrefixError::Overlap {
+            prefix: b"n".to_vec(),
+            live: b"n".to_vec()
+        })
+    );
+}
+
+#[test]
+fn test_prefix_guard_kit_keys() {
+    let mut guard = PrefixGuard::new();
+
+    assert_eq!(
+        guard.create(b"ST".as_slice()),
+        Err(PrefixError::Overlap {
+            prefix: b"ST".to_vec(),
+            live: STATE_KEY.to_vec()
+        })
+    );
+    assert!(guard.create(b"S").is_err());
+    assert!(guard.create(b"CODE".as_slice()).is_err());
+    assert!(guard.create(b"s").is_ok());
+}
+
+#[test]
+fn test_prefix_guard_replace() {
+    let mut guard = PrefixGuard::new().live(b"m");
+
+    assert_eq!(
+        guard.replace(b"p"),
+        Err(PrefixError::NotLive {
+            prefix: b"p".to_vec()
+        })
+    );
+    assert_eq!(guard.replace(b"m"), Ok(b"m".to_vec()));
+    assert!(guard.create(b"m").is_err());
+}
+
+#[test]
+fn test_prefix_guard_live_vector() {
+    setup();
+    let mut messages: Vector<String> = Vector::new(b"m".to_vec());
+    messages.push(&"hello".to_string());
+
+    assert_eq!(vector_prefix(&messages), b"m".to_vec());
+
+    // the prefix the vector was stored with, whatever the current code would use
+    let mut guard = PrefixGuard::new().live_vector(&messages);
+    assert_eq!(
+        guard.replace(vec![0]),
+        Err(PrefixError::NotLive { prefix: vec![0] })
+    );
+    assert_eq!(guard.replace(b"m"), Ok(b"m".to_vec()));
+}
+
+fn vector_key(prefix: &[u8], idx: u64) -> Vec<u8> {
+    [prefix, &idx.to_le_bytes()].concat()
+}
+
+#[test]
+fn test_collect_garbage() {
+    setup();
+
+    for idx in 0..3 {
+        env::storage_write(&vector_key(b"p", idx), &[1; 16]);
+    }
+    env::storage_write(&vector_key(b"o", 0), &[1; 16]);
+    env::storage_write(&vector_key(b"m", 0), &[1; 16]);
+    retire_vector(b"p", 3);
+    retire_vector(b"o", 1);
+    retire_vector(b"e", 0);
+
+    // the last retired vector goes first, from its last entry
+    let collection = collect_garbage(2);
+    assert_eq!(collection.removed_keys, U64(2));
+    assert_eq!(collection.remaining_keys, U64(2));
+    assert!(!collection.done);
+    assert!(collection.reclaimed_bytes.0 > 0);
+    assert!(!env::storage_has_key(&vector_key(b"o", 0)));
+    assert!(!env::storage_has_key(&vector_key(b"p", 2)));
+    assert_eq!(
+        retired_vectors(),
+        vec![RetiredVector {
+            prefix: b"p".to_vec(),
+            len: U64(2)
+        }]
+    );
+
+    let collection = collect_garbage(10);
+    assert_eq!(collection.removed_keys, U64(2));
+    assert!(collection.done);
+    assert!(!env::storage_has_key(&vector_key(b"p", 0)));
+    assert!(!env::storage_has_key(RETIRED_KEY));
+    // live entries are left alone
+    assert!(env::storage_has_key(&vector_key(b"m", 0)));
+
+    let logs = get_logs();
+    let event: near_sdk::serde_json::Value =
+        near_sdk::serde_json::from_str(logs[1].strip_prefix("EVENT_JSON:").unwrap()).unwrap();
+    assert_eq!(event["event"], "storage_reclaimed");
+    assert_eq!(event["data"][0]["removed_keys"], "2");
+    assert_eq!(
+        event["data"][0]["reclaimed_bytes"],
+        collection.reclaimed_bytes.0.to_string()
+    );
+
+    // nothing left, nothing logged
+    assert_eq!(collect_garbage(10).removed_keys, U64(0));
+    assert_eq!(get_logs().len(), 2);
+}
+
+#[test]
+fn test_prefix_guard_retired_vectors() {
+    setup();
+
+    retire_vector(b"p", 1);
+    let mut guard = PrefixGuard::new();
+
+    assert!(guard.create(b"p").is_err());
+    assert!(guard.create(b"RETIRED".as_slice()).is_err());
+
+    collect_garbage(1);
+    assert!(PrefixGuard::new().create(b"p").is_ok());
+}
+
+#[test]
+fn test_scan_variant() {
+    setup();
+
+    env::storage_write(&vector_key(b"m", 0), &[1, 7]);
+    env::storage_write(&vector_key(b"m", 1), &[0, 7]);
+    env::storage_write(&vector_key(b"m", 2), &[0]);
+    // past the length of the vector
+    env::storage_write(&vector_key(b"m", 3), &[0]);
+
+    assert_eq!(
+        scan_variant(b"m", 3, 0, None, None, None).unwrap(),
+        VariantScan {
+            discriminant: 0,
+            remaining: U64(2),
+            first_index: Some(U64(1)),
+            retirable: false,
+            next_cursor: None,
+        }
+    );
+    assert_eq!(
+        scan_variant(b"m", 3, 2, None, None, None).unwrap(),
+        VariantScan {
+            discriminant: 2,
+            remaining: U64(0),
+            first_index: None,
+            retirable: true,
+            next_cursor: None,
+        }
+    );
+}
+
+#[test]
+fn test_scan_variant_pages() {
+    setup();
+
+    env::storage_write(&vector_key(b"m", 0), &[1, 7]);
+    env::storage_write(&vector_key(b"m", 1), &[0, 7]);
+    env::storage_write(&vector_key(b"m", 2), &[0]);
+
+    // the counts of the first page are carried to the last one
+    let first = scan_variant(b"m", 3, 0, None, Some(U64(2)), None).unwrap();
+    assert_eq!(first.remaining, U64(1));
+    assert!(!first.retirable);
+    let last = scan_variant(b"m", 3, 0, None, Some(U64(2)), first.next_cursor).unwrap();
+    assert_eq!(
+        last,
+        VariantScan {
+            discriminant: 0,
+            remaining: U64(2),
+            first_index: Some(U64(1)),
+            retirable: false,
+            next_cursor: None,
+        }
+    );
+
+    let first = scan_variant(b"m", 3, 2, None, Some(U64(1)), None).unwrap();
+    assert!(!first.retirable);
+    let mut scan = first;
+    while let Some(cursor) = scan.next_cursor.take() {
+        scan = scan_variant(b"m", 3, 2, None, Some(U64(1)), Some(cursor)).unwrap();
+    }
+    assert!(scan.retirable);
+
+    // a scan not starting at the first entry cannot tell the variant is gone
+    let scan = scan_variant(b"m", 3, 2, Some(U128(1)), None, None).unwrap();
+    assert_eq!(scan.remaining, U64(0));
+    assert!(!scan.retirable);
+    assert!(env::storage_read(VARIANT_SCAN_KEY).is_none());
+}
+
+#[test]
+fn test_scan_variant_keeps_progress_on_chain() {
+    setup();
+
+    env::storage_write(&vector_key(b"m", 0), &[0]);
+    env::storage_write(&vector_key(b"m", 1), &[1]);
+    env::storage_write(&vector_key(b"m", 2), &[1]);
+
+    let first = scan_variant(b"m", 3, 0, None, Some(U64(1)), None).unwrap();
+    assert!(env::storage_read(VARIANT_SCAN_KEY).is_some());
+    // a cursor skipping entries does not continue the scan
+    assert_eq!(
+        scan_variant(
+            b"m",
+            3,
+            0,
+            None,
+            None,
+            Some(Base64VecU8(2u64.to_le_bytes().to_vec()))
+        ),
+        Err(ScanError::StaleCursor)
+    );
+    // nor does one of another variant
+    assert_eq!(
+        scan_variant(b"m", 3, 1, None, None, first.next_cursor.clone()),
+        Err(ScanError::StaleCursor)
+    );
+
+    let last = scan_variant(b"m", 3, 0, None, None, first.next_cursor.clone()).unwrap();
+    assert_eq!(last.remaining, U64(1));
+    assert!(last.next_cursor.is_none());
+    // the last page ends the scan
+    assert_eq!(
+        scan_variant(b"m", 3, 0, None, None, first.next_cursor),
+        Err(ScanError::StaleCursor)
+    );
+}
+
+#[test]
+fn test_scan_variant_missing_entry() {
+    setup();
+
+    env::storage_write(&vector_key(b"m", 0), &[1]);
+    env::storage_write(&vector_key(b"m", 2), &[1]);
+
+    assert_eq!(
+        scan_variant(b"m", 3, 0, None, None, None),
+        Err(ScanError::MissingEntry { index: 1 })
+    );
+}
+
+#[test]
+fn test_paginate() {
+    setup();
+
+    let mut read = vec![];
+    let page = paginate(25, None, None, None, |idx| {
+        read.push(idx);
+        Some(idx)
+    });
+    assert_eq!(page.items, (0..10).collect::<Vec<_>>());
+    assert_eq!(read, page.items);
+
+    // the cursor resumes where the previous page stopped, reading only the new entries
+    read.clear();
+    let page = paginate(25, None, Some(U64(10)), page.next_cursor, |idx| {
+        read.push(idx);
+        Some(idx)
+    });
+    assert_eq!(page.items, (10..20).collect::<Vec<_>>());
+    assert_eq!(read, page.items);
+
+    let last = paginate(25, None, None, page.next_cursor, Some);
+    assert_eq!(
+        last,
+        Page {
+            items: (20..25).collect(),
+            next_cursor: None,
+        }
+    );
+
+    let deep = paginate(25, Some(U128(23)), Some(U64(MAX_PAGE_LIMIT)), None, Some);
+    assert_eq!(deep.items, vec![23, 24]);
+    let past_end = paginate(25, Some(U128(40)), None, None, Some);
+    assert!(past_end.items.is_empty());
+    assert_eq!(past_end.next_cursor, None);
+}
+
+#[test]
+fn test_paginate_newest_first() {
+    setup();
+
+    let newest = Order::NewestFirst;
+    let page = paginate_ordered(25, newest, None, None, None, Some);
+    assert_eq!(page.items, (15..25).rev().collect::<Vec<_>>());
+
+    // entries pushed after the first page do not shift the next ones
+    let page = paginate_ordered(30, newest, None, None, page.next_cursor, Some);
+    assert_eq!(page.items, (5..15).rev().collect::<Vec<_>>());
+    let last = paginate_ordered(30, newest, None, None, page.next_cursor, Some);
+    assert_eq!(
+        last,
+        Page {
+            items: (0..5).rev().collect(),
+            next_cursor: None,
+        }
+    );
+
+    // `from_index` counts from the newest entry
+    let page = paginate_ordered(25, newest, Some(U128(20)), None, None, Some);
+    assert_eq!(page.items, vec![4, 3, 2, 1, 0]);
+    let page = paginate_ordered(25, newest, Some(U128(40)), None, None, Some);
+    assert!(page.items.is_empty());
+
+    // filtered out entries still count toward the limit
+    let page = paginate_ordered(25, newest, None, Some(U64(5)), None, |idx| {
+        (idx % 2 == 0).then_some(idx)
+    });
+    assert_eq!(page.items, vec![24, 22, 20]);
+    assert!(page.next_cursor.is_some());
+}
+
+#[test]
+#[should_panic(expected = "limit must not exceed 100")]
+fn test_paginate_limit_is_capped() {
+    setup();
+
+    paginate(500, None, Some(U64(MAX_PAGE_LIMIT + 1)), None, Some);
+}
+
+#[test]
+#[should_panic(expected = "Invalid cursor")]
+fn test_paginate_invalid_cursor() {
+    setup();
+
+    paginate(500, None, None, Some(Base64VecU8(vec![1, 2])), Some);
+}
+
+#[test]
+#[should_panic(expected = "Pass either from_index or cursor")]
+fn test_paginate_index_and_cursor() {
+    setup();
+
+    let cursor = paginate(500, None, None, None, Some).next_cursor;
+    paginate(500, Some(U128(0)), None, cursor, Some);
+}
@@ -0,0 +1,214 @@
+use migration_kit::{expect_state, MigrationStatus, StateMigrations, VERSION_KEY};
+use near_sdk::borsh::{BorshDeserialize, BorshSerialize};
+use near_sdk::test_utils::VMContextBuilder;
+use near_sdk::{env, testing_env, Gas};
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+#[borsh(crate = "near_sdk::borsh")]
+struct BookV1 {
+    messages: Vec<String>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+#[borsh(crate = "near_sdk::borsh")]
+struct BookV2 {
+    messages: Vec<String>,
+    owner: String,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+#[borsh(crate = "near_sdk::borsh")]
+struct BookV3 {
+    messages: Vec<(String, u128)>,
+    owner: String,
+}
+
+fn add_owner(old: BookV1) -> BookV2 {
+    BookV2 {
+        messages: old.messages,
+        owner: "bob.near".to_string(),
+    }
+}
+
+impl From<BookV2> for BookV3 {
+    fn from(old: BookV2) -> Self {
+        Self {
+            messages: old.messages.into_iter().map(|m| (m, 0)).collect(),
+            owner: old.owner,
+        }
+    }
+}
+
+#[derive(StateMigrations, Debug, PartialEq, Eq)]
+#[migrations(version = BookVersion)]
+enum BookState {
+    V1(BookV1),
+    #[migrate(with = add_owner)]
+    V2(BookV2),
+    V3(BookV3),
+}
+
+// The same chain, taking the owner from the migration arguments
+struct OwnerArgs {
+    owner: String,
+}
+
+fn add_owner_from_args(old: BookV1, args: &OwnerArgs) -> BookV2 {
+    BookV2 {
+        messages: old.messages,
+        owner: args.owner.clone(),
+    }
+}
+
+#[derive(StateMigrations)]
+#[migrations(version = ArgsBookVersion, args = OwnerArgs)]
+enum ArgsBookState {
+    V1(BookV1),
+    #[migrate(with = add_owner_from_args)]
+    V2(BookV2),
+    V3(BookV3),
+}
+
+fn setup() {
+    testing_env!(VMContextBuilder::new()
+        .prepaid_gas(Gas::from_tgas(300))
+        .build());
+    env::state_write(&BookV1 {
+        messages: vec!["hello".to_string()],
+    });
+}
+
+#[test]
+fn test_unversioned_state_is_the_first_version() {
+    setup();
+
+    assert_eq!(BookVersion::read(), BookVersion::V1);
+    assert_eq!(
+        BookState::read(),
+        BookState::V1(BookV1 {
+            messages: vec!["hello".to_string()]
+        })
+    );
+}
+
+#[test]
+fn test_migrate_step_by_step() {
+    setup();
+
+    assert_eq!(BookState::migrate_step(), Some(BookVersion::V2));
+    assert_eq!(BookVersion::read(), BookVersion::V2);
+    assert_eq!(
+        expect_state::<BookV2>(),
+        BookV2 {
+            messages: vec!["hello".to_string()],
+            owner: "bob.near".to_string(),
+        }
+    );
+
+    assert_eq!(BookState::migrate_step(), Some(BookVersion::V3));
+    assert_eq!(BookState::migrate_step(), None);
+    assert_eq!(BookVersion::read(), BookVersion::LATEST);
+    assert_eq!(
+        BookState::read(),
+        BookState::V3(BookV3 {
+            messages: vec![("hello".to_string(), 0)],
+            owner: "bob.near".to_string(),
+        })
+    );
+}
+
+#[test]
+fn test_migrate_to_latest() {
+    setup();
+
+    assert_eq!(BookState::migrate_to_latest(), BookVersion::V3);
+    assert_eq!(
+        expect_state::<BookV3>(),
+        BookV3 {
+            messages: vec![("hello".to_string(), 0)],
+            owner: "bob.near".to_string(),
+        }
+    );
+}
+
+#[test]
+fn test_version_layout_is_stable() {
+    setup();
+
+    // versions are stored as their position, like a hand-written `#[near]` enum
+    BookVersion::V2.write();
+    assert_eq!(env::storage_read(VERSION_KEY), Some(vec![1]));
+    assert_eq!(BookVersion::V1.next(), Some(BookVersion::V2));
+    assert_eq!(BookVersion::V3.next(), None);
+}
+
+#[test]
+fn test_compile_errors() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
+
+#[test]
+fn test_migrate_within_gas() {
+    setup();
+
+    let report = BookState::migrate_within_gas(Gas::from_tgas(0));
+
+    assert_eq!(report.start_version, BookVersion::V1);
+    assert_eq!(report.end_version, BookVersion::V3);
+    assert_eq!(report.hops_applied, 2);
+    assert!(report.gas_used > Gas::from_gas(0));
+    assert_eq!(report.status, MigrationStatus::Done);
+
+    // nothing left to do
+    let report = BookState::migrate_within_gas(Gas::from_tgas(0));
+    assert_eq!(report.start_version, BookVersion::V3);
+    assert_eq!(report.hops_applied, 0);
+    assert_eq!(report.status, MigrationStatus::Done);
+}
+
+#[test]
+fn test_migrate_within_gas_out_of_budget() {
+    setup();
+
+    // the margin exceeds the prepaid gas, so no hop can be applied
+    let report = BookState::migrate_within_gas(Gas::from_tgas(300));
+
+    assert_eq!(report.start_version, BookVersion::V1);
+    assert_eq!(report.end_version, BookVersion::V1);
+    assert_eq!(report.hops_applied, 0);
+    assert_eq!(report.status, MigrationStatus::NeedsMigration);
+    assert_eq!(BookVersion::read(), BookVersion::V1);
+
+    assert_eq!(
+        near_sdk::serde_json::to_value(&report).unwrap(),
+        near_sdk::serde_json::json!({
+            "start_version": "V1",
+            "end_version": "V1",
+            "hops_applied": 0,
+            "gas_used": report.gas_used,
+            "status": "needs-migration",
+        })
+    );
+}
+
+#[test]
+fn test_migrate_with_args() {
+    setup();
+
+    let args = OwnerArgs {
+        owner: "alice.near".to_string(),
+    };
+    assert_eq!(
+        ArgsBookState::migrate_step(&args),
+        Some(ArgsBookVersion::V2)
+    );
+    assert_eq!(ArgsBookState::migrate_to_latest(&args), ArgsBookVersion::V3);
+    assert_eq!(
+        expect_state::<BookV3>(),
+        BookV3 {
+            messages: vec![("hello".to_string(), 0)],
+            owner: "alice.near".to_string(),
+        }
+    );
+}
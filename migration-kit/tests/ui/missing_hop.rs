@@ -0,0 +1,21 @@
+use migration_kit::StateMigrations;
+use near_sdk::near;
+
+#[near]
+struct BookV1 {
+    messages: Vec<String>,
+}
+
+#[near]
+struct BookV2 {
+    messages: Vec<String>,
+    owner: String,
+}
+
+#[derive(StateMigrations)]
+enum BookState {
+    V1(BookV1),
+    V2(BookV2),
+}
+
+fn main() {}
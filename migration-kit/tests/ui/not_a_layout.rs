@@ -0,0 +1,9 @@
+use migration_kit::StateMigrations;
+
+#[derive(StateMigrations)]
+enum BookState {
+    V1,
+    V2 { messages: Vec<String> },
+}
+
+fn main() {}
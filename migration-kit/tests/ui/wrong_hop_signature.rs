@@ -0,0 +1,26 @@
+use migration_kit::StateMigrations;
+use near_sdk::near;
+
+#[near]
+struct BookV1 {
+    messages: Vec<String>,
+}
+
+#[near]
+struct BookV2 {
+    messages: Vec<String>,
+    owner: String,
+}
+
+fn add_owner(old: BookV2) -> BookV2 {
+    old
+}
+
+#[derive(StateMigrations)]
+enum BookState {
+    V1(BookV1),
+    #[migrate(with = add_owner)]
+    V2(BookV2),
+}
+
+fn main() {}
@@ -0,0 +1,44 @@
+use migration_kit::{paginate, vector_prefix, DryRunReport, PaymentMergeEstimate, PaymentPolicy};
+use near_sdk::json_types::Base64VecU8;
+use near_sdk::IntoStorageKey;
+
+use crate::*;
+
+#[near]
+impl GuestBook {
+    /// Checks the messages can be merged with their payments by the update, with
+    /// `payment_policy`, and estimates the cost of merging a page of them.
+    ///
+    /// The update deploys and migrates in one call, so the check runs on the code it replaces.
+    pub fn migration_dry_run(
+        &self,
+        payment_policy: Option<PaymentPolicy>,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> DryRunReport {
+        let messages = self.messages.len();
+        let mut estimate = PaymentMergeEstimate::new(
+            payment_policy.unwrap_or_default(),
+            messages,
+            self.payments.len(),
+        );
+        // messages stored by the first base contracts, under `b"m"`, move to the new prefix
+        if vector_prefix(&self.messages) != StorageKey::Messages.into_storage_key() {
+            estimate = estimate.moving_messages();
+        }
+
+        let page = paginate(messages, from_index, limit, cursor, |idx| {
+            let posted = self.messages.get(idx)?;
+            let len = near_sdk::borsh::to_vec(&posted).map_or(0, |data| data.len() as u64);
+            estimate.add_message(idx, len);
+            None::<()>
+        });
+
+        // then the payments are retired, counted with the last page
+        if page.next_cursor.is_none() {
+            estimate.add_payments();
+        }
+        estimate.report(page.next_cursor)
+    }
+}
@@ -1,32 +1,132 @@
-use near_sdk::{Gas, Promise};
+use migration_kit::{DeployAndMigrate, UpdateProposal};
+use near_sdk::json_types::{Base58CryptoHash, U64};
+use near_sdk::serde_json::{self, json};
+use near_sdk::{CryptoHash, Promise};
 
 use crate::*;
 
-const NO_ARGS: Vec<u8> = vec![];
-const CALL_GAS: Gas = Gas::from_tgas(200); // 200 TGAS
-
-#[near]
 impl GuestBook {
-    pub fn update_contract(&self) -> Promise {
+    fn assert_manager(&self) {
         // Check the caller is authorized to update the code
         assert!(
             env::predecessor_account_id() == self.manager,
             "Only the manager can update the code"
         );
+    }
+
+    fn deploy_and_migrate(code: Vec<u8>, migrate_args: serde_json::Value) -> Promise {
+        // Time locked contracts only deploy code proposed long enough ago, with these arguments
+        let migrate_args = migrate_args.to_string().into_bytes();
+        migration_kit::take_executable_update(&code, Some(&migrate_args))
+            .unwrap_or_else(|err| err.panic());
+
+        // Deploy the contract on self and call its `migrate` method from a separate
+        // receipt, then check the outcome in `on_update`, which runs on the new code
+        DeployAndMigrate::new(code)
+            .args(migrate_args)
+            .with_rollback("on_update")
+            .build()
+            .as_return()
+    }
+}
+
+#[near]
+impl GuestBook {
+    pub fn update_contract(&self) -> Promise {
+        self.assert_manager();
 
         // Receive the code directly from the input to avoid the
         // GAS overhead of deserializing parameters
         let code = env::input().expect("Error: No input").to_vec();
 
-        // Deploy the contract on self
-        Promise::new(env::current_account_id())
-            .deploy_contract(code)
-            .function_call(
-                "migrate".to_string(),
-                NO_ARGS,
-                NearToken::from_near(0),
-                CALL_GAS,
-            )
-            .as_return()
+        Self::deploy_and_migrate(code, json!({}))
+    }
+
+    /// Appends a chunk to the staged code, whose complete sha256 must be `code_hash`,
+    /// returning the number of bytes staged. A new `code_hash` starts a new upload.
+    pub fn stage_code_chunk(
+        &mut self,
+        #[serializer(borsh)] code_hash: CryptoHash,
+        #[serializer(borsh)] chunk: Vec<u8>,
+    ) -> U64 {
+        self.assert_manager();
+        U64(migration_kit::stage_code_chunk(code_hash, &chunk))
+    }
+
+    /// Deploys the staged code once it matches its declared hash, passing `migrate_args`
+    /// to its `migrate` method
+    pub fn deploy_staged_code(&mut self, migrate_args: Option<serde_json::Value>) -> Promise {
+        self.assert_manager();
+
+        let code = migration_kit::take_staged_code().unwrap_or_else(|err| err.panic());
+        Self::deploy_and_migrate(code, migrate_args.unwrap_or(json!({})))
+    }
+
+    pub fn discard_staged_code(&mut self) {
+        self.assert_manager();
+        migration_kit::discard_staged_code();
+    }
+
+    /// Hash of the code staged so far
+    pub fn get_staged_code_hash() -> Option<Base58CryptoHash> {
+        migration_kit::staged_code_hash()
+    }
+
+    /// Announces the code hashing to `code_hash`, which can be deployed with `migrate_args`, `{}`
+    /// by default, once the update delay elapsed. Fails if updates are not time locked
+    pub fn propose_update(
+        &mut self,
+        code_hash: Base58CryptoHash,
+        migrate_args: Option<serde_json::Value>,
+    ) -> UpdateProposal {
+        self.assert_manager();
+        let migrate_args = migrate_args.unwrap_or(json!({})).to_string().into_bytes();
+        migration_kit::propose_update(code_hash.into(), Some(migrate_args))
+            .unwrap_or_else(|err| err.panic())
+    }
+
+    /// Announces the code hashing to `code_hash` as the code to stash, which can be stashed
+    /// once the update delay elapsed. Fails if updates are not time locked
+    pub fn propose_stash(&mut self, code_hash: Base58CryptoHash) -> UpdateProposal {
+        self.assert_manager();
+        migration_kit::propose_update(code_hash.into(), None).unwrap_or_else(|err| err.panic())
+    }
+
+    pub fn cancel_update(&mut self, code_hash: Base58CryptoHash) -> UpdateProposal {
+        self.assert_manager();
+        migration_kit::cancel_update(code_hash.into()).unwrap_or_else(|err| err.panic())
+    }
+
+    pub fn get_pending_updates() -> Vec<UpdateProposal> {
+        migration_kit::pending_updates()
+    }
+
+    /// Nanoseconds between proposing and deploying an update, `None` if updates are not time locked
+    pub fn get_update_delay() -> Option<U64> {
+        migration_kit::update_delay().map(U64)
+    }
+
+    /// Stashes the code the contract runs, received as raw input, to roll back to
+    /// if the migration of an update fails. Updates fail until a code is stashed, which is only
+    /// done once, later stashes come from the updates themselves.
+    pub fn stash_code(&mut self) {
+        self.assert_manager();
+        let code = env::input().expect("Error: No input");
+
+        // Time locked contracts only stash code proposed as the stash long enough ago
+        migration_kit::take_executable_update(&code, None).unwrap_or_else(|err| err.panic());
+        migration_kit::stash_code(&code);
+    }
+
+    #[private]
+    pub fn on_update() -> bool {
+        // The new code was deployed on its own receipt, so if `migrate` failed the
+        // stashed code is redeployed to run on the old state
+        migration_kit::resolve_update()
+    }
+
+    /// Hash of the code the contract rolls back to, the last one successfully deployed
+    pub fn get_code_hash() -> Option<Base58CryptoHash> {
+        migration_kit::stashed_code_hash()
     }
 }
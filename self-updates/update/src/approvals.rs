@@ -0,0 +1,141 @@
+use near_sdk::json_types::Base58CryptoHash;
+use near_sdk::CryptoHash;
+
+use crate::*;
+
+#[near(serializers = [json])]
+pub struct ApproverSet {
+    pub approvers: Vec<AccountId>,
+    /// Number of approvers needed to update the code or replace the set
+    pub threshold: u32,
+}
+
+impl GuestBook {
+    pub(crate) fn assert_approver(&self) {
+        assert!(
+            self.approvers.contains(&env::predecessor_account_id()),
+            "Only an approver can update the code"
+        );
+    }
+
+    /// Current approvers who approved `hash`, counting the caller
+    fn approvals_with_caller(&self, hash: &CryptoHash) -> Vec<AccountId> {
+        let mut approvals = self.approvals.get(hash).unwrap_or_default();
+        approvals.retain(|approver| self.approvers.contains(approver));
+
+        let caller = env::predecessor_account_id();
+        if !approvals.contains(&caller) {
+            approvals.push(caller);
+        }
+        approvals
+    }
+
+    /// Checks the caller and the previous approvals of `hash` reach the threshold,
+    /// consuming them
+    pub(crate) fn take_approvals(&mut self, hash: &CryptoHash) {
+        self.assert_approver();
+
+        let approvals = self.approvals_with_caller(hash);
+        assert!(
+            approvals.len() >= self.threshold as usize,
+            "{} of {} approvals",
+            approvals.len(),
+            self.threshold
+        );
+        self.approvals.remove(hash);
+    }
+}
+
+/// Checks the approvers are unique and `threshold` of them can be reached
+pub(crate) fn assert_valid_approvers(approvers: &[AccountId], threshold: u32) {
+    let mut unique = approvers.to_vec();
+    unique.sort();
+    unique.dedup();
+    assert!(
+        unique.len() == approvers.len(),
+        "The approvers must be unique"
+    );
+    assert!(
+        threshold > 0 && threshold as usize <= approvers.len(),
+        "The threshold must be between 1 and the number of approvers"
+    );
+}
+
+/// Hash approved to deploy the code hashing to `code_hash` and migrate it with `migrate_args`,
+/// the bare code hash being the one approved to stash it
+pub(crate) fn update_hash(code_hash: &CryptoHash, migrate_args: &[u8]) -> CryptoHash {
+    let data =
+        near_sdk::borsh::to_vec(&(code_hash, migrate_args)).expect("Cannot serialize the update");
+    env::sha256_array(data)
+}
+
+fn approver_set_hash(approvers: &[AccountId], threshold: u32) -> CryptoHash {
+    let data =
+        near_sdk::borsh::to_vec(&(approvers, threshold)).expect("Cannot serialize the approvers");
+    env::sha256_array(data)
+}
+
+#[near]
+impl GuestBook {
+    /// Approves the update hashing to `hash`, see `get_update_hash`, stashing the code hashing
+    /// to it, or replacing the approvers with the set hashing to it
+    pub fn approve(&mut self, hash: Base58CryptoHash) -> Vec<AccountId> {
+        self.assert_approver();
+
+        let hash = CryptoHash::from(hash);
+        let approvals = self.approvals_with_caller(&hash);
+        self.approvals.insert(&hash, &approvals);
+        approvals
+    }
+
+    pub fn revoke_approval(&mut self, hash: Base58CryptoHash) -> Vec<AccountId> {
+        self.assert_approver();
+
+        let hash = CryptoHash::from(hash);
+        let caller = env::predecessor_account_id();
+        let mut approvals = self.approvals.get(&hash).unwrap_or_default();
+        approvals.retain(|approver| approver != &caller);
+
+        if approvals.is_empty() {
+            self.approvals.remove(&hash);
+        } else {
+            self.approvals.insert(&hash, &approvals);
+        }
+        approvals
+    }
+
+    pub fn get_approvals(&self, hash: Base58CryptoHash) -> Vec<AccountId> {
+        self.approvals.get(&hash.into()).unwrap_or_default()
+    }
+
+    pub fn get_approvers(&self) -> ApproverSet {
+        ApproverSet {
+            approvers: self.approvers.clone(),
+            threshold: self.threshold,
+        }
+    }
+
+    /// Hash to approve before deploying the code hashing to `code_hash` with `migrate_args`,
+    /// `{}` by default
+    pub fn get_update_hash(
+        code_hash: Base58CryptoHash,
+        migrate_args: Option<near_sdk::serde_json::Value>,
+    ) -> Base58CryptoHash {
+        let migrate_args = migrate_args.unwrap_or_else(|| near_sdk::serde_json::json!({}));
+        update_hash(&code_hash.into(), migrate_args.to_string().as_bytes()).into()
+    }
+
+    /// Hash to approve before calling `set_approvers` with these arguments
+    pub fn get_approver_set_hash(approvers: Vec<AccountId>, threshold: u32) -> Base58CryptoHash {
+        approver_set_hash(&approvers, threshold).into()
+    }
+
+    /// Replaces the approvers once enough of the current ones approved the new set
+    pub fn set_approvers(&mut self, approvers: Vec<AccountId>, threshold: u32) {
+        assert_valid_approvers(&approvers, threshold);
+
+        self.take_approvals(&approver_set_hash(&approvers, threshold));
+        self.approvers = approvers;
+        self.threshold = threshold;
+    }
+}
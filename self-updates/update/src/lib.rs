@@ -1,15 +1,30 @@
-use near_sdk::near;
+use near_sdk::{near, BorshStorageKey};
 
-use near_sdk::collections::Vector;
-use near_sdk::json_types::{U64, U128};
+use near_sdk::borsh::BorshSerialize;
 
-use near_sdk::{env, AccountId, NearToken, PanicOnDefault};
+use migration_kit::{paginate, Page};
+use near_sdk::collections::{LookupMap, Vector};
+use near_sdk::json_types::{Base64VecU8, U128, U64};
 
+use near_sdk::{env, AccountId, CryptoHash, NearToken, PanicOnDefault};
+
+mod approvals;
 mod migrate;
 mod update;
 
 const POINT_ONE: NearToken = NearToken::from_millinear(100);
 
+// Variants are never removed nor reordered, so no collection reuses the prefix of an older one
+#[derive(BorshSerialize, BorshStorageKey)]
+#[borsh(crate = "near_sdk::borsh")]
+pub enum StorageKey {
+    Messages,
+    // payments of the base contract, removed by the migration
+    Payments,
+    Quarantine,
+    Approvals,
+}
+
 #[near(serializers = [borsh, json])]
 pub struct PostedMessage {
     pub payment: NearToken,
@@ -22,16 +37,26 @@ pub struct PostedMessage {
 #[derive(PanicOnDefault)]
 pub struct GuestBook {
     messages: Vector<PostedMessage>,
-    manager: AccountId,
+    // entries set aside by the migration, see `PaymentPolicy::Quarantine`
+    quarantine: Vector<migration_kit::Quarantined<migrate::OldPostedMessage>>,
+    approvers: Vec<AccountId>,
+    threshold: u32,
+    // approvals of code and approver set hashes
+    approvals: LookupMap<CryptoHash, Vec<AccountId>>,
 }
 
 #[near]
 impl GuestBook {
     #[init]
-    pub fn init(manager: AccountId) -> Self {
+    pub fn init(approvers: Vec<AccountId>, threshold: u32) -> Self {
+        approvals::assert_valid_approvers(&approvers, threshold);
+
         Self {
-            messages: Vector::new(b"m"),
-            manager,
+            messages: Vector::new(StorageKey::Messages),
+            quarantine: Vector::new(StorageKey::Quarantine),
+            approvers,
+            threshold,
+            approvals: LookupMap::new(StorageKey::Approvals),
         }
     }
 
@@ -49,13 +74,18 @@ impl GuestBook {
         self.messages.push(&message);
     }
 
-    pub fn get_messages(&self, from_index: Option<U128>, limit: Option<U64>) -> Vec<PostedMessage> {
-        let from = u128::from(from_index.unwrap_or(U128(0)));
+    pub fn get_messages(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> Page<PostedMessage> {
+        paginate(self.messages.len(), from_index, limit, cursor, |idx| {
+            self.messages.get(idx)
+        })
+    }
 
-        self.messages
-            .iter()
-            .skip(from as usize)
-            .take(u64::from(limit.unwrap_or(U64::from(10))) as usize)
-            .collect()
+    pub fn total_messages(&self) -> U64 {
+        U64(self.messages.len())
     }
 }
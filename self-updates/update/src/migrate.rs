@@ -1,6 +1,9 @@
+use migration_kit::{vector_prefix, GarbageCollection, PaymentPolicy, PrefixGuard, Quarantined};
+use near_sdk::IntoStorageKey;
+
 use crate::*;
 
-#[near(serializers = [borsh])]
+#[near(serializers = [borsh, json])]
 pub struct OldPostedMessage {
     pub premium: bool,
     pub sender: AccountId,
@@ -18,31 +21,120 @@ pub struct OldState {
 impl GuestBook {
     #[private]
     #[init(ignore_state)]
-    pub fn migrate() -> Self {
-        let old_state: OldState = env::state_read().expect("failed");
-        let mut new_messages: Vector<PostedMessage> = Vector::new(b"p");
+    pub fn migrate(payment_policy: Option<PaymentPolicy>) -> Self {
+        let old_state: OldState = migration_kit::expect_state();
+
+        // the new messages overwrite the old ones one by one when they share their prefix,
+        // every other collection must stay clear of the old entries, live under the prefixes
+        // they were stored with
+        let old_messages_prefix = vector_prefix(&old_state.messages);
+        let in_place = old_messages_prefix == StorageKey::Messages.into_storage_key();
+        let mut prefixes = PrefixGuard::new()
+            .live_vector(&old_state.messages)
+            .live_vector(&old_state.payments);
+        let new_messages_prefix = if in_place {
+            prefixes.replace(StorageKey::Messages)
+        } else {
+            prefixes.create(StorageKey::Messages)
+        };
+        let mut new_messages: Vector<PostedMessage> =
+            Vector::new(new_messages_prefix.unwrap_or_else(|err| err.panic()));
+        let mut quarantine = Vector::new(
+            prefixes
+                .create(StorageKey::Quarantine)
+                .unwrap_or_else(|err| err.panic()),
+        );
+        let approvals = LookupMap::new(
+            prefixes
+                .create(StorageKey::Approvals)
+                .unwrap_or_else(|err| err.panic()),
+        );
+
+        let messages = old_state.messages.len();
+        let payments = old_state.payments.len();
+        let policy = payment_policy.unwrap_or_default();
+        policy.check_lengths(messages, payments);
 
         // iterate through the messages of the previous state
         for (idx, posted) in old_state.messages.iter().enumerate() {
             // get the payment using the message index
-            let payment = old_state
-                .payments
-                .get(idx as u64)
-                .unwrap_or(NearToken::from_near(0));
-
-            // Create a PostedMessage with the new format and push it
-            new_messages.push(&PostedMessage {
-                payment,
-                premium: posted.premium,
-                sender: posted.sender,
-                text: posted.text,
-            })
+            let payment = old_state.payments.get(idx as u64);
+
+            // Create a PostedMessage with the new format and push it, unless the
+            // policy quarantines messages without payment
+            match policy.resolve(idx as u64, payment) {
+                Some(payment) => new_messages.push(&PostedMessage {
+                    payment,
+                    premium: posted.premium,
+                    sender: posted.sender,
+                    text: posted.text,
+                }),
+                None => quarantine.push(&Quarantined {
+                    index: U64(idx as u64),
+                    message: Some(posted),
+                    payment: None,
+                }),
+            }
+
+            // quarantined messages leave old messages past the migrated ones when they share
+            // their prefix, removed as the loop reads them rather than in a pass of their own
+            if in_place && idx as u64 >= new_messages.len() {
+                let key = [old_messages_prefix.clone(), idx.to_le_bytes().to_vec()];
+                env::storage_remove(&key.concat());
+            }
+        }
+
+        // payments past the last message have nothing to be migrated with
+        if policy == PaymentPolicy::Quarantine {
+            for idx in messages..payments {
+                quarantine.push(&Quarantined {
+                    index: U64(idx),
+                    message: None,
+                    payment: old_state.payments.get(idx),
+                });
+            }
         }
+        migration_kit::emit_payment_mismatch(
+            policy,
+            messages.saturating_sub(payments),
+            payments.saturating_sub(messages),
+        );
 
-        // return the new state
+        // old messages under another prefix, e.g. `b"m"` of the first base contracts, are all
+        // left behind
+        if !in_place {
+            migration_kit::retire_vector(old_messages_prefix, messages);
+        }
+
+        // the payments are left for `collect_garbage`, clearing them all at once could run
+        // out of gas on a large guest book
+        migration_kit::retire_vector(vector_prefix(&old_state.payments), payments);
+
+        // the single manager becomes the only approver
         Self {
             messages: new_messages,
-            manager: old_state.manager,
+            quarantine,
+            approvers: vec![old_state.manager],
+            threshold: 1,
+            approvals,
         }
     }
+
+    /// Messages and payments set aside by a migration with the `quarantine` policy
+    pub fn get_quarantined(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<U64>,
+        cursor: Option<Base64VecU8>,
+    ) -> Page<Quarantined<OldPostedMessage>> {
+        paginate(self.quarantine.len(), from_index, limit, cursor, |idx| {
+            self.quarantine.get(idx)
+        })
+    }
+
+    /// Removes up to `limit` entries left behind by the migration, 100 by default
+    #[private]
+    pub fn collect_garbage(&mut self, limit: Option<U64>) -> GarbageCollection {
+        migration_kit::collect_garbage(limit.unwrap_or(U64(100)).0)
+    }
 }
@@ -1,34 +1,83 @@
 use std::fs;
 
-use near_sdk::json_types::U128;
+use migration_kit::Page;
+use near_sdk::json_types::{I64, U128, U64};
 use near_sdk::AccountId;
+use near_workspaces::network::Sandbox;
+use near_workspaces::operations::Function;
 use near_workspaces::types::{Gas, NearToken};
 use near_workspaces::Account;
-use near_workspaces::Contract;
+use near_workspaces::{Contract, ContractState, Worker};
 use rstest::{fixture, rstest};
 use serde_json::json;
+use sha2::{Digest, Sha256};
 
 const FIVE_NEAR: NearToken = NearToken::from_near(5);
+const TEN_NEAR: NearToken = NearToken::from_near(10);
 const ONE_TENTH_NEAR: NearToken = NearToken::from_millinear(100);
 const NINE_HUNDREDTH_NEAR: NearToken = NearToken::from_millinear(90);
+const ONE_MINUTE: u64 = 60_000_000_000;
+
+// Prefixes of the vectors, the borsh encoding of their `StorageKey` variant
+const MESSAGES_PREFIX: &[u8] = &[0];
+const PAYMENTS_PREFIX: &[u8] = &[1];
 
 struct Common {
+    sandbox: Worker<Sandbox>,
     contract: Contract,
+    base_wasm: Vec<u8>,
     alice: Account,
     bob: Account,
     guest_book: Account,
 }
 
-#[fixture]
-async fn base_contract() -> Common {
+async fn deployed_code_hash(contract: &Contract) -> Option<String> {
+    match contract.view_account().await.unwrap().contract_state {
+        ContractState::LocalHash(hash) => Some(hash.to_string()),
+        _ => None,
+    }
+}
+
+async fn stage_code(
+    base_contract: &Common,
+    code_hash: [u8; 32],
+    chunks: &[&[u8]],
+) -> Result<(), Box<dyn std::error::Error>> {
+    for chunk in chunks {
+        let stage_outcome = base_contract
+            .alice
+            .call(base_contract.guest_book.id(), "stage_code_chunk")
+            .args_borsh((code_hash, chunk.to_vec()))
+            .gas(Gas::from_tgas(300))
+            .transact()
+            .await?;
+
+        assert!(stage_outcome.is_success());
+    }
+    Ok(())
+}
+
+async fn deploy_base(update_delay: Option<u64>) -> Common {
     let sandbox = near_workspaces::sandbox().await.unwrap();
 
     fs::create_dir_all("../../target/near/self_base").unwrap();
     let contract_wasm = near_workspaces::compile_project("../base").await.unwrap();
 
     let root = sandbox.root_account().unwrap();
-    let alice = root.create_subaccount("alice").initial_balance(FIVE_NEAR).transact().await.unwrap().unwrap();
-    let guest_book_account = root.create_subaccount("gbook").initial_balance(FIVE_NEAR).transact().await.unwrap().unwrap();
+    let alice = root
+        .create_subaccount("alice")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
+    let guest_book_account = root
+        .create_subaccount("gbook")
+        .initial_balance(TEN_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
 
     let contract = guest_book_account
         .deploy(&contract_wasm)
@@ -39,14 +88,59 @@ async fn base_contract() -> Common {
 
     let guest_book_init_outcome = guest_book_account
         .call(contract.id(), "init")
-        .args_json(json!({"manager": alice.id().to_string() }))
+        .args_json(
+            json!({"manager": alice.id().to_string(), "update_delay": update_delay.map(U64) }),
+        )
         .transact()
         .await
         .unwrap();
 
     assert!(guest_book_init_outcome.is_success());
 
-    let bob = root.create_subaccount("bob").initial_balance(FIVE_NEAR).transact().await.unwrap().unwrap();
+    // updates roll back to the stashed code, which must be the running one, and time locked
+    // contracts only stash code proposed as the stash long enough ago
+    if update_delay.is_some() {
+        let code_hash =
+            near_workspaces::CryptoHash(Sha256::digest(&contract_wasm).into()).to_string();
+        let proposal: UpdateProposal = alice
+            .call(contract.id(), "propose_stash")
+            .args_json(json!({"code_hash": code_hash}))
+            .transact()
+            .await
+            .unwrap()
+            .json()
+            .unwrap();
+        fast_forward_to(&sandbox, proposal.executable_at.0).await;
+    }
+
+    let stash_outcome = alice
+        .call(contract.id(), "stash_code")
+        .args(contract_wasm.clone())
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await
+        .unwrap();
+
+    assert!(stash_outcome.is_success());
+
+    // only the first stash is accepted, later ones come from the updates
+    let restash_outcome = alice
+        .call(contract.id(), "stash_code")
+        .args(vec![0; 8])
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await
+        .unwrap();
+
+    assert!(restash_outcome.is_failure());
+
+    let bob = root
+        .create_subaccount("bob")
+        .initial_balance(FIVE_NEAR)
+        .transact()
+        .await
+        .unwrap()
+        .unwrap();
 
     let bob_first_message_outcome = bob
         .call(contract.id(), "add_message")
@@ -69,13 +163,25 @@ async fn base_contract() -> Common {
     assert!(alice_first_message_outcome.is_success());
 
     Common {
+        sandbox,
         contract,
+        base_wasm: contract_wasm,
         alice,
         bob,
         guest_book: guest_book_account,
     }
 }
 
+#[fixture]
+async fn base_contract() -> Common {
+    deploy_base(None).await
+}
+
+#[fixture]
+async fn timelocked_contract() -> Common {
+    deploy_base(Some(ONE_MINUTE)).await
+}
+
 #[rstest]
 #[tokio::test]
 async fn test_self_updates_base_contract_returns(
@@ -95,7 +201,8 @@ async fn test_self_updates_base_contract_returns(
         .view("get_messages")
         .args_json(json!({}))
         .await?
-        .json()?;
+        .json::<Page<_>>()?
+        .items;
 
     assert_eq!(
         messages_vec,
@@ -118,7 +225,8 @@ async fn test_self_updates_base_contract_returns(
         .view("get_payments")
         .args_json(json!({}))
         .await?
-        .json()?;
+        .json::<Page<_>>()?
+        .items;
 
     assert_eq!(
         payments_vec,
@@ -140,6 +248,18 @@ async fn test_self_updates_update_by_manager(
 
     fs::create_dir_all("../../target/near/self_update").unwrap();
     let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+    let code_hash =
+        near_workspaces::CryptoHash(Sha256::digest(&updated_contract_wasm).into()).to_string();
+
+    // without a delay, proposals would never be consumed
+    let propose_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "propose_update")
+        .args_json(json!({"code_hash": code_hash}))
+        .transact()
+        .await?;
+
+    assert!(propose_outcome.is_failure());
 
     let manager_update_call_outcome = base_contract
         .alice
@@ -166,7 +286,8 @@ async fn test_self_updates_update_by_manager(
         .view("get_messages")
         .args_json(json!({}))
         .await?
-        .json()?;
+        .json::<Page<_>>()?
+        .items;
     assert_eq!(
         messages_vec,
         vec![
@@ -189,3 +310,1211 @@ async fn test_self_updates_update_by_manager(
     assert!(get_payments_result.is_err());
     Ok(())
 }
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_migration_storage_layout(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+
+    fs::create_dir_all("../../target/near/self_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+
+    let update_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "update_contract")
+        .args(updated_contract_wasm)
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+    assert!(update_outcome.json::<bool>()?);
+
+    #[derive(near_sdk::borsh::BorshDeserialize)]
+    #[borsh(crate = "near_sdk::borsh")]
+    pub struct StoredMessage {
+        pub payment: near_sdk::NearToken,
+        pub premium: bool,
+        pub sender: AccountId,
+        pub text: String,
+    }
+
+    // the payments are left for the garbage collection
+    let state = base_contract.contract.view_state().await?;
+    assert!(state.keys().any(|key| key.starts_with(PAYMENTS_PREFIX)));
+    let storage_before = base_contract.contract.view_account().await?.storage_usage;
+
+    let outcome = base_contract
+        .contract
+        .call("collect_garbage")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    let event: near_sdk::serde_json::Value =
+        near_sdk::serde_json::from_str(outcome.logs()[0].strip_prefix("EVENT_JSON:").unwrap())?;
+    let collection: near_sdk::serde_json::Value = outcome.json()?;
+    let storage_after = base_contract.contract.view_account().await?.storage_usage;
+
+    assert_eq!(collection["removed_keys"], "2");
+    assert_eq!(collection["done"], true);
+    assert_eq!(event["event"], "storage_reclaimed");
+    assert_eq!(event["data"][0], collection);
+    assert_eq!(
+        collection["reclaimed_bytes"],
+        (storage_before - storage_after).to_string()
+    );
+
+    // the payments are gone and every message entry holds a migrated message
+    let state = base_contract.contract.view_state().await?;
+    assert!(state.keys().all(|key| !key.starts_with(PAYMENTS_PREFIX)));
+    assert!(!state.contains_key(b"RETIRED".as_slice()));
+
+    let mut stored_messages: Vec<(Vec<u8>, StoredMessage)> = state
+        .iter()
+        .filter(|(key, _)| key.len() == 9 && key.starts_with(MESSAGES_PREFIX))
+        .map(|(key, value)| (key.clone(), near_sdk::borsh::from_slice(value).unwrap()))
+        .collect();
+    stored_messages.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    assert_eq!(stored_messages.len(), 2);
+    let (_, hello) = &stored_messages[0];
+    assert_eq!(
+        hello.payment.as_yoctonear(),
+        NINE_HUNDREDTH_NEAR.as_yoctonear()
+    );
+    assert!(!hello.premium);
+    assert_eq!(&hello.sender, base_contract.bob.id());
+    assert_eq!(hello.text, "hello");
+    let (_, bye) = &stored_messages[1];
+    assert_eq!(bye.payment.as_yoctonear(), ONE_TENTH_NEAR.as_yoctonear());
+    assert!(bye.premium);
+    assert_eq!(&bye.sender, base_contract.alice.id());
+    assert_eq!(bye.text, "bye");
+    Ok(())
+}
+
+// Moves the base vectors under `b"m"` and `b"p"`, the prefixes of the first base contracts,
+// marking the texts and doubling the payments so the migrated values tell where they were read
+async fn patch_original_prefixes(base_contract: &Common) {
+    let sandbox = &base_contract.sandbox;
+    let id = base_contract.contract.id();
+
+    for (key, value) in base_contract.contract.view_state().await.unwrap() {
+        let (prefix, value) = match key.split_first() {
+            Some((0, _)) if key.len() == 9 => {
+                let (premium, sender, text): (bool, String, String) =
+                    near_sdk::borsh::from_slice(&value).unwrap();
+                let value = (premium, sender, format!("{text} (m)"));
+                (b"m", near_sdk::borsh::to_vec(&value).unwrap())
+            }
+            Some((1, _)) if key.len() == 9 => {
+                let payment = u128::from_le_bytes(value.try_into().unwrap());
+                (b"p", (2 * payment).to_le_bytes().to_vec())
+            }
+            _ => continue,
+        };
+        let key = [prefix.as_slice(), &key[1..]].concat();
+        sandbox.patch_state(id, &key, &value).await.unwrap();
+    }
+
+    let state = near_sdk::borsh::to_vec(&(
+        2u64,
+        b"m".to_vec(),
+        2u64,
+        b"p".to_vec(),
+        base_contract.alice.id().clone(),
+    ))
+    .unwrap();
+    sandbox.patch_state(id, b"STATE", &state).await.unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_original_prefixes(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+    patch_original_prefixes(&base_contract).await;
+    let contract = &base_contract.contract;
+
+    // the old messages move to the prefix of the new ones
+    let report: near_sdk::serde_json::Value = contract
+        .view("migration_dry_run")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(report["status"], "pass");
+    assert_eq!(report["retired_keys"], "4");
+
+    fs::create_dir_all("../../target/near/self_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+
+    let update_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "update_contract")
+        .args(updated_contract_wasm)
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+    assert!(update_outcome.json::<bool>()?);
+
+    let messages: Vec<near_sdk::serde_json::Value> = contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0]["text"], "hello (m)");
+    assert_eq!(
+        messages[0]["payment"],
+        (2 * NINE_HUNDREDTH_NEAR.as_yoctonear()).to_string()
+    );
+    assert_eq!(messages[1]["text"], "bye (m)");
+    assert_eq!(
+        messages[1]["payment"],
+        (2 * ONE_TENTH_NEAR.as_yoctonear()).to_string()
+    );
+
+    // both old vectors are retired, and collected without touching the new messages
+    let collection: near_sdk::serde_json::Value = contract
+        .call("collect_garbage")
+        .args_json(json!({}))
+        .transact()
+        .await?
+        .json()?;
+
+    assert_eq!(collection["removed_keys"], "4");
+    assert_eq!(collection["done"], true);
+
+    let state = contract.view_state().await?;
+    assert!(state
+        .keys()
+        .all(|key| !key.starts_with(b"m") && !key.starts_with(b"p")));
+
+    let messages: Vec<near_sdk::serde_json::Value> = contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(messages.len(), 2);
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_failed_update_keeps_old_code(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+
+    // the base code has no `migrate` method, so the migration fails
+    let failed_update_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "update_contract")
+        .args(base_contract.base_wasm.clone())
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(!failed_update_outcome.receipt_failures().is_empty());
+    assert!(!failed_update_outcome.json::<bool>()?);
+
+    #[derive(near_sdk::serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(crate = "near_sdk::serde")]
+    pub struct PostedMessage {
+        pub premium: bool,
+        pub sender: AccountId,
+        pub text: String,
+    }
+    let messages_vec: Vec<PostedMessage> = base_contract
+        .contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(messages_vec.len(), 2);
+    assert_eq!(messages_vec[0].text, "hello");
+
+    let payments_vec: Vec<U128> = base_contract
+        .contract
+        .view("get_payments")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(payments_vec.len(), 2);
+
+    let code_hash: Option<String> = base_contract
+        .contract
+        .view("get_code_hash")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(code_hash, deployed_code_hash(&base_contract.contract).await);
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_failed_migration_rolls_back(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+
+    fs::create_dir_all("../../target/near/self_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+
+    let manager_update_call_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "update_contract")
+        .args(updated_contract_wasm)
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(manager_update_call_outcome.json::<bool>()?);
+
+    let contract = base_contract.contract;
+    let updated_code_hash = deployed_code_hash(&contract).await;
+
+    let code_hash: Option<String> = contract
+        .view("get_code_hash")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert!(updated_code_hash.is_some());
+    assert_eq!(code_hash, updated_code_hash);
+
+    // going back to the base code fails, since it cannot migrate the new state
+    let failed_update_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "update_contract")
+        .args(base_contract.base_wasm)
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    // the base code was deployed on its own receipt, then `on_update` redeployed the
+    // updated code once `migrate` failed on the next one
+    assert!(!failed_update_outcome.receipt_failures().is_empty());
+    assert!(!failed_update_outcome.json::<bool>()?);
+    assert_eq!(deployed_code_hash(&contract).await, updated_code_hash);
+
+    #[derive(near_sdk::serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(crate = "near_sdk::serde")]
+    pub struct PostedMessage {
+        pub payment: NearToken,
+        pub premium: bool,
+        pub sender: AccountId,
+        pub text: String,
+    }
+    let messages_vec: Vec<PostedMessage> = contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(messages_vec.len(), 2);
+    assert_eq!(messages_vec[1].payment, ONE_TENTH_NEAR);
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_deploy_staged_code(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+
+    fs::create_dir_all("../../target/near/self_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+    let code_hash: [u8; 32] = Sha256::digest(&updated_contract_wasm).into();
+
+    let bob_stage_outcome = base_contract
+        .bob
+        .call(base_contract.guest_book.id(), "stage_code_chunk")
+        .args_borsh((code_hash, updated_contract_wasm.clone()))
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(bob_stage_outcome.is_failure());
+
+    let chunks: Vec<&[u8]> = updated_contract_wasm
+        .chunks(updated_contract_wasm.len() / 3 + 1)
+        .collect();
+    stage_code(&base_contract, code_hash, &chunks).await?;
+
+    let staged_code_hash: Option<String> = base_contract
+        .contract
+        .view("get_staged_code_hash")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(
+        staged_code_hash,
+        Some(near_workspaces::CryptoHash(code_hash).to_string())
+    );
+
+    let deploy_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "deploy_staged_code")
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(deploy_outcome.json::<bool>()?);
+
+    let contract = base_contract.contract;
+
+    #[derive(near_sdk::serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(crate = "near_sdk::serde")]
+    pub struct PostedMessage {
+        pub payment: NearToken,
+        pub premium: bool,
+        pub sender: AccountId,
+        pub text: String,
+    }
+    let messages_vec: Vec<PostedMessage> = contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(messages_vec.len(), 2);
+    assert_eq!(messages_vec[0].payment, NINE_HUNDREDTH_NEAR);
+
+    let staged_code_hash: Option<String> = contract
+        .view("get_staged_code_hash")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(staged_code_hash, None);
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_partial_or_corrupt_staged_code(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+
+    fs::create_dir_all("../../target/near/self_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+    let code_hash: [u8; 32] = Sha256::digest(&updated_contract_wasm).into();
+    let (first_half, second_half) = updated_contract_wasm.split_at(updated_contract_wasm.len() / 2);
+
+    // a partial upload cannot be deployed, but it can be completed
+    stage_code(&base_contract, code_hash, &[first_half]).await?;
+
+    let partial_deploy_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "deploy_staged_code")
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(partial_deploy_outcome.is_failure());
+
+    let mut corrupt_half = second_half.to_vec();
+    corrupt_half[0] ^= 0xff;
+    stage_code(&base_contract, code_hash, &[&corrupt_half]).await?;
+
+    let corrupt_deploy_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "deploy_staged_code")
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(corrupt_deploy_outcome.is_failure());
+
+    // the contract still runs the base code
+    let payments_vec: Vec<U128> = base_contract
+        .contract
+        .view("get_payments")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(payments_vec.len(), 2);
+
+    // a corrupt upload has to be discarded before starting over
+    let discard_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "discard_staged_code")
+        .transact()
+        .await?;
+
+    assert!(discard_outcome.is_success());
+
+    stage_code(&base_contract, code_hash, &[first_half, second_half]).await?;
+
+    let deploy_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "deploy_staged_code")
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(deploy_outcome.json::<bool>()?);
+    Ok(())
+}
+
+#[derive(near_sdk::serde::Deserialize, Debug, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UpdateProposal {
+    pub code_hash: String,
+    pub proposed_at: U64,
+    pub executable_at: U64,
+}
+
+async fn fast_forward_to(sandbox: &Worker<Sandbox>, timestamp: u64) {
+    while sandbox.view_block().await.unwrap().timestamp() < timestamp {
+        sandbox.fast_forward(100).await.unwrap();
+    }
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_time_locked_update(
+    #[future] timelocked_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = timelocked_contract.await;
+
+    fs::create_dir_all("../../target/near/self_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+    let code_hash =
+        near_workspaces::CryptoHash(Sha256::digest(&updated_contract_wasm).into()).to_string();
+
+    let update_delay: Option<U64> = base_contract
+        .contract
+        .view("get_update_delay")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(update_delay, Some(U64(ONE_MINUTE)));
+
+    // the code must be proposed before being deployed
+    let unproposed_update_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "update_contract")
+        .args(updated_contract_wasm.clone())
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(unproposed_update_outcome.is_failure());
+
+    let bob_propose_outcome = base_contract
+        .bob
+        .call(base_contract.guest_book.id(), "propose_update")
+        .args_json(json!({"code_hash": code_hash}))
+        .transact()
+        .await?;
+
+    assert!(bob_propose_outcome.is_failure());
+
+    // the proposal also binds the arguments of the migration
+    let bogus_proposal: UpdateProposal = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "propose_update")
+        .args_json(json!({"code_hash": code_hash, "migrate_args": {"bogus": true}}))
+        .transact()
+        .await?
+        .json()?;
+
+    fast_forward_to(&base_contract.sandbox, bogus_proposal.executable_at.0).await;
+
+    let other_args_update_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "update_contract")
+        .args(updated_contract_wasm.clone())
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(other_args_update_outcome.is_failure());
+
+    let proposal: UpdateProposal = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "propose_update")
+        .args_json(json!({"code_hash": code_hash}))
+        .transact()
+        .await?
+        .json()?;
+
+    assert_eq!(proposal.code_hash, code_hash);
+    assert_eq!(
+        proposal.executable_at.0,
+        proposal.proposed_at.0 + ONE_MINUTE
+    );
+
+    // anyone can see what is about to be deployed
+    let pending_updates: Vec<UpdateProposal> = base_contract
+        .bob
+        .view(base_contract.guest_book.id(), "get_pending_updates")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(pending_updates, vec![proposal]);
+
+    let early_update_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "update_contract")
+        .args(updated_contract_wasm.clone())
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(early_update_outcome.is_failure());
+
+    fast_forward_to(&base_contract.sandbox, pending_updates[0].executable_at.0).await;
+
+    let update_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "update_contract")
+        .args(updated_contract_wasm)
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(update_outcome.json::<bool>()?);
+
+    let pending_updates: Vec<UpdateProposal> = base_contract
+        .contract
+        .view("get_pending_updates")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(pending_updates, vec![]);
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_cancel_time_locked_update(
+    #[future] timelocked_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = timelocked_contract.await;
+
+    fs::create_dir_all("../../target/near/self_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+    let code_hash =
+        near_workspaces::CryptoHash(Sha256::digest(&updated_contract_wasm).into()).to_string();
+
+    let proposal: UpdateProposal = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "propose_update")
+        .args_json(json!({"code_hash": code_hash}))
+        .transact()
+        .await?
+        .json()?;
+
+    let bob_cancel_outcome = base_contract
+        .bob
+        .call(base_contract.guest_book.id(), "cancel_update")
+        .args_json(json!({"code_hash": code_hash}))
+        .transact()
+        .await?;
+
+    assert!(bob_cancel_outcome.is_failure());
+
+    let cancel_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "cancel_update")
+        .args_json(json!({"code_hash": code_hash}))
+        .transact()
+        .await?;
+
+    assert!(cancel_outcome.is_success());
+
+    fast_forward_to(&base_contract.sandbox, proposal.executable_at.0).await;
+
+    let update_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "update_contract")
+        .args(updated_contract_wasm)
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(update_outcome.is_failure());
+
+    // the contract still runs the base code
+    let payments_vec: Vec<U128> = base_contract
+        .contract
+        .view("get_payments")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(payments_vec.len(), 2);
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_multisig_approvals(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+
+    fs::create_dir_all("../../target/near/self_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+
+    let manager_update_call_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "update_contract")
+        .args(updated_contract_wasm)
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(manager_update_call_outcome.json::<bool>()?);
+
+    #[derive(near_sdk::serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(crate = "near_sdk::serde")]
+    pub struct ApproverSet {
+        pub approvers: Vec<AccountId>,
+        pub threshold: u32,
+    }
+    let contract = &base_contract.contract;
+    let (alice, bob) = (&base_contract.alice, &base_contract.bob);
+
+    // the manager is the only approver after the migration
+    let approver_set: ApproverSet = contract
+        .view("get_approvers")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(
+        approver_set,
+        ApproverSet {
+            approvers: vec![alice.id().clone()],
+            threshold: 1,
+        }
+    );
+
+    let carol = base_contract.sandbox.dev_create_account().await?;
+    let new_approvers = json!({
+        "approvers": [alice.id(), bob.id(), carol.id()],
+        "threshold": 2,
+    });
+
+    let bob_set_approvers_outcome = bob
+        .call(contract.id(), "set_approvers")
+        .args_json(new_approvers.clone())
+        .transact()
+        .await?;
+
+    assert!(bob_set_approvers_outcome.is_failure());
+
+    let set_approvers_outcome = alice
+        .call(contract.id(), "set_approvers")
+        .args_json(new_approvers)
+        .transact()
+        .await?;
+
+    assert!(set_approvers_outcome.is_success());
+
+    let approver_set: ApproverSet = contract
+        .view("get_approvers")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(approver_set.threshold, 2);
+    assert_eq!(approver_set.approvers.len(), 3);
+
+    // the base code cannot migrate the new state, but reaching the update shows the threshold was met
+    let code_hash =
+        near_workspaces::CryptoHash(Sha256::digest(&base_contract.base_wasm).into()).to_string();
+
+    // approvals cover the migration arguments as well as the code
+    let update_hash: String = contract
+        .view("get_update_hash")
+        .args_json(json!({"code_hash": code_hash}))
+        .await?
+        .json()?;
+    assert_ne!(update_hash, code_hash);
+
+    let approve_code_outcome = bob
+        .call(contract.id(), "approve")
+        .args_json(json!({"hash": code_hash}))
+        .transact()
+        .await?;
+
+    assert!(approve_code_outcome.is_success());
+
+    let code_approval_outcome = alice
+        .call(contract.id(), "update_contract")
+        .args(base_contract.base_wasm.clone())
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(code_approval_outcome.is_failure());
+
+    let code_hash = update_hash;
+
+    let single_approval_outcome = alice
+        .call(contract.id(), "update_contract")
+        .args(base_contract.base_wasm.clone())
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(single_approval_outcome.is_failure());
+
+    let approvals: Vec<AccountId> = bob
+        .call(contract.id(), "approve")
+        .args_json(json!({"hash": code_hash}))
+        .transact()
+        .await?
+        .json()?;
+
+    assert_eq!(approvals, vec![bob.id().clone()]);
+
+    let approvals: Vec<AccountId> = bob
+        .call(contract.id(), "revoke_approval")
+        .args_json(json!({"hash": code_hash}))
+        .transact()
+        .await?
+        .json()?;
+
+    assert!(approvals.is_empty());
+
+    let revoked_approval_outcome = alice
+        .call(contract.id(), "update_contract")
+        .args(base_contract.base_wasm.clone())
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(revoked_approval_outcome.is_failure());
+
+    let carol_approve_outcome = carol
+        .call(contract.id(), "approve")
+        .args_json(json!({"hash": code_hash}))
+        .transact()
+        .await?;
+
+    assert!(carol_approve_outcome.is_success());
+
+    let update_outcome = alice
+        .call(contract.id(), "update_contract")
+        .args(base_contract.base_wasm.clone())
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(update_outcome.is_success());
+    assert!(!update_outcome.json::<bool>()?);
+
+    let approvals: Vec<AccountId> = contract
+        .view("get_approvals")
+        .args_json(json!({"hash": code_hash}))
+        .await?
+        .json()?;
+
+    assert!(approvals.is_empty());
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_init_rejects_duplicate_approvers(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+
+    fs::create_dir_all("../../target/near/self_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+    let contract = base_contract
+        .sandbox
+        .dev_deploy(&updated_contract_wasm)
+        .await?;
+    let alice = &base_contract.alice;
+
+    // a duplicate would count twice toward the threshold
+    let duplicate_init_outcome = contract
+        .call("init")
+        .args_json(json!({"approvers": [alice.id(), alice.id()], "threshold": 2}))
+        .transact()
+        .await?;
+
+    assert!(duplicate_init_outcome.is_failure());
+
+    let init_outcome = contract
+        .call("init")
+        .args_json(json!({"approvers": [alice.id(), base_contract.bob.id()], "threshold": 2}))
+        .transact()
+        .await?;
+
+    assert!(init_outcome.is_success());
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_stash_needs_approvals(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+
+    fs::create_dir_all("../../target/near/self_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+    let contract = base_contract
+        .sandbox
+        .dev_deploy(&updated_contract_wasm)
+        .await?;
+    let (alice, bob) = (&base_contract.alice, &base_contract.bob);
+
+    let init_outcome = contract
+        .call("init")
+        .args_json(json!({"approvers": [alice.id(), bob.id()], "threshold": 2}))
+        .transact()
+        .await?;
+
+    assert!(init_outcome.is_success());
+
+    // a single approver cannot choose the code updates roll back to
+    let single_stash_outcome = alice
+        .call(contract.id(), "stash_code")
+        .args(updated_contract_wasm.clone())
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+
+    assert!(single_stash_outcome.is_failure());
+
+    let code_hash =
+        near_workspaces::CryptoHash(Sha256::digest(&updated_contract_wasm).into()).to_string();
+    let approve_outcome = bob
+        .call(contract.id(), "approve")
+        .args_json(json!({"hash": code_hash}))
+        .transact()
+        .await?;
+
+    assert!(approve_outcome.is_success());
+
+    let stash_outcome = alice
+        .call(contract.id(), "stash_code")
+        .args(updated_contract_wasm)
+        .gas(Gas::from_tgas(100))
+        .transact()
+        .await?;
+
+    assert!(stash_outcome.is_success());
+
+    let stashed_hash: Option<String> = contract
+        .view("get_code_hash")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(stashed_hash, Some(code_hash));
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_migration_dry_run(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+
+    #[derive(near_sdk::serde::Deserialize, Debug, PartialEq, Eq)]
+    #[serde(crate = "near_sdk::serde")]
+    pub struct DryRunReport {
+        pub status: String,
+        pub error: Option<String>,
+        pub messages: U64,
+        pub payments: U64,
+        pub missing_payments: U64,
+        pub orphaned_payments: U64,
+        pub storage_delta: I64,
+        pub estimated_gas: Gas,
+        pub retired_keys: U64,
+        pub next_cursor: Option<String>,
+    }
+    // the update migrates as soon as it is deployed, the base code runs the check
+    let report: DryRunReport = base_contract
+        .contract
+        .view("migration_dry_run")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(report.status, "pass");
+    assert_eq!(report.messages, U64(2));
+    assert_eq!(report.payments, U64(2));
+    assert_eq!(report.missing_payments, U64(0));
+    // the payments are merged into the messages, their records left for `collect_garbage`
+    assert!(report.storage_delta.0 > 0);
+    assert_eq!(report.retired_keys, U64(2));
+    assert!(report.estimated_gas > Gas::from_gas(0));
+    assert_eq!(report.next_cursor, None);
+
+    // a strict update of a guest book with a message missing its payment would fail
+    patch_orphaned_payment(&base_contract, ONE_TENTH_NEAR).await;
+
+    let report: DryRunReport = base_contract
+        .contract
+        .view("migration_dry_run")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert_eq!(report.status, "fail");
+    assert!(report.error.is_some());
+    assert_eq!(report.orphaned_payments, U64(1));
+
+    fs::create_dir_all("../../target/near/self_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+
+    let update_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "update_contract")
+        .args(updated_contract_wasm)
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(!update_outcome.json::<bool>()?);
+    Ok(())
+}
+
+// Adds a payment past the last message, the base contract never stores one
+async fn patch_orphaned_payment(base_contract: &Common, payment: NearToken) {
+    let state = near_sdk::borsh::to_vec(&(
+        2u64,
+        MESSAGES_PREFIX.to_vec(),
+        3u64,
+        PAYMENTS_PREFIX.to_vec(),
+        base_contract.alice.id().clone(),
+    ))
+    .unwrap();
+    let mut payment_key = PAYMENTS_PREFIX.to_vec();
+    payment_key.extend_from_slice(&2u64.to_le_bytes());
+
+    let sandbox = &base_contract.sandbox;
+    let id = base_contract.contract.id();
+    sandbox.patch_state(id, b"STATE", &state).await.unwrap();
+    sandbox
+        .patch_state(id, &payment_key, &payment.as_yoctonear().to_le_bytes())
+        .await
+        .unwrap();
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_strict_policy_rolls_back(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+    patch_orphaned_payment(&base_contract, ONE_TENTH_NEAR).await;
+
+    fs::create_dir_all("../../target/near/self_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+
+    let update_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "update_contract")
+        .args(updated_contract_wasm)
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    assert!(!update_outcome.json::<bool>()?);
+
+    // `migrate` failed on its own receipt after the new code was deployed, `on_update`
+    // redeployed the base code stashed before the first update
+    let code_hash: Option<String> = base_contract
+        .contract
+        .view("get_code_hash")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+
+    assert!(code_hash.is_some());
+    assert_eq!(deployed_code_hash(&base_contract.contract).await, code_hash);
+
+    let payments_vec: Vec<U128> = base_contract
+        .contract
+        .view("get_payments")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(payments_vec.len(), 3);
+    Ok(())
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_quarantine_policy(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+    patch_orphaned_payment(&base_contract, ONE_TENTH_NEAR).await;
+
+    fs::create_dir_all("../../target/near/self_update").unwrap();
+    let updated_contract_wasm = near_workspaces::compile_project("./").await.unwrap();
+    let code_hash: [u8; 32] = Sha256::digest(&updated_contract_wasm).into();
+    stage_code(&base_contract, code_hash, &[&updated_contract_wasm]).await?;
+
+    let deploy_outcome = base_contract
+        .alice
+        .call(base_contract.guest_book.id(), "deploy_staged_code")
+        .args_json(json!({"migrate_args": {"payment_policy": "quarantine"}}))
+        .gas(Gas::from_tgas(300))
+        .transact()
+        .await?;
+
+    let events: Vec<near_sdk::serde_json::Value> = deploy_outcome
+        .logs()
+        .iter()
+        .filter_map(|log| log.strip_prefix("EVENT_JSON:"))
+        .map(|event| near_sdk::serde_json::from_str(event).unwrap())
+        .collect();
+
+    assert!(deploy_outcome.json::<bool>()?);
+
+    assert_eq!(
+        events,
+        vec![json!({
+            "standard": "migration-kit",
+            "version": "1.0.0",
+            "event": "payment_mismatch",
+            "data": [{"policy": "quarantine", "missing_payments": "0", "orphaned_payments": "1"}],
+        })]
+    );
+
+    let quarantined: Vec<near_sdk::serde_json::Value> = base_contract
+        .contract
+        .view("get_quarantined")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(
+        quarantined,
+        vec![json!({"index": "2", "message": null, "payment": ONE_TENTH_NEAR})]
+    );
+
+    let messages_vec: Vec<near_sdk::serde_json::Value> = base_contract
+        .contract
+        .view("get_messages")
+        .args_json(json!({}))
+        .await?
+        .json::<Page<_>>()?
+        .items;
+
+    assert_eq!(messages_vec.len(), 2);
+
+    // the orphaned payment was moved, not left behind under its old key
+    let collect_outcome = base_contract
+        .contract
+        .call("collect_garbage")
+        .args_json(json!({}))
+        .transact()
+        .await?;
+    assert!(collect_outcome.is_success());
+    let state = base_contract.contract.view_state().await?;
+    assert!(state.keys().all(|key| !key.starts_with(PAYMENTS_PREFIX)));
+    Ok(())
+}
+
+// Gas burnt by a page view called in a transaction, views do not report it
+async fn page_gas(
+    caller: &Account,
+    contract: &Contract,
+    method: &str,
+    args: serde_json::Value,
+) -> Result<(Gas, Page<serde_json::Value>), Box<dyn std::error::Error>> {
+    let outcome = caller
+        .call(contract.id(), method)
+        .args_json(args)
+        .transact()
+        .await?;
+    let gas = outcome.total_gas_burnt;
+    Ok((gas, outcome.json()?))
+}
+
+#[rstest]
+#[tokio::test]
+async fn test_self_updates_deep_page_gas(
+    #[future] base_contract: Common,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let base_contract = base_contract.await;
+
+    for batch in 0..3 {
+        let mut transaction = base_contract.bob.batch(base_contract.contract.id());
+        for i in 0..20 {
+            transaction = transaction.call(
+                Function::new("add_message")
+                    .args_json(json!({"text": format!("message {}", batch * 20 + i)}))
+                    .deposit(ONE_TENTH_NEAR)
+                    .gas(Gas::from_tgas(10)),
+            );
+        }
+        assert!(transaction.transact().await?.is_success());
+    }
+
+    let total: U64 = base_contract
+        .contract
+        .view("total_messages")
+        .args_json(json!({}))
+        .await?
+        .json()?;
+    assert_eq!(total, U64(62));
+
+    // deep pages cost as much as the first ones, the entries before them are never read
+    for method in ["get_messages", "get_payments"] {
+        let (first_gas, first_page) = page_gas(
+            &base_contract.alice,
+            &base_contract.contract,
+            method,
+            json!({}),
+        )
+        .await?;
+        let (deep_gas, deep_page) = page_gas(
+            &base_contract.alice,
+            &base_contract.contract,
+            method,
+            json!({"from_index": "55"}),
+        )
+        .await?;
+        assert_eq!(first_page.items.len(), 10);
+        assert!(first_page.next_cursor.is_some());
+        assert_eq!(deep_page.items.len(), 7);
+        assert!(deep_page.next_cursor.is_none());
+        assert!(deep_gas.as_gas() < first_gas.as_gas() * 11 / 10);
+    }
+    Ok(())
+}